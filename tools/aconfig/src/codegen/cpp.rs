@@ -21,16 +21,33 @@ use tinytemplate::TinyTemplate;
 
 use crate::codegen;
 use crate::commands::{CodegenMode, OutputFile};
-use crate::protos::{ProtoFlagPermission, ProtoFlagState, ProtoParsedFlag};
+use crate::protos::{ParsedFlagExt, ProtoParsedFlag};
+
+#[derive(Copy, Clone, Debug)]
+pub struct CppCodegenConfig {
+    pub codegen_mode: CodegenMode,
+    /// Hint the compiler that read-write flags usually have their default value
+    pub branch_hints: bool,
+    /// Also generate include/<package>_c.h, a header for plain C: read-only flags are inline
+    /// functions, read-write flags are defined (with C linkage) in the C++ source file
+    pub c_header: bool,
+}
+
+impl CppCodegenConfig {
+    pub fn new(codegen_mode: CodegenMode) -> Self {
+        CppCodegenConfig { codegen_mode, branch_hints: false, c_header: false }
+    }
+}
 
 pub fn generate_cpp_code<'a, I>(
     package: &str,
     parsed_flags_iter: I,
-    codegen_mode: CodegenMode,
+    config: CppCodegenConfig,
 ) -> Result<Vec<OutputFile>>
 where
     I: Iterator<Item = &'a ProtoParsedFlag>,
 {
+    let codegen_mode = config.codegen_mode;
     let mut readwrite_count = 0;
     let class_elements: Vec<ClassElement> = parsed_flags_iter
         .map(|pf| create_class_element(package, pf, &mut readwrite_count))
@@ -50,28 +67,39 @@ where
         readwrite,
         readwrite_count,
         for_test: codegen_mode == CodegenMode::Test,
+        branch_hints: config.branch_hints,
         class_elements,
     };
 
-    let files = [
+    let header_name = format!("{}.h", header);
+    let source_name = format!("{}.cc", header);
+    let c_header_name = format!("{}_c.h", header);
+    let mut files = vec![
         FileSpec {
-            name: &format!("{}.h", header),
+            name: &header_name,
             template: include_str!("../../templates/cpp_exported_header.template"),
             dir: "include",
         },
         FileSpec {
-            name: &format!("{}.cc", header),
+            name: &source_name,
             template: include_str!("../../templates/cpp_source_file.template"),
             dir: "",
         },
     ];
+    if config.c_header {
+        files.push(FileSpec {
+            name: &c_header_name,
+            template: include_str!("../../templates/c_header.template"),
+            dir: "include",
+        });
+    }
     files.iter().map(|file| generate_file(file, &context)).collect()
 }
 
 pub fn generate_file(file: &FileSpec, context: &Context) -> Result<OutputFile> {
     let mut template = TinyTemplate::new();
     template.add_template(file.name, file.template)?;
-    let contents = template.render(file.name, &context)?;
+    let contents = codegen::generated_file_header() + &template.render(file.name, &context)?;
     let path: PathBuf = [&file.dir, &file.name].iter().collect();
     Ok(OutputFile { contents: contents.into(), path })
 }
@@ -93,6 +121,7 @@ pub struct Context<'a> {
     pub readwrite: bool,
     pub readwrite_count: i32,
     pub for_test: bool,
+    pub branch_hints: bool,
     pub class_elements: Vec<ClassElement>,
 }
 
@@ -110,20 +139,16 @@ pub struct ClassElement {
 
 fn create_class_element(package: &str, pf: &ProtoParsedFlag, rw_count: &mut i32) -> ClassElement {
     ClassElement {
-        readwrite_idx: if pf.permission() == ProtoFlagPermission::READ_WRITE {
+        readwrite_idx: if !pf.is_compile_time_constant() {
             let index = *rw_count;
             *rw_count += 1;
             index
         } else {
             -1
         },
-        readwrite: pf.permission() == ProtoFlagPermission::READ_WRITE,
+        readwrite: !pf.is_compile_time_constant(),
         is_fixed_read_only: pf.is_fixed_read_only(),
-        default_value: if pf.state() == ProtoFlagState::ENABLED {
-            "true".to_string()
-        } else {
-            "false".to_string()
-        },
+        default_value: pf.effective_default_value().to_string(),
         flag_name: pf.name().to_string(),
         flag_macro: pf.name().to_uppercase(),
         device_config_namespace: pf.namespace().to_string(),
@@ -138,6 +163,7 @@ mod tests {
     use std::collections::HashMap;
 
     const EXPORTED_PROD_HEADER_EXPECTED: &str = r#"
+// Generated by aconfig 0.1.0. DO NOT EDIT.
 #pragma once
 
 #ifndef COM_ANDROID_ACONFIG_TEST
@@ -236,6 +262,7 @@ bool com_android_aconfig_test_enabled_rw();
 "#;
 
     const EXPORTED_TEST_HEADER_EXPECTED: &str = r#"
+// Generated by aconfig 0.1.0. DO NOT EDIT.
 #pragma once
 
 #ifdef __cplusplus
@@ -402,6 +429,7 @@ void com_android_aconfig_test_reset_flags();
 "#;
 
     const PROD_SOURCE_FILE_EXPECTED: &str = r#"
+// Generated by aconfig 0.1.0. DO NOT EDIT.
 #include "com_android_aconfig_test.h"
 #include <server_configurable_flags/get_flags.h>
 #include <vector>
@@ -510,6 +538,7 @@ bool com_android_aconfig_test_enabled_rw() {
 "#;
 
     const TEST_SOURCE_FILE_EXPECTED: &str = r#"
+// Generated by aconfig 0.1.0. DO NOT EDIT.
 #include "com_android_aconfig_test.h"
 #include <server_configurable_flags/get_flags.h>
 #include <unordered_map>
@@ -734,9 +763,12 @@ void com_android_aconfig_test_reset_flags() {
 
     fn test_generate_cpp_code(mode: CodegenMode) {
         let parsed_flags = crate::test::parse_test_flags();
-        let generated =
-            generate_cpp_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), mode)
-                .unwrap();
+        let generated = generate_cpp_code(
+            crate::test::TEST_PACKAGE,
+            parsed_flags.parsed_flag.iter(),
+            CppCodegenConfig::new(mode),
+        )
+        .unwrap();
         let mut generated_files_map = HashMap::new();
         for file in generated {
             generated_files_map.insert(
@@ -785,4 +817,126 @@ void com_android_aconfig_test_reset_flags() {
     fn test_generate_cpp_code_for_test() {
         test_generate_cpp_code(CodegenMode::Test);
     }
+
+    const PROD_C_HEADER_EXPECTED: &str = r#"
+// Generated by aconfig 0.1.0. DO NOT EDIT.
+#pragma once
+
+#include <stdbool.h>
+
+#ifndef COM_ANDROID_ACONFIG_TEST
+#define COM_ANDROID_ACONFIG_TEST(FLAG) COM_ANDROID_ACONFIG_TEST_##FLAG
+#endif
+
+#ifndef COM_ANDROID_ACONFIG_TEST_DISABLED_RO
+#define COM_ANDROID_ACONFIG_TEST_DISABLED_RO false
+#endif
+
+#ifndef COM_ANDROID_ACONFIG_TEST_ENABLED_FIXED_RO
+#define COM_ANDROID_ACONFIG_TEST_ENABLED_FIXED_RO true
+#endif
+
+#ifndef COM_ANDROID_ACONFIG_TEST_ENABLED_RO
+#define COM_ANDROID_ACONFIG_TEST_ENABLED_RO true
+#endif
+
+#ifndef COM_ANDROID_ACONFIG_TEST_ENABLED_RO_EXPORTED
+#define COM_ANDROID_ACONFIG_TEST_ENABLED_RO_EXPORTED true
+#endif
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+bool com_android_aconfig_test_disabled_ro(void);
+
+bool com_android_aconfig_test_disabled_rw(void);
+
+bool com_android_aconfig_test_disabled_rw_exported(void);
+
+bool com_android_aconfig_test_disabled_rw_in_other_namespace(void);
+
+bool com_android_aconfig_test_enabled_fixed_ro(void);
+
+bool com_android_aconfig_test_enabled_ro(void);
+
+bool com_android_aconfig_test_enabled_ro_exported(void);
+
+bool com_android_aconfig_test_enabled_rw(void);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif
+"#;
+
+    #[test]
+    fn test_generate_cpp_code_c_header() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let generate = |codegen_mode, path: &str| {
+            let config = CppCodegenConfig { c_header: true, ..CppCodegenConfig::new(codegen_mode) };
+            let generated = generate_cpp_code(
+                crate::test::TEST_PACKAGE,
+                parsed_flags.parsed_flag.iter(),
+                config,
+            )
+            .unwrap();
+            assert_eq!(3, generated.len());
+            let header =
+                generated.into_iter().find(|file| file.path.to_str() == Some(path)).unwrap();
+            String::from_utf8(header.contents).unwrap()
+        };
+        let header = generate(CodegenMode::Production, "include/com_android_aconfig_test_c.h");
+        assert_eq!(None, crate::test::first_significant_code_diff(PROD_C_HEADER_EXPECTED, &header));
+        for pf in parsed_flags.parsed_flag.iter() {
+            let declaration = format!("bool com_android_aconfig_test_{}(void)", pf.name());
+            assert_eq!(1, header.matches(&declaration).count(), "{}", declaration);
+        }
+
+        // a translation unit may include both this and the C++ header, which declares the same
+        // functions with C linkage: neither may declare them static or inline
+        let cpp_header = generate(CodegenMode::Production, "include/com_android_aconfig_test.h");
+        for pf in parsed_flags.parsed_flag.iter() {
+            let name = format!("com_android_aconfig_test_{}", pf.name());
+            assert!(cpp_header.contains(&format!("bool {}();", name)));
+            assert!(!format!("{}{}", cpp_header, header)
+                .lines()
+                .any(|line| line.contains(&name)
+                    && (line.contains("static") || line.contains("inline"))));
+        }
+        // both define the macro of a fixed read-only flag, to the same value
+        let definition = "#ifndef COM_ANDROID_ACONFIG_TEST_ENABLED_FIXED_RO\n#define COM_ANDROID_ACONFIG_TEST_ENABLED_FIXED_RO true\n#endif";
+        assert!(cpp_header.contains(definition));
+        assert!(header.contains(definition));
+
+        // in test mode all flags can be changed, so none is folded
+        let header = generate(CodegenMode::Test, "include/com_android_aconfig_test_c.h");
+        assert!(header.contains("extern \"C\" {"));
+        assert!(!header.contains("#define"));
+        assert!(header.contains("bool com_android_aconfig_test_disabled_ro(void);"));
+        assert!(header.contains("void set_com_android_aconfig_test_disabled_ro(bool val);"));
+        assert!(header.contains("void com_android_aconfig_test_reset_flags(void);"));
+    }
+
+    #[test]
+    fn test_generate_cpp_code_branch_hints() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let config = CppCodegenConfig {
+            branch_hints: true,
+            ..CppCodegenConfig::new(CodegenMode::Production)
+        };
+        let generated =
+            generate_cpp_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap();
+        let source = generated
+            .iter()
+            .find(|file| file.path.to_str() == Some("com_android_aconfig_test.cc"))
+            .unwrap();
+        let source = std::str::from_utf8(&source.contents).unwrap();
+        assert!(source.contains("if (__builtin_expect(cache_[0] == -1, 0)) {"));
+        assert!(source.contains("return __builtin_expect(cache_[0], false);"));
+        assert!(source.contains("return __builtin_expect(cache_[3], true);"));
+        // one hint on the cache check and one on the value, for each of the 4 read-write flags
+        assert_eq!(8, source.matches("__builtin_expect").count());
+        assert!(source.contains("virtual bool disabled_ro() override {\n            return false;"));
+    }
 }
@@ -14,7 +14,7 @@
  * limitations under the License.
  */
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, ensure, Result};
 use serde::Serialize;
 use std::collections::{BTreeMap, BTreeSet};
 use std::path::PathBuf;
@@ -22,21 +22,140 @@ use tinytemplate::TinyTemplate;
 
 use crate::codegen;
 use crate::commands::{CodegenMode, OutputFile};
-use crate::protos::{ProtoFlagPermission, ProtoFlagState, ProtoParsedFlag};
+use crate::protos::{ParsedFlagExt, ProtoParsedFlag};
+
+/// Formatting applied to the generated Java sources
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct JavaStyle {
+    /// Number of spaces per indentation level
+    pub indent_width: usize,
+    /// Put opening braces of blocks on a line of their own
+    pub braces_on_new_line: bool,
+}
+
+impl Default for JavaStyle {
+    fn default() -> Self {
+        // matches the formatting of the templates
+        JavaStyle { indent_width: TEMPLATE_INDENT_WIDTH, braces_on_new_line: false }
+    }
+}
+
+const TEMPLATE_INDENT_WIDTH: usize = 4;
+
+#[derive(Copy, Clone, Debug)]
+pub struct JavaCodegenConfig<'a> {
+    pub codegen_mode: CodegenMode,
+    /// Also generate FlagsTestParams.java, listing all flags and their default values
+    pub generate_test_params: bool,
+    /// Also generate FlagCountTest.java, asserting the number of flags has not changed since the
+    /// code was generated (implies generate_test_params)
+    pub generate_flag_count_test: bool,
+    /// Also generate ReadOnlyFlagsTest.java, asserting each read-only accessor returns its
+    /// declared value (production mode only: in test mode the values come from the test, in
+    /// exported mode from DeviceConfig)
+    pub generate_read_only_test: bool,
+    /// Also generate FlagsValues.java, a record with the value of each flag and a factory which
+    /// reads them all at once (requires Java 16)
+    pub generate_values_snapshot: bool,
+    /// Also generate FlagConstants.java, with a constant for each read-only flag (not for
+    /// exported libraries, which read all flags at runtime)
+    pub generate_constants: bool,
+    /// Annotate accessors with @ReadOnly or @ReadWrite (annotation types are generated too)
+    pub annotate_permissions: bool,
+    /// Log each read of a flag FeatureFlagsImpl reads at runtime, if debug logging is enabled for
+    /// the FeatureFlagsImpl tag (read-only flags stay constants)
+    pub log_reads: bool,
+    /// Give FeatureFlagsImpl a constructor taking a FlagValueProvider, which it reads the server
+    /// side values from instead of DeviceConfig, e.g. in unit tests (the no-argument constructor
+    /// still uses DeviceConfig). The read values, including those of fallback namespaces and the
+    /// kill switch, are then kept per instance.
+    pub value_provider: bool,
+    /// Make the accessors in Flags package-private instead of public
+    pub package_private: bool,
+    /// Only generate the FeatureFlags interface (and the annotation types it refers to)
+    pub interface_only: bool,
+    /// Nest FeatureFlags, FeatureFlagsImpl and FakeFeatureFlagsImpl in Flags, so that the
+    /// library is a single file (optional files, e.g. annotation types, are still separate)
+    pub single_file: bool,
+    /// Mark read-only accessors in Flags with @FoldableFlag (annotation type is generated too)
+    /// and add a VALUE_ constant with their value, so that an annotation processor can replace
+    /// calls to them with the constant
+    pub fold_read_only: bool,
+    /// Annotate read-write accessors in Flags with @DeviceConfigKey, naming the DeviceConfig
+    /// namespace and flag they are read from (annotation type is generated too)
+    pub device_config_key_annotation: bool,
+    /// For each read-write flag, add a method to Flags which registers a DeviceConfig listener
+    /// for changes of the flag's value
+    pub change_listeners: bool,
+    /// Add a DEFAULTS map to Flags, from the name of each flag to its default value (not for
+    /// exported libraries, which have no defaults of their own)
+    pub default_values_map: bool,
+    /// Add a <NAME>_INDEX constant for each flag to Flags, numbering the flags from 0 in the
+    /// order of their names, and a FLAG_COUNT constant (not for exported libraries)
+    pub flag_indices: bool,
+    /// Name of the class with the static accessors, "Flags" by default
+    pub flags_class_name: &'a str,
+    /// Fully qualified name of a class with a static recordFlagRead(String flagName,
+    /// long durationNanos) method: if set, FeatureFlagsImpl reports how long each read of a
+    /// read-write flag took, including the DeviceConfig lookup on first access
+    pub read_timing_helper: Option<&'a str>,
+    /// DeviceConfig flag, as "<namespace>/<name>", which when true makes all read-write flags use
+    /// their default value (not for exported libraries, which have no defaults of their own)
+    pub kill_switch: Option<&'a str>,
+    pub style: JavaStyle,
+}
+
+impl JavaCodegenConfig<'_> {
+    pub fn new(codegen_mode: CodegenMode) -> Self {
+        JavaCodegenConfig {
+            codegen_mode,
+            generate_test_params: false,
+            generate_flag_count_test: false,
+            generate_read_only_test: false,
+            generate_values_snapshot: false,
+            generate_constants: false,
+            annotate_permissions: false,
+            log_reads: false,
+            value_provider: false,
+            package_private: false,
+            interface_only: false,
+            single_file: false,
+            fold_read_only: false,
+            device_config_key_annotation: false,
+            change_listeners: false,
+            default_values_map: false,
+            flag_indices: false,
+            flags_class_name: "Flags",
+            read_timing_helper: None,
+            kill_switch: None,
+            style: JavaStyle::default(),
+        }
+    }
+}
 
 pub fn generate_java_code<'a, I>(
     package: &str,
     parsed_flags_iter: I,
-    codegen_mode: CodegenMode,
+    config: JavaCodegenConfig,
 ) -> Result<Vec<OutputFile>>
 where
     I: Iterator<Item = &'a ProtoParsedFlag>,
 {
-    let flag_elements: Vec<FlagElement> =
-        parsed_flags_iter.map(|pf| create_flag_element(package, pf)).collect();
-    let exported_flag_elements: Vec<FlagElement> =
-        flag_elements.iter().filter(|elem| elem.exported).cloned().collect();
+    let codegen_mode = config.codegen_mode;
+    let parsed_flags: Vec<&ProtoParsedFlag> = parsed_flags_iter.collect();
+    let mut flag_elements: Vec<FlagElement> =
+        parsed_flags.iter().map(|pf| create_flag_element(package, pf)).collect();
+    // all flags are in the same package: ordering by device config flag is ordering by name
+    let mut sorted_flags: Vec<String> =
+        flag_elements.iter().map(|fe| fe.device_config_flag.clone()).collect();
+    sorted_flags.sort();
+    for fe in flag_elements.iter_mut() {
+        fe.index = sorted_flags.binary_search(&fe.device_config_flag).unwrap();
+    }
+    let exported_flag_elements: Vec<&FlagElement> =
+        flag_elements.iter().filter(|elem| elem.exported).collect();
     let namespace_flags = gen_flags_by_namespace(&flag_elements);
+    let flag_groups = gen_flag_groups(&flag_elements, codegen_mode == CodegenMode::Exported);
     let properties_set: BTreeSet<String> =
         flag_elements.iter().map(|fe| format_property_name(&fe.device_config_namespace)).collect();
     let is_test_mode = codegen_mode == CodegenMode::Test;
@@ -44,19 +163,99 @@ where
     let runtime_lookup_required =
         flag_elements.iter().any(|elem| elem.is_read_write) || library_exported;
 
+    ensure!(
+        is_valid_java_class_name(config.flags_class_name),
+        "bad class name {}",
+        config.flags_class_name
+    );
+    ensure!(
+        !(config.single_file && config.interface_only),
+        "single file mode requires Flags, but only the interface is generated"
+    );
+    if let Some(helper) = config.read_timing_helper {
+        ensure!(
+            helper.split('.').all(is_valid_java_identifier),
+            "bad read timing helper class {}",
+            helper
+        );
+    }
+    let (kill_switch_namespace, kill_switch_key) = match config.kill_switch {
+        Some(kill_switch) if !library_exported => {
+            let Some((namespace, key)) = kill_switch.split_once('/') else {
+                bail!("bad kill switch {}: expected <namespace>/<name>", kill_switch);
+            };
+            ensure!(
+                codegen::is_valid_name_ident(namespace)
+                    && key.split('.').all(codegen::is_valid_name_ident),
+                "bad kill switch {}",
+                kill_switch
+            );
+            if let Some(fe) =
+                flag_elements.iter().find(|fe| fe.method_name == "isKillSwitchEngaged")
+            {
+                bail!("flag {} clashes with the kill switch method", fe.device_config_flag);
+            }
+            (namespace.to_string(), key.to_string())
+        }
+        _ => (String::new(), String::new()),
+    };
+    if config.generate_values_snapshot {
+        // record components get accessors, which must not clash with the other methods
+        if let Some(fe) = flag_elements.iter().find(|fe| {
+            ["current", "hashCode", "toString", "getClass", "notify", "notifyAll", "wait"]
+                .contains(&fe.method_name.as_str())
+        }) {
+            bail!(
+                "flag {} clashes with method {}() of FlagsValues",
+                fe.device_config_flag,
+                fe.method_name
+            );
+        }
+    }
+    if !library_exported {
+        check_constant_name_clashes(
+            flag_elements.iter().map(|fe| {
+                let has_value = config.fold_read_only && !fe.is_read_write;
+                (fe.device_config_flag.clone(), fe.flag_name_constant_suffix.clone(), has_value)
+            }),
+            config.flag_indices,
+        )?;
+    }
+    // only flags read at runtime use their fallback namespaces
+    let has_fallback_namespaces = flag_elements.iter().any(|fe| {
+        !fe.fallback_namespaces.is_empty()
+            && if library_exported { fe.exported } else { fe.is_read_write }
+    });
     if library_exported && exported_flag_elements.is_empty() {
         return Err(anyhow!("exported library contains no exported flags"));
     }
 
     let context = Context {
-        flag_elements,
+        flag_count: flag_elements.len(),
+        read_write_count: crate::protos::parsed_flags::rw_flag_count(parsed_flags),
+        flag_elements: &flag_elements,
         exported_flag_elements,
         namespace_flags,
+        flag_groups,
         is_test_mode,
         runtime_lookup_required,
         properties_set,
         package_name: package.to_string(),
         library_exported,
+        annotate_permissions: config.annotate_permissions,
+        log_reads: config.log_reads,
+        value_provider: config.value_provider,
+        package_private: config.package_private,
+        fold_read_only: config.fold_read_only,
+        device_config_key_annotation: config.device_config_key_annotation,
+        change_listeners: config.change_listeners,
+        default_values_map: config.default_values_map,
+        flag_indices: config.flag_indices,
+        has_fallback_namespaces,
+        flags_class_name: config.flags_class_name.to_string(),
+        read_timing_helper: config.read_timing_helper.unwrap_or_default().to_string(),
+        kill_switch_namespace,
+        kill_switch_key,
     };
     let mut template = TinyTemplate::new();
     template.add_template("Flags.java", include_str!("../../templates/Flags.java.template"))?;
@@ -72,56 +271,329 @@ where
         "FakeFeatureFlagsImpl.java",
         include_str!("../../templates/FakeFeatureFlagsImpl.java.template"),
     )?;
+    template.add_template(
+        "FlagsTestParams.java",
+        include_str!("../../templates/FlagsTestParams.java.template"),
+    )?;
+    template.add_template(
+        "FlagCountTest.java",
+        include_str!("../../templates/FlagCountTest.java.template"),
+    )?;
+    template.add_template(
+        "ReadOnlyFlagsTest.java",
+        include_str!("../../templates/ReadOnlyFlagsTest.java.template"),
+    )?;
+    template.add_template(
+        "FlagsValues.java",
+        include_str!("../../templates/FlagsValues.java.template"),
+    )?;
+    template.add_template(
+        "FlagConstants.java",
+        include_str!("../../templates/FlagConstants.java.template"),
+    )?;
+    template.add_template(
+        "DeviceConfigKey.java",
+        include_str!("../../templates/DeviceConfigKey.java.template"),
+    )?;
 
-    let path: PathBuf = package.split('.').collect();
-    ["Flags.java", "FeatureFlags.java", "FeatureFlagsImpl.java", "FakeFeatureFlagsImpl.java"]
-        .iter()
-        .map(|file| {
-            Ok(OutputFile {
-                contents: template.render(file, &context)?.into(),
-                path: path.join(file),
+    let mut files = if config.interface_only {
+        vec!["FeatureFlags.java"]
+    } else {
+        vec![
+            "Flags.java",
+            "FeatureFlags.java",
+            "FeatureFlagsImpl.java",
+            "FakeFeatureFlagsImpl.java",
+        ]
+    };
+    if (config.generate_test_params || config.generate_flag_count_test) && !config.interface_only {
+        files.push("FlagsTestParams.java");
+    }
+    if config.generate_flag_count_test && !config.interface_only {
+        files.push("FlagCountTest.java");
+    }
+    if config.generate_read_only_test
+        && !config.interface_only
+        && codegen_mode == CodegenMode::Production
+    {
+        files.push("ReadOnlyFlagsTest.java");
+    }
+    if config.generate_values_snapshot && !config.interface_only {
+        files.push("FlagsValues.java");
+    }
+    if config.generate_constants && !config.interface_only && !library_exported {
+        files.push("FlagConstants.java");
+    }
+    if config.device_config_key_annotation && !config.interface_only {
+        files.push("DeviceConfigKey.java");
+    }
+    let path: PathBuf = codegen::package_segments(package).collect();
+    let mut output_files = vec![];
+    if config.single_file {
+        let nested_files =
+            ["FeatureFlags.java", "FeatureFlagsImpl.java", "FakeFeatureFlagsImpl.java"];
+        let nested = nested_files
+            .iter()
+            .map(|file| template.render(file, &context))
+            .collect::<Result<Vec<String>, _>>()?;
+        let contents = nest_java_classes(&template.render("Flags.java", &context)?, &nested);
+        output_files.push(OutputFile {
+            contents: finish_file(contents, &config.style),
+            path: path.join(output_file_name("Flags.java", &config)),
+        });
+        files.retain(|file| *file != "Flags.java" && !nested_files.contains(file));
+    }
+    output_files.extend(
+        files
+            .iter()
+            .map(|file| {
+                Ok(OutputFile {
+                    contents: render_file(&template, file, &context, &config.style)?,
+                    path: path.join(output_file_name(file, &config)),
+                })
             })
-        })
-        .collect::<Result<Vec<OutputFile>>>()
+            .collect::<Result<Vec<OutputFile>>>()?,
+    );
+
+    let mut annotation_names = vec![];
+    if config.annotate_permissions {
+        annotation_names.extend(["ReadOnly", "ReadWrite"]);
+    }
+    if config.fold_read_only && !config.interface_only {
+        annotation_names.push("FoldableFlag");
+    }
+    if !annotation_names.is_empty() {
+        template.add_template(
+            "MarkerAnnotation.java",
+            include_str!("../../templates/MarkerAnnotation.java.template"),
+        )?;
+        for annotation_name in annotation_names {
+            let annotation_context = AnnotationContext {
+                package_name: package.to_string(),
+                annotation_name: annotation_name.to_string(),
+            };
+            output_files.push(OutputFile {
+                contents: render_file(
+                    &template,
+                    "MarkerAnnotation.java",
+                    &annotation_context,
+                    &config.style,
+                )?,
+                path: path.join(format!("{}.java", annotation_name)),
+            });
+        }
+    }
+    Ok(output_files)
 }
 
-fn gen_flags_by_namespace(flags: &[FlagElement]) -> Vec<NamespaceFlags> {
-    let mut namespace_to_flag: BTreeMap<String, Vec<FlagElement>> = BTreeMap::new();
+// A public Java class has to be in a file of the same name, so the file names follow the class
+// names (which for some templates are configurable)
+fn output_file_name(template_name: &str, config: &JavaCodegenConfig) -> String {
+    match template_name {
+        "Flags.java" => format!("{}.java", config.flags_class_name),
+        _ => template_name.to_string(),
+    }
+}
 
-    for flag in flags {
-        match namespace_to_flag.get_mut(&flag.device_config_namespace) {
-            Some(flag_list) => flag_list.push(flag.clone()),
-            None => {
-                namespace_to_flag.insert(flag.device_config_namespace.clone(), vec![flag.clone()]);
+fn render_file<C: Serialize>(
+    template: &TinyTemplate,
+    name: &str,
+    context: &C,
+    style: &JavaStyle,
+) -> Result<Vec<u8>> {
+    Ok(finish_file(template.render(name, context)?, style))
+}
+
+fn finish_file(contents: String, style: &JavaStyle) -> Vec<u8> {
+    let contents = apply_style(contents, style);
+    (codegen::generated_file_header() + &contents).into()
+}
+
+// Put the types of the `nested` files into the class of `outer`, before its closing brace. The
+// imports of all files are merged; nested classes become static, so they can be used like the
+// top level classes they replace.
+fn nest_java_classes(outer: &str, nested: &[String]) -> String {
+    let is_preamble = |line: &str| {
+        line.trim().is_empty()
+            || line.starts_with("//")
+            || line.starts_with("package ")
+            || line.starts_with("import ")
+    };
+    let split =
+        |contents: &str| -> usize { contents.lines().take_while(|line| is_preamble(line)).count() };
+
+    let mut imports: Vec<&str> = vec![];
+    for contents in std::iter::once(outer).chain(nested.iter().map(|s| s.as_str())) {
+        for line in contents.lines().take(split(contents)) {
+            if line.starts_with("import ") && !imports.contains(&line) {
+                imports.push(line);
             }
         }
     }
 
+    let mut output = String::new();
+    let outer_lines: Vec<&str> = outer.lines().collect();
+    let (preamble, body) = outer_lines.split_at(split(outer));
+    let mut imports_written = false;
+    for line in preamble {
+        if line.starts_with("import ") {
+            if !imports_written {
+                imports.iter().for_each(|import| output += &format!("{}\n", import));
+                imports_written = true;
+            }
+            continue;
+        }
+        output += &format!("{}\n", line);
+    }
+    if !imports_written {
+        imports.iter().for_each(|import| output += &format!("{}\n", import));
+    }
+
+    let closing_brace = body.iter().rposition(|line| line.trim() == "}").unwrap_or(body.len());
+    body[..closing_brace].iter().for_each(|line| output += &format!("{}\n", line));
+    let indent = " ".repeat(TEMPLATE_INDENT_WIDTH);
+    for contents in nested {
+        let mut declared = false;
+        for line in contents.lines().skip(split(contents)) {
+            let mut line = line.to_string();
+            if !declared && line.starts_with("public ") && line.contains(" class ") {
+                line = line.replacen("public ", "public static ", 1);
+                declared = true;
+            }
+            if line.trim().is_empty() {
+                output += "\n";
+            } else {
+                output += &format!("{}{}\n", indent, line);
+            }
+        }
+    }
+    body[closing_brace..].iter().for_each(|line| output += &format!("{}\n", line));
+    output
+}
+
+// The templates are indented with TEMPLATE_INDENT_WIDTH spaces per level and put opening braces at
+// the end of the line; rewrite the rendered output to follow the requested style instead.
+fn apply_style(contents: String, style: &JavaStyle) -> String {
+    if *style == JavaStyle::default() {
+        return contents;
+    }
+    let mut output = String::with_capacity(contents.len());
+    for line in contents.split_inclusive('\n') {
+        let code = line.trim_start_matches(' ');
+        let leading = line.len() - code.len();
+        let indent = " ".repeat(
+            leading / TEMPLATE_INDENT_WIDTH * style.indent_width + leading % TEMPLATE_INDENT_WIDTH,
+        );
+        let body = code.trim_end();
+        // array initializers (`= {`) are not blocks, keep them as they are
+        if style.braces_on_new_line
+            && body.len() > 1
+            && body.ends_with('{')
+            && !body.ends_with("= {")
+            && !body.starts_with("//")
+            && !body.starts_with('*')
+        {
+            output.push_str(&indent);
+            output.push_str(body[..body.len() - 1].trim_end());
+            output.push('\n');
+            output.push_str(&indent);
+            output.push_str("{\n");
+            continue;
+        }
+        output.push_str(&indent);
+        output.push_str(code);
+    }
+    output
+}
+
+// Empty unless at least one flag has a group; the flags without a group come last
+fn gen_flag_groups(flags: &[FlagElement], library_exported: bool) -> Vec<FlagGroup<'_>> {
+    let flags: Vec<&FlagElement> =
+        flags.iter().filter(|flag| flag.exported || !library_exported).collect();
+    if flags.iter().all(|flag| flag.group.is_empty()) {
+        return vec![];
+    }
+    let mut group_to_flag: BTreeMap<&str, Vec<&FlagElement>> = BTreeMap::new();
+    let mut ungrouped = vec![];
+    for flag in flags {
+        if flag.group.is_empty() {
+            ungrouped.push(flag);
+        } else {
+            group_to_flag.entry(&flag.group).or_default().push(flag);
+        }
+    }
+    let mut groups: Vec<FlagGroup> = group_to_flag
+        .into_iter()
+        .map(|(name, flags)| FlagGroup { name: name.to_string(), flags })
+        .collect();
+    if !ungrouped.is_empty() {
+        groups.push(FlagGroup { name: "Other flags".to_string(), flags: ungrouped });
+    }
+    groups
+}
+
+fn gen_flags_by_namespace(flags: &[FlagElement]) -> Vec<NamespaceFlags<'_>> {
+    let mut namespace_to_flag: BTreeMap<&str, Vec<&FlagElement>> = BTreeMap::new();
+
+    for flag in flags {
+        namespace_to_flag.entry(&flag.device_config_namespace).or_default().push(flag);
+    }
+
     namespace_to_flag
-        .iter()
-        .map(|(namespace, flags)| NamespaceFlags {
-            namespace: namespace.to_string(),
-            flags: flags.clone(),
-        })
+        .into_iter()
+        .map(|(namespace, flags)| NamespaceFlags { namespace, flags })
         .collect()
 }
 
+// The flag elements are created once and shared by all parts of the context (and all rendered
+// files): for large packages, copying them dominates the time spent generating code.
 #[derive(Serialize)]
-struct Context {
-    pub flag_elements: Vec<FlagElement>,
-    pub exported_flag_elements: Vec<FlagElement>,
-    pub namespace_flags: Vec<NamespaceFlags>,
+struct Context<'a> {
+    pub flag_count: usize,
+    pub read_write_count: usize,
+    pub flag_elements: &'a [FlagElement],
+    pub exported_flag_elements: Vec<&'a FlagElement>,
+    pub namespace_flags: Vec<NamespaceFlags<'a>>,
+    pub flag_groups: Vec<FlagGroup<'a>>,
     pub is_test_mode: bool,
     pub runtime_lookup_required: bool,
     pub properties_set: BTreeSet<String>,
     pub package_name: String,
     pub library_exported: bool,
+    pub annotate_permissions: bool,
+    pub log_reads: bool,
+    pub value_provider: bool,
+    pub package_private: bool,
+    pub fold_read_only: bool,
+    pub device_config_key_annotation: bool,
+    pub change_listeners: bool,
+    pub default_values_map: bool,
+    pub flag_indices: bool,
+    pub has_fallback_namespaces: bool,
+    pub flags_class_name: String,
+    // empty if timing is disabled
+    pub read_timing_helper: String,
+    // empty if there is no kill switch
+    pub kill_switch_namespace: String,
+    pub kill_switch_key: String,
+}
+
+#[derive(Serialize)]
+struct AnnotationContext {
+    pub package_name: String,
+    pub annotation_name: String,
+}
+
+#[derive(Serialize, Debug)]
+struct FlagGroup<'a> {
+    pub name: String,
+    pub flags: Vec<&'a FlagElement>,
 }
 
 #[derive(Serialize, Debug)]
-struct NamespaceFlags {
-    pub namespace: String,
-    pub flags: Vec<FlagElement>,
+struct NamespaceFlags<'a> {
+    pub namespace: &'a str,
+    pub flags: Vec<&'a FlagElement>,
 }
 
 #[derive(Serialize, Clone, Debug)]
@@ -132,26 +604,37 @@ struct FlagElement {
     pub flag_name_constant_suffix: String,
     pub is_read_write: bool,
     pub method_name: String,
+    pub listener_method_name: String,
     pub properties: String,
     pub exported: bool,
+    pub aliases: Vec<String>,
+    pub fallback_namespaces: Vec<String>,
+    pub group: String,
+    // position of the flag in the flags ordered by name
+    pub index: usize,
 }
 
 fn create_flag_element(package: &str, pf: &ProtoParsedFlag) -> FlagElement {
     let device_config_flag = codegen::create_device_config_ident(package, pf.name())
         .expect("values checked at flag parse time");
     FlagElement {
-        default_value: pf.state() == ProtoFlagState::ENABLED,
+        default_value: pf.effective_default_value(),
         device_config_namespace: pf.namespace().to_string(),
         device_config_flag,
         flag_name_constant_suffix: pf.name().to_ascii_uppercase(),
-        is_read_write: pf.permission() == ProtoFlagPermission::READ_WRITE,
+        is_read_write: !pf.is_compile_time_constant(),
         method_name: format_java_method_name(pf.name()),
+        listener_method_name: format_java_listener_method_name(pf.name()),
         properties: format_property_name(pf.namespace()),
         exported: pf.is_exported.unwrap_or(false),
+        aliases: pf.alias.iter().map(|alias| format_java_method_name(alias)).collect(),
+        fallback_namespaces: pf.fallback_namespace.clone(),
+        group: pf.group().to_string(),
+        index: 0,
     }
 }
 
-fn format_java_method_name(flag_name: &str) -> String {
+pub fn format_java_method_name(flag_name: &str) -> String {
     let splits: Vec<&str> = flag_name.split('_').filter(|&word| !word.is_empty()).collect();
     if splits.len() == 1 {
         let name = splits[0];
@@ -172,6 +655,137 @@ fn format_java_method_name(flag_name: &str) -> String {
     }
 }
 
+fn is_valid_java_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+// Other names the templates use for classes are not allowed, to prevent clashes
+fn is_valid_java_class_name(name: &str) -> bool {
+    is_valid_java_identifier(name)
+        && ![
+            "FeatureFlags",
+            "FeatureFlagsImpl",
+            "FakeFeatureFlagsImpl",
+            "FlagsTestParams",
+            "FlagCountTest",
+            "ReadOnlyFlagsTest",
+            "FlagsValues",
+            "FlagConstants",
+            "ReadOnly",
+            "ReadWrite",
+            "FoldableFlag",
+            "DeviceConfigKey",
+        ]
+        .contains(&name)
+}
+
+// Members the templates generate besides those derived from flags
+const GENERATED_MEMBER_NAMES: &[&str] = &[
+    "PACKAGE",
+    "DEFAULTS",
+    "FEATURE_FLAGS",
+    "setFeatureFlags",
+    "unsetFeatureFlags",
+    "ALL_FLAGS",
+    "DEFAULT_VALUES",
+    "setFlag",
+    "resetAll",
+    "getValue",
+    "mFlagMap",
+    "mProvider",
+    "logRead",
+    "readWithFallback",
+    "LOG_FLAG_READS",
+    "TAG",
+    "current",
+    "FLAG_COUNT",
+];
+
+/// Verify no accessor (or alias) of a flag has the name of a member the templates generate.
+/// Names are compared ignoring case and underscores, e.g. featureFlags clashes with FEATURE_FLAGS.
+/// Also verify the constants generated for the flags, e.g. FLAG_<NAME> and <NAME>_INDEX, do not
+/// clash with each other.
+pub fn check_member_name_clashes<'a, I>(parsed_flags_iter: I) -> Result<()>
+where
+    I: Iterator<Item = &'a ProtoParsedFlag>,
+{
+    let normalize = |name: &str| name.replace('_', "").to_ascii_lowercase();
+    let parsed_flags: Vec<&ProtoParsedFlag> = parsed_flags_iter.collect();
+    // with every option which adds constants: read-only flags may be folded into VALUE_<NAME>
+    check_constant_name_clashes(
+        parsed_flags.iter().map(|pf| {
+            (
+                pf.fully_qualified_name(),
+                pf.name().to_ascii_uppercase(),
+                pf.is_compile_time_constant(),
+            )
+        }),
+        true,
+    )?;
+    for pf in parsed_flags {
+        let accessors = std::iter::once(pf.name()).chain(pf.alias.iter().map(|a| a.as_str()));
+        for accessor in accessors.map(format_java_method_name) {
+            if let Some(member) = GENERATED_MEMBER_NAMES
+                .iter()
+                .find(|member| normalize(member) == normalize(&accessor))
+            {
+                bail!(
+                    "accessor {} of flag {} clashes with generated member {}",
+                    accessor,
+                    pf.fully_qualified_name(),
+                    member
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// Of the constants Flags declares for each flag, no two may have the same name, e.g. FLAG_A_INDEX
+// (of flag a_index) and FLAG_A_INDEX (the index of flag flag_a). Each flag is given as its name,
+// the suffix of its constants and whether it has a VALUE_ constant; with `flag_indices` there are
+// also the <NAME>_INDEX constants and FLAG_COUNT.
+fn check_constant_name_clashes<I>(flags: I, flag_indices: bool) -> Result<()>
+where
+    I: Iterator<Item = (String, String, bool)>,
+{
+    // constant -> the flag it belongs to, None for FLAG_COUNT
+    let mut constants: BTreeMap<String, Option<String>> = BTreeMap::new();
+    if flag_indices {
+        constants.insert("FLAG_COUNT".to_string(), None);
+    }
+    for (flag, suffix, has_value) in flags {
+        let names = [
+            Some(format!("FLAG_{}", suffix)),
+            has_value.then(|| format!("VALUE_{}", suffix)),
+            flag_indices.then(|| format!("{}_INDEX", suffix)),
+        ];
+        for constant in names.into_iter().flatten() {
+            match constants.insert(constant.clone(), Some(flag.clone())) {
+                None => {}
+                Some(None) => bail!("flag {} clashes with the FLAG_COUNT constant", flag),
+                Some(Some(other)) => bail!(
+                    "flag {}: constant {} clashes with a constant of flag {}",
+                    flag,
+                    constant,
+                    other
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn format_java_listener_method_name(flag_name: &str) -> String {
+    let name = format_java_method_name(flag_name);
+    format!("add{}{}Listener", &name[0..1].to_ascii_uppercase(), &name[1..])
+}
+
 fn format_property_name(property_name: &str) -> String {
     let name = format_java_method_name(property_name);
     format!("mProperties{}{}", &name[0..1].to_ascii_uppercase(), &name[1..])
@@ -181,8 +795,10 @@ fn format_property_name(property_name: &str) -> String {
 mod tests {
     use super::*;
     use std::collections::HashMap;
+    use std::path::Path;
 
     const EXPECTED_FEATUREFLAGS_COMMON_CONTENT: &str = r#"
+    // Generated by aconfig 0.1.0. DO NOT EDIT.
     package com.android.aconfig.test;
     // TODO(b/303773055): Remove the annotation after access issue is resolved.
     import android.compat.annotation.UnsupportedAppUsage;
@@ -212,11 +828,14 @@ mod tests {
     "#;
 
     const EXPECTED_FLAG_COMMON_CONTENT: &str = r#"
+    // Generated by aconfig 0.1.0. DO NOT EDIT.
     package com.android.aconfig.test;
     // TODO(b/303773055): Remove the annotation after access issue is resolved.
     import android.compat.annotation.UnsupportedAppUsage;
     /** @hide */
     public final class Flags {
+        /** @hide */
+        public static final String PACKAGE = "com.android.aconfig.test";
         /** @hide */
         public static final String FLAG_DISABLED_RO = "com.android.aconfig.test.disabled_ro";
         /** @hide */
@@ -273,6 +892,7 @@ mod tests {
     "#;
 
     const EXPECTED_FAKEFEATUREFLAGSIMPL_CONTENT: &str = r#"
+    // Generated by aconfig 0.1.0. DO NOT EDIT.
     package com.android.aconfig.test;
     // TODO(b/303773055): Remove the annotation after access issue is resolved.
     import android.compat.annotation.UnsupportedAppUsage;
@@ -362,7 +982,7 @@ mod tests {
         let generated_files = generate_java_code(
             crate::test::TEST_PACKAGE,
             parsed_flags.parsed_flag.iter(),
-            CodegenMode::Production,
+            JavaCodegenConfig::new(CodegenMode::Production),
         )
         .unwrap();
         let expect_flags_content = EXPECTED_FLAG_COMMON_CONTENT.to_string()
@@ -371,6 +991,7 @@ mod tests {
         }"#;
 
         let expect_featureflagsimpl_content = r#"
+        // Generated by aconfig 0.1.0. DO NOT EDIT.
         package com.android.aconfig.test;
         // TODO(b/303773055): Remove the annotation after access issue is resolved.
         import android.compat.annotation.UnsupportedAppUsage;
@@ -378,6 +999,7 @@ mod tests {
         import android.provider.DeviceConfig.Properties;
         /** @hide */
         public final class FeatureFlagsImpl implements FeatureFlags {
+            // 4 read-write flag(s), read from DeviceConfig on first access
             private static boolean aconfig_test_is_cached = false;
             private static boolean other_namespace_is_cached = false;
             private static boolean disabledRw = false;
@@ -515,16 +1137,19 @@ mod tests {
         let generated_files = generate_java_code(
             crate::test::TEST_PACKAGE,
             parsed_flags.parsed_flag.iter(),
-            CodegenMode::Exported,
+            JavaCodegenConfig::new(CodegenMode::Exported),
         )
         .unwrap();
 
         let expect_flags_content = r#"
+        // Generated by aconfig 0.1.0. DO NOT EDIT.
         package com.android.aconfig.test;
         // TODO(b/303773055): Remove the annotation after access issue is resolved.
         import android.compat.annotation.UnsupportedAppUsage;
         /** @hide */
         public final class Flags {
+            /** @hide */
+            public static final String PACKAGE = "com.android.aconfig.test";
             /** @hide */
             public static final String FLAG_DISABLED_RW_EXPORTED = "com.android.aconfig.test.disabled_rw_exported";
             /** @hide */
@@ -543,6 +1168,7 @@ mod tests {
         "#;
 
         let expect_feature_flags_content = r#"
+        // Generated by aconfig 0.1.0. DO NOT EDIT.
         package com.android.aconfig.test;
         // TODO(b/303773055): Remove the annotation after access issue is resolved.
         import android.compat.annotation.UnsupportedAppUsage;
@@ -556,6 +1182,7 @@ mod tests {
         "#;
 
         let expect_feature_flags_impl_content = r#"
+        // Generated by aconfig 0.1.0. DO NOT EDIT.
         package com.android.aconfig.test;
         // TODO(b/303773055): Remove the annotation after access issue is resolved.
         import android.compat.annotation.UnsupportedAppUsage;
@@ -625,6 +1252,7 @@ mod tests {
         }"#;
 
         let expect_fake_feature_flags_impl_content = r#"
+        // Generated by aconfig 0.1.0. DO NOT EDIT.
         package com.android.aconfig.test;
         // TODO(b/303773055): Remove the annotation after access issue is resolved.
         import android.compat.annotation.UnsupportedAppUsage;
@@ -706,7 +1334,7 @@ mod tests {
         let generated_files = generate_java_code(
             crate::test::TEST_PACKAGE,
             parsed_flags.parsed_flag.iter(),
-            CodegenMode::Test,
+            JavaCodegenConfig::new(CodegenMode::Test),
         )
         .unwrap();
 
@@ -722,6 +1350,7 @@ mod tests {
         }
         "#;
         let expect_featureflagsimpl_content = r#"
+        // Generated by aconfig 0.1.0. DO NOT EDIT.
         package com.android.aconfig.test;
         // TODO(b/303773055): Remove the annotation after access issue is resolved.
         import android.compat.annotation.UnsupportedAppUsage;
@@ -806,6 +1435,1265 @@ mod tests {
         assert!(file_set.is_empty());
     }
 
+    #[test]
+    fn test_generate_java_code_test_params() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let generated_files = generate_java_code(
+            crate::test::TEST_PACKAGE,
+            parsed_flags.parsed_flag.iter(),
+            JavaCodegenConfig::new(CodegenMode::Production),
+        )
+        .unwrap();
+        assert!(!generated_files.iter().any(|file| file.path.ends_with("FlagsTestParams.java")));
+
+        let config = JavaCodegenConfig {
+            generate_test_params: true,
+            ..JavaCodegenConfig::new(CodegenMode::Production)
+        };
+        let generated_files =
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap();
+        let expect_flags_test_params_content = r#"
+        // Generated by aconfig 0.1.0. DO NOT EDIT.
+        package com.android.aconfig.test;
+        /** @hide */
+        public final class FlagsTestParams {
+            public static final String[] ALL_FLAGS = {
+                Flags.FLAG_DISABLED_RO,
+                Flags.FLAG_DISABLED_RW,
+                Flags.FLAG_DISABLED_RW_EXPORTED,
+                Flags.FLAG_DISABLED_RW_IN_OTHER_NAMESPACE,
+                Flags.FLAG_ENABLED_FIXED_RO,
+                Flags.FLAG_ENABLED_RO,
+                Flags.FLAG_ENABLED_RO_EXPORTED,
+                Flags.FLAG_ENABLED_RW
+            };
+            public static final boolean[] DEFAULT_VALUES = {
+                false,
+                false,
+                false,
+                false,
+                true,
+                true,
+                true,
+                true
+            };
+        }
+        "#;
+        let file = generated_files
+            .iter()
+            .find(|file| {
+                file.path.to_str() == Some("com/android/aconfig/test/FlagsTestParams.java")
+            })
+            .unwrap();
+        assert_eq!(
+            None,
+            crate::test::first_significant_code_diff(
+                expect_flags_test_params_content,
+                &String::from_utf8(file.contents.clone()).unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_generate_java_code_annotate_permissions() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let config = JavaCodegenConfig {
+            annotate_permissions: true,
+            ..JavaCodegenConfig::new(CodegenMode::Production)
+        };
+        let generated_files =
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap();
+        let contents_of = |name: &str| -> String {
+            let file = generated_files
+                .iter()
+                .find(|file| file.path == PathBuf::from("com/android/aconfig/test").join(name))
+                .unwrap();
+            String::from_utf8(file.contents.clone())
+                .unwrap()
+                .lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+
+        let flags = contents_of("Flags.java");
+        let feature_flags = contents_of("FeatureFlags.java");
+        for (method_name, annotation) in [
+            ("disabledRo", "@ReadOnly"),
+            ("disabledRw", "@ReadWrite"),
+            ("disabledRwExported", "@ReadWrite"),
+            ("disabledRwInOtherNamespace", "@ReadWrite"),
+            ("enabledFixedRo", "@ReadOnly"),
+            ("enabledRo", "@ReadOnly"),
+            ("enabledRoExported", "@ReadOnly"),
+            ("enabledRw", "@ReadWrite"),
+        ] {
+            let expected = format!(
+                "{}\n@UnsupportedAppUsage\npublic static boolean {}()",
+                annotation, method_name
+            );
+            assert!(flags.contains(&expected), "Flags.java: missing {}", expected);
+            let expected =
+                format!("{}\n@UnsupportedAppUsage\nboolean {}();", annotation, method_name);
+            assert!(feature_flags.contains(&expected), "FeatureFlags.java: missing {}", expected);
+        }
+
+        let expected = r#"
+        // Generated by aconfig 0.1.0. DO NOT EDIT.
+        package com.android.aconfig.test;
+        import java.lang.annotation.ElementType;
+        import java.lang.annotation.Retention;
+        import java.lang.annotation.RetentionPolicy;
+        import java.lang.annotation.Target;
+        /** @hide */
+        @Retention(RetentionPolicy.CLASS)
+        @Target(ElementType.METHOD)
+        public @interface ReadOnly {}
+        "#;
+        assert_eq!(
+            None,
+            crate::test::first_significant_code_diff(expected, &contents_of("ReadOnly.java"))
+        );
+        assert!(contents_of("ReadWrite.java").contains("public @interface ReadWrite {}"));
+    }
+
+    #[test]
+    fn test_generate_java_code_style() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let generate = |style: JavaStyle| -> String {
+            let config =
+                JavaCodegenConfig { style, ..JavaCodegenConfig::new(CodegenMode::Production) };
+            let generated_files = generate_java_code(
+                crate::test::TEST_PACKAGE,
+                parsed_flags.parsed_flag.iter(),
+                config,
+            )
+            .unwrap();
+            let file = generated_files
+                .iter()
+                .find(|file| file.path == Path::new("com/android/aconfig/test/Flags.java"))
+                .unwrap();
+            String::from_utf8(file.contents.clone()).unwrap()
+        };
+
+        let flags = generate(JavaStyle { indent_width: 2, braces_on_new_line: false });
+        assert!(flags.contains(
+            "\n  public static boolean disabledRo() {\n    return FEATURE_FLAGS.disabledRo();\n  }\n"
+        ));
+        assert!(!flags.contains("\n    public static"));
+
+        let flags = generate(JavaStyle { indent_width: 4, braces_on_new_line: true });
+        assert!(flags.contains("\npublic final class Flags\n{\n"));
+        assert!(flags.contains(
+            "\n    public static boolean disabledRo()\n    {\n        return FEATURE_FLAGS.disabledRo();\n    }\n"
+        ));
+    }
+
+    #[test]
+    fn test_generate_java_code_constants() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let generate = |codegen_mode| {
+            let config = JavaCodegenConfig {
+                generate_constants: true,
+                ..JavaCodegenConfig::new(codegen_mode)
+            };
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap()
+        };
+        let generated_files = generate(CodegenMode::Production);
+        let file = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/android/aconfig/test/FlagConstants.java"))
+            .unwrap();
+        let contents = std::str::from_utf8(&file.contents).unwrap();
+        let expect = r#"
+public final class FlagConstants {
+    public static final boolean DISABLED_RO = false;
+    public static final boolean ENABLED_FIXED_RO = true;
+    public static final boolean ENABLED_RO = true;
+    public static final boolean ENABLED_RO_EXPORTED = true;
+
+    private FlagConstants() {}
+}
+"#;
+        assert!(contents.contains(expect), "unexpected contents: {}", contents);
+        assert!(!contents.contains("import "));
+
+        let generated_files = generate(CodegenMode::Exported);
+        assert!(!generated_files
+            .iter()
+            .any(|file| file.path == Path::new("com/android/aconfig/test/FlagConstants.java")));
+    }
+
+    #[test]
+    fn test_generate_java_code_values_snapshot() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let config = JavaCodegenConfig {
+            generate_values_snapshot: true,
+            ..JavaCodegenConfig::new(CodegenMode::Production)
+        };
+        let generated_files =
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap();
+        let file = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/android/aconfig/test/FlagsValues.java"))
+            .unwrap();
+        let contents = std::str::from_utf8(&file.contents).unwrap();
+        let expect = r#"
+public record FlagsValues(
+        boolean disabledRo,
+        boolean disabledRw,
+        boolean disabledRwExported,
+        boolean disabledRwInOtherNamespace,
+        boolean enabledFixedRo,
+        boolean enabledRo,
+        boolean enabledRoExported,
+        boolean enabledRw) {
+    /** Read the current value of each flag. */
+    public static FlagsValues current() {
+        return new FlagsValues(
+                Flags.disabledRo(),
+                Flags.disabledRw(),
+                Flags.disabledRwExported(),
+                Flags.disabledRwInOtherNamespace(),
+                Flags.enabledFixedRo(),
+                Flags.enabledRo(),
+                Flags.enabledRoExported(),
+                Flags.enabledRw());
+    }
+}
+"#;
+        assert!(contents.contains(expect), "unexpected contents: {}", contents);
+        assert_eq!(parsed_flags.parsed_flag.len(), contents.matches("        boolean ").count());
+    }
+
+    #[test]
+    fn test_generate_java_code_read_timing_helper() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let generate = |read_timing_helper| {
+            let config = JavaCodegenConfig {
+                read_timing_helper,
+                ..JavaCodegenConfig::new(CodegenMode::Production)
+            };
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+        };
+
+        let generated_files = generate(Some("com.example.FlagMetrics")).unwrap();
+        let file = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/android/aconfig/test/FeatureFlagsImpl.java"))
+            .unwrap();
+        let contents = std::str::from_utf8(&file.contents).unwrap();
+        assert!(contents.contains(
+            r#"
+    public boolean enabledRw() {
+        long readStartNanos = System.nanoTime();
+        if (!aconfig_test_is_cached) {
+            load_overrides_aconfig_test();
+        }
+        com.example.FlagMetrics.recordFlagRead(
+            "com.android.aconfig.test.enabled_rw", System.nanoTime() - readStartNanos);
+        return enabledRw;
+    }
+"#
+        ));
+        // one sample per read-write flag, none for the read-only flags
+        assert_eq!(4, contents.matches("recordFlagRead(").count());
+        assert!(
+            contents.contains("\n    public boolean enabledRo() {\n        return true;\n    }\n")
+        );
+
+        let error = generate(Some("com.example.")).err().unwrap();
+        assert_eq!(format!("{:?}", error), "bad read timing helper class com.example.");
+    }
+
+    #[test]
+    fn test_generate_java_code_kill_switch() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let generate = |kill_switch| {
+            let config = JavaCodegenConfig {
+                kill_switch,
+                ..JavaCodegenConfig::new(CodegenMode::Production)
+            };
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+        };
+
+        let generated_files = generate(Some("aconfig_flags/com.example.kill_switch")).unwrap();
+        let file = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/android/aconfig/test/FeatureFlagsImpl.java"))
+            .unwrap();
+        let contents = std::str::from_utf8(&file.contents).unwrap();
+        assert!(contents.contains(
+            r#"
+            KILL_SWITCH = DeviceConfig.getBoolean(
+                "aconfig_flags", "com.example.kill_switch", false);
+"#
+        ));
+        // the kill switch is checked before DeviceConfig is read
+        assert!(contents.contains(
+            r#"
+    public boolean enabledRw() {
+        if (isKillSwitchEngaged()) {
+            return true;
+        }
+        if (!aconfig_test_is_cached) {
+            load_overrides_aconfig_test();
+        }
+        return enabledRw;
+    }
+"#
+        ));
+        assert_eq!(4, contents.matches("if (isKillSwitchEngaged())").count());
+        assert!(
+            contents.contains("\n    public boolean enabledRo() {\n        return true;\n    }\n")
+        );
+
+        let error = generate(Some("com.example.kill_switch")).err().unwrap();
+        assert_eq!(
+            format!("{:?}", error),
+            "bad kill switch com.example.kill_switch: expected <namespace>/<name>"
+        );
+    }
+
+    #[test]
+    fn test_generate_java_code_log_reads() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let generate = |log_reads: bool| -> String {
+            let config =
+                JavaCodegenConfig { log_reads, ..JavaCodegenConfig::new(CodegenMode::Production) };
+            let generated_files = generate_java_code(
+                crate::test::TEST_PACKAGE,
+                parsed_flags.parsed_flag.iter(),
+                config,
+            )
+            .unwrap();
+            let file = generated_files
+                .iter()
+                .find(|file| {
+                    file.path == Path::new("com/android/aconfig/test/FeatureFlagsImpl.java")
+                })
+                .unwrap();
+            String::from_utf8(file.contents.clone()).unwrap()
+        };
+
+        let impl_with_logging = generate(true);
+        assert!(impl_with_logging.contains("import android.util.Log;"));
+        assert!(impl_with_logging.contains(
+            "private static final boolean LOG_FLAG_READS = Log.isLoggable(TAG, Log.DEBUG);"
+        ));
+        assert!(impl_with_logging.contains("Log.d(TAG, flagName + \" = \" + value);"));
+        assert!(impl_with_logging
+            .contains("return logRead(\"com.android.aconfig.test.enabled_rw\", enabledRw);"));
+        assert!(!impl_with_logging.contains("return enabledRw;"));
+
+        let impl_without_logging = generate(false);
+        assert!(!impl_without_logging.contains("Log"));
+        assert!(impl_without_logging.contains("return enabledRw;"));
+
+        // read-only accessors stay constant returns, so R8 can still fold them
+        let accessor = |feature_flags_impl: &str, method_name: &str| {
+            let start =
+                feature_flags_impl.find(&format!("public boolean {}()", method_name)).unwrap();
+            let end = start + feature_flags_impl[start..].find('}').unwrap();
+            feature_flags_impl[start..end].to_string()
+        };
+        for method_name in ["disabledRo", "enabledFixedRo", "enabledRo", "enabledRoExported"] {
+            assert_eq!(
+                accessor(&impl_without_logging, method_name),
+                accessor(&impl_with_logging, method_name)
+            );
+        }
+        assert!(accessor(&impl_with_logging, "disabledRo").contains("return false;"));
+    }
+
+    #[test]
+    fn test_generate_java_code_value_provider() {
+        let mut parsed_flags = crate::test::parse_test_flags();
+        let flag =
+            parsed_flags.parsed_flag.iter_mut().find(|pf| pf.name() == "enabled_rw").unwrap();
+        flag.fallback_namespace.push("older_ns".to_string());
+        let config = JavaCodegenConfig {
+            value_provider: true,
+            kill_switch: Some("aconfig_test/kill_switch"),
+            ..JavaCodegenConfig::new(CodegenMode::Production)
+        };
+        let generated_files =
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap();
+        let file = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/android/aconfig/test/FeatureFlagsImpl.java"))
+            .unwrap();
+        let feature_flags_impl = std::str::from_utf8(&file.contents).unwrap();
+        let expected = r#"
+    public FeatureFlagsImpl() {
+        this(DeviceConfig::getProperties);
+    }
+
+    public FeatureFlagsImpl(FlagValueProvider provider) {
+        mProvider = provider;
+    }
+"#;
+        assert!(feature_flags_impl.contains(expected), "{}", feature_flags_impl);
+        assert!(feature_flags_impl
+            .contains("Properties properties = mProvider.getProperties(\"aconfig_test\");"));
+        // fallback namespaces and the kill switch are read from the provider too, so tests need
+        // not mock DeviceConfig
+        assert!(feature_flags_impl
+            .contains("Properties fallback = mProvider.getProperties(namespace, key);"));
+        assert!(feature_flags_impl.contains(
+            "KILL_SWITCH = mProvider.getProperties(\"aconfig_test\", \"kill_switch\")\n                .getBoolean(\"kill_switch\", false);"
+        ));
+        assert!(!feature_flags_impl.contains("DeviceConfig.get"));
+        // values are per instance: a provider injected in one test does not leak into the next
+        assert!(feature_flags_impl.contains("    private boolean aconfig_test_is_cached = false;"));
+        assert!(feature_flags_impl.contains("    private boolean enabledRw = true;"));
+        assert!(feature_flags_impl.contains("    private boolean KILL_SWITCH = false;"));
+        assert!(!feature_flags_impl.contains("private static boolean"));
+    }
+
+    #[test]
+    fn test_generate_java_code_package_constant() {
+        let parsed_flags = crate::protos::parsed_flags::rename_package(
+            &crate::test::parse_test_flags(),
+            "com.example.renamed",
+        )
+        .unwrap();
+        let generated_files = generate_java_code(
+            "com.example.renamed",
+            parsed_flags.parsed_flag.iter(),
+            JavaCodegenConfig::new(CodegenMode::Production),
+        )
+        .unwrap();
+        let file = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/example/renamed/Flags.java"))
+            .unwrap();
+        let flags = std::str::from_utf8(&file.contents).unwrap();
+        assert!(
+            flags.contains("\n    public static final String PACKAGE = \"com.example.renamed\";\n")
+        );
+    }
+
+    #[test]
+    fn test_generate_java_code_interface_only() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let config = JavaCodegenConfig {
+            interface_only: true,
+            generate_test_params: true,
+            ..JavaCodegenConfig::new(CodegenMode::Production)
+        };
+        let generated_files =
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap();
+        assert_eq!(1, generated_files.len());
+        assert_eq!(
+            Path::new("com/android/aconfig/test/FeatureFlags.java"),
+            generated_files[0].path
+        );
+        assert_eq!(
+            None,
+            crate::test::first_significant_code_diff(
+                EXPECTED_FEATUREFLAGS_COMMON_CONTENT,
+                std::str::from_utf8(&generated_files[0].contents).unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_generate_java_code_package_private() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let config = JavaCodegenConfig {
+            package_private: true,
+            ..JavaCodegenConfig::new(CodegenMode::Test)
+        };
+        let generated_files =
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap();
+        let file = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/android/aconfig/test/Flags.java"))
+            .unwrap();
+        let flags = std::str::from_utf8(&file.contents).unwrap();
+        assert!(flags.contains("\n    static boolean disabledRo() {\n"));
+        assert!(flags.contains("\n    static boolean enabledRw() {\n"));
+        assert!(!flags.contains("public static boolean"));
+        // only the accessors are affected
+        assert!(flags.contains("public static void setFeatureFlags(FeatureFlags featureFlags)"));
+    }
+
+    #[test]
+    fn test_generate_java_code_aliases() {
+        let mut parsed_flags = crate::test::parse_test_flags();
+        let flag =
+            parsed_flags.parsed_flag.iter_mut().find(|pf| pf.name() == "enabled_rw").unwrap();
+        flag.alias.push("old_enabled_rw".to_string());
+        let generated_files = generate_java_code(
+            crate::test::TEST_PACKAGE,
+            parsed_flags.parsed_flag.iter(),
+            JavaCodegenConfig::new(CodegenMode::Production),
+        )
+        .unwrap();
+        let file = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/android/aconfig/test/Flags.java"))
+            .unwrap();
+        let flags = std::str::from_utf8(&file.contents).unwrap();
+        assert!(flags.contains(
+            "    /** @deprecated Use {@link #enabledRw()} instead. */\n    @Deprecated\n    public static boolean oldEnabledRw() {\n        return enabledRw();\n    }\n"
+        ));
+        assert_eq!(1, flags.matches("@Deprecated").count());
+    }
+
+    #[test]
+    fn test_generate_java_code_fallback_namespaces() {
+        let mut parsed_flags = crate::test::parse_test_flags();
+        let flag =
+            parsed_flags.parsed_flag.iter_mut().find(|pf| pf.name() == "enabled_rw").unwrap();
+        flag.fallback_namespace.extend(["older_ns".to_string(), "oldest_ns".to_string()]);
+        let generated_files = generate_java_code(
+            crate::test::TEST_PACKAGE,
+            parsed_flags.parsed_flag.iter(),
+            JavaCodegenConfig::new(CodegenMode::Production),
+        )
+        .unwrap();
+        let file = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/android/aconfig/test/FeatureFlagsImpl.java"))
+            .unwrap();
+        let contents = std::str::from_utf8(&file.contents).unwrap();
+        assert!(contents.contains(
+            r#"
+            enabledRw =
+                readWithFallback(properties, "com.android.aconfig.test.enabled_rw", true, "older_ns", "oldest_ns");
+"#
+        ));
+        // a fallback namespace is only read if the namespaces before it do not have the key
+        let expected = r#"
+        if (properties.getKeyset().contains(key)) {
+            return properties.getBoolean(key, defaultValue);
+        }
+        for (String namespace : fallbackNamespaces) {
+            Properties fallback = DeviceConfig.getProperties(namespace, key);
+            if (fallback.getKeyset().contains(key)) {
+                return fallback.getBoolean(key, defaultValue);
+            }
+        }
+        return defaultValue;
+"#;
+        assert!(contents.contains(expected), "{}", contents);
+        assert_eq!(1, contents.matches("DeviceConfig.getProperties(namespace, key)").count());
+        // flags without fallback namespaces read their namespace only
+        assert!(contents.contains(
+            r#"
+            disabledRw =
+                properties.getBoolean("com.android.aconfig.test.disabled_rw", false);
+"#
+        ));
+    }
+
+    #[test]
+    fn test_generate_java_code_flag_groups() {
+        let mut parsed_flags = crate::test::parse_test_flags();
+        for pf in parsed_flags.parsed_flag.iter_mut() {
+            match pf.name() {
+                "disabled_rw" | "enabled_rw" => pf.set_group("networking".to_string()),
+                "enabled_ro" => pf.set_group("ui".to_string()),
+                _ => {}
+            }
+        }
+        let generated_files = generate_java_code(
+            crate::test::TEST_PACKAGE,
+            parsed_flags.parsed_flag.iter(),
+            JavaCodegenConfig::new(CodegenMode::Production),
+        )
+        .unwrap();
+        let file = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/android/aconfig/test/Flags.java"))
+            .unwrap();
+        let flags = std::str::from_utf8(&file.contents).unwrap();
+        let expect = r#"
+/**
+ * <h2>networking</h2>
+ * <ul>
+ *   <li>{@link #disabledRw()}</li>
+ *   <li>{@link #enabledRw()}</li>
+ * </ul>
+ * <h2>ui</h2>
+ * <ul>
+ *   <li>{@link #enabledRo()}</li>
+ * </ul>
+ * <h2>Other flags</h2>
+ * <ul>
+ *   <li>{@link #disabledRo()}</li>
+ *   <li>{@link #disabledRwExported()}</li>
+ *   <li>{@link #disabledRwInOtherNamespace()}</li>
+ *   <li>{@link #enabledFixedRo()}</li>
+ *   <li>{@link #enabledRoExported()}</li>
+ * </ul>
+ * @hide
+ */
+public final class Flags {
+"#;
+        assert!(flags.contains(expect), "unexpected contents: {}", flags);
+    }
+
+    #[test]
+    fn test_generate_java_code_fold_read_only() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let config = JavaCodegenConfig {
+            fold_read_only: true,
+            ..JavaCodegenConfig::new(CodegenMode::Production)
+        };
+        let generated_files =
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap();
+        let contents_of = |name: &str| -> String {
+            let file = generated_files
+                .iter()
+                .find(|file| file.path == PathBuf::from("com/android/aconfig/test").join(name))
+                .unwrap();
+            String::from_utf8(file.contents.clone()).unwrap()
+        };
+        let flags = contents_of("Flags.java");
+        assert!(flags.contains("public static final boolean VALUE_DISABLED_RO = false;"));
+        assert!(flags.contains("public static final boolean VALUE_ENABLED_RO = true;"));
+        assert!(!flags.contains("VALUE_ENABLED_RW"));
+        assert!(flags.contains(
+            "    @FoldableFlag\n    @UnsupportedAppUsage\n    public static boolean enabledFixedRo() {"
+        ));
+        // one per read-only flag
+        assert_eq!(4, flags.matches("@FoldableFlag").count());
+        assert!(contents_of("FoldableFlag.java").contains("public @interface FoldableFlag {}"));
+    }
+
+    #[test]
+    fn test_generate_java_code_device_config_key_annotation() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let config = JavaCodegenConfig {
+            device_config_key_annotation: true,
+            ..JavaCodegenConfig::new(CodegenMode::Production)
+        };
+        let generated_files =
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap();
+        let contents_of = |name: &str| -> String {
+            let file = generated_files
+                .iter()
+                .find(|file| file.path == PathBuf::from("com/android/aconfig/test").join(name))
+                .unwrap();
+            String::from_utf8(file.contents.clone()).unwrap()
+        };
+        let flags = contents_of("Flags.java");
+        for pf in parsed_flags.parsed_flag.iter() {
+            let fe = create_flag_element(crate::test::TEST_PACKAGE, pf);
+            let annotation = format!(
+                "    @DeviceConfigKey(namespace = \"{}\", key = \"{}\")\n    @UnsupportedAppUsage\n    public static boolean {}() {{",
+                fe.device_config_namespace, fe.device_config_flag, fe.method_name
+            );
+            assert_eq!(fe.is_read_write, flags.contains(&annotation), "{}", annotation);
+        }
+        assert!(flags.contains(
+            "    @DeviceConfigKey(namespace = \"other_namespace\", key = \"com.android.aconfig.test.disabled_rw_in_other_namespace\")"
+        ));
+        // one per read-write flag
+        assert_eq!(4, flags.matches("@DeviceConfigKey").count());
+        let annotation = contents_of("DeviceConfigKey.java");
+        assert!(annotation.contains("public @interface DeviceConfigKey {"));
+        assert!(annotation.contains("    String namespace();\n"));
+        assert!(annotation.contains("    String key();\n"));
+    }
+
+    #[test]
+    fn test_generate_java_code_flag_count_test() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let config = JavaCodegenConfig {
+            generate_flag_count_test: true,
+            ..JavaCodegenConfig::new(CodegenMode::Production)
+        };
+        let generated_files =
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap();
+        let contents_of = |name: &str| {
+            let file = generated_files
+                .iter()
+                .find(|file| file.path == Path::new("com/android/aconfig/test").join(name))
+                .unwrap_or_else(|| panic!("{} not generated", name));
+            String::from_utf8(file.contents.clone()).unwrap()
+        };
+        let expected = format!(
+            "assertEquals({}, FlagsTestParams.ALL_FLAGS.length);",
+            parsed_flags.parsed_flag.len()
+        );
+        assert!(contents_of("FlagCountTest.java").contains(&expected));
+        // the test refers to FlagsTestParams, so it is generated as well
+        assert!(contents_of("FlagsTestParams.java").contains("ALL_FLAGS"));
+    }
+
+    #[test]
+    fn test_generate_java_code_single_file() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let generate = |codegen_mode| {
+            let config =
+                JavaCodegenConfig { single_file: true, ..JavaCodegenConfig::new(codegen_mode) };
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap()
+        };
+        let generated_files = generate(CodegenMode::Production);
+        assert_eq!(1, generated_files.len());
+        assert_eq!(Path::new("com/android/aconfig/test/Flags.java"), generated_files[0].path);
+        let contents = std::str::from_utf8(&generated_files[0].contents).unwrap();
+
+        assert_eq!(1, contents.matches("\npackage com.android.aconfig.test;\n").count());
+        assert!(contents.starts_with("// Generated by aconfig"));
+        assert!(contents.contains("\npublic final class Flags {\n"));
+        assert!(contents.contains("\n    public interface FeatureFlags {\n"));
+        assert!(contents.contains(
+            "\n    public static final class FeatureFlagsImpl implements FeatureFlags {\n"
+        ));
+        assert!(contents.contains(
+            "\n    public static class FakeFeatureFlagsImpl implements FeatureFlags {\n"
+        ));
+        // the nested classes are inside Flags: the file ends with the closing brace of Flags
+        assert!(contents.trim_end().ends_with("    }\n}"));
+        assert_eq!(contents.matches('{').count(), contents.matches('}').count());
+        // each import once, before the classes
+        for import in [
+            "import android.compat.annotation.UnsupportedAppUsage;",
+            "import android.provider.DeviceConfig;",
+            "import java.util.HashMap;",
+        ] {
+            assert_eq!(1, contents.matches(import).count(), "{}", import);
+            assert!(contents.find(import).unwrap() < contents.find("class Flags").unwrap());
+        }
+        // the accessors of the nested classes are unchanged, apart from the indentation
+        assert!(contents.contains("\n        public boolean disabledRw() {\n"));
+
+        let generated_files = generate(CodegenMode::Test);
+        assert_eq!(1, generated_files.len());
+        let contents = std::str::from_utf8(&generated_files[0].contents).unwrap();
+        assert!(contents.contains("public static void setFeatureFlags(FeatureFlags featureFlags)"));
+        assert!(contents.contains("\n    public static final class FeatureFlagsImpl"));
+    }
+
+    #[test]
+    fn test_generate_java_code_read_only_test() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let generate = |codegen_mode| {
+            let config = JavaCodegenConfig {
+                generate_read_only_test: true,
+                ..JavaCodegenConfig::new(codegen_mode)
+            };
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap()
+                .into_iter()
+                .find(|file| {
+                    file.path == Path::new("com/android/aconfig/test/ReadOnlyFlagsTest.java")
+                })
+                .map(|file| String::from_utf8(file.contents).unwrap())
+        };
+        let test = generate(CodegenMode::Production).unwrap();
+        for pf in parsed_flags.parsed_flag.iter() {
+            let fe = create_flag_element(crate::test::TEST_PACKAGE, pf);
+            let expected = format!(
+                "assertEquals(Flags.FLAG_{}, {}, Flags.{}());",
+                fe.flag_name_constant_suffix, fe.default_value, fe.method_name
+            );
+            assert_eq!(!fe.is_read_write, test.contains(&expected), "{}", expected);
+        }
+        // one per read-only flag
+        assert_eq!(4, test.matches("assertEquals(").count());
+        assert!(test
+            .contains("assertEquals(Flags.FLAG_ENABLED_FIXED_RO, true, Flags.enabledFixedRo());"));
+        assert!(test.contains("assertEquals(Flags.FLAG_DISABLED_RO, false, Flags.disabledRo());"));
+
+        assert!(generate(CodegenMode::Test).is_none());
+        assert!(generate(CodegenMode::Exported).is_none());
+    }
+
+    #[test]
+    fn test_generate_java_code_change_listeners() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let generate = |change_listeners| {
+            let config = JavaCodegenConfig {
+                change_listeners,
+                ..JavaCodegenConfig::new(CodegenMode::Production)
+            };
+            let generated_files = generate_java_code(
+                crate::test::TEST_PACKAGE,
+                parsed_flags.parsed_flag.iter(),
+                config,
+            )
+            .unwrap();
+            let file = generated_files
+                .iter()
+                .find(|file| file.path == Path::new("com/android/aconfig/test/Flags.java"))
+                .unwrap();
+            String::from_utf8(file.contents.clone()).unwrap()
+        };
+
+        assert!(!generate(false).contains("Listener"));
+        let flags = generate(true);
+        assert!(flags.contains("import android.provider.DeviceConfig;\n"));
+        assert!(flags.contains("import java.util.concurrent.Executor;\n"));
+        assert!(flags.contains(
+            r#"
+    public static DeviceConfig.OnPropertiesChangedListener addDisabledRwInOtherNamespaceListener(
+            Executor executor, Runnable callback) {
+        DeviceConfig.OnPropertiesChangedListener listener = properties -> {
+            if (properties.getKeyset().contains(FLAG_DISABLED_RW_IN_OTHER_NAMESPACE)) {
+                callback.run();
+            }
+        };
+        DeviceConfig.addOnPropertiesChangedListener(
+                "other_namespace", executor, listener);
+        return listener;
+    }
+"#
+        ));
+        for pf in parsed_flags.parsed_flag.iter() {
+            let listener_method_name = format_java_listener_method_name(pf.name());
+            assert_eq!(
+                !pf.is_compile_time_constant(),
+                flags.contains(&format!(" {}(", listener_method_name)),
+                "{}",
+                listener_method_name
+            );
+        }
+        // one per read-write flag
+        assert_eq!(4, flags.matches("DeviceConfig.addOnPropertiesChangedListener(").count());
+    }
+
+    #[test]
+    fn test_generate_java_code_default_values_map() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let config = JavaCodegenConfig {
+            default_values_map: true,
+            ..JavaCodegenConfig::new(CodegenMode::Production)
+        };
+        let generated_files =
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap();
+        let flags = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/android/aconfig/test/Flags.java"))
+            .unwrap();
+        let flags = std::str::from_utf8(&flags.contents).unwrap();
+        assert!(flags.contains("\nimport java.util.Map;\n"));
+        let expected = r#"
+    /** Default value of each flag, keyed by its full name. @hide */
+    public static final Map<String, Boolean> DEFAULTS = Map.ofEntries(
+        Map.entry(FLAG_DISABLED_RO, false),
+        Map.entry(FLAG_DISABLED_RW, false),
+        Map.entry(FLAG_DISABLED_RW_EXPORTED, false),
+        Map.entry(FLAG_DISABLED_RW_IN_OTHER_NAMESPACE, false),
+        Map.entry(FLAG_ENABLED_FIXED_RO, true),
+        Map.entry(FLAG_ENABLED_RO, true),
+        Map.entry(FLAG_ENABLED_RO_EXPORTED, true),
+        Map.entry(FLAG_ENABLED_RW, true)
+    );
+"#;
+        assert!(flags.contains(expected), "{}", flags);
+    }
+
+    #[test]
+    fn test_context_read_write_count() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let generated_files = generate_java_code(
+            crate::test::TEST_PACKAGE,
+            parsed_flags.parsed_flag.iter(),
+            JavaCodegenConfig::new(CodegenMode::Production),
+        )
+        .unwrap();
+        let feature_flags_impl = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/android/aconfig/test/FeatureFlagsImpl.java"))
+            .unwrap();
+        let feature_flags_impl = std::str::from_utf8(&feature_flags_impl.contents).unwrap();
+        assert!(feature_flags_impl
+            .contains("    // 4 read-write flag(s), read from DeviceConfig on first access\n"));
+
+        // without read-write flags, FeatureFlagsImpl has nothing to count
+        let read_only_flags: Vec<_> =
+            parsed_flags.parsed_flag.iter().filter(|pf| pf.is_compile_time_constant()).collect();
+        let generated_files = generate_java_code(
+            crate::test::TEST_PACKAGE,
+            read_only_flags.into_iter(),
+            JavaCodegenConfig::new(CodegenMode::Production),
+        )
+        .unwrap();
+        let feature_flags_impl = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/android/aconfig/test/FeatureFlagsImpl.java"))
+            .unwrap();
+        let feature_flags_impl = std::str::from_utf8(&feature_flags_impl.contents).unwrap();
+        assert!(!feature_flags_impl.contains("read-write flag(s)"));
+    }
+
+    #[test]
+    fn test_generate_java_code_flag_indices() {
+        let mut parsed_flags = crate::test::parse_test_flags();
+        // the index follows the name, not the order of the flags
+        parsed_flags.parsed_flag.reverse();
+        let config = JavaCodegenConfig {
+            flag_indices: true,
+            ..JavaCodegenConfig::new(CodegenMode::Production)
+        };
+        let generated_files =
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap();
+        let flags = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/android/aconfig/test/Flags.java"))
+            .unwrap();
+        let flags = std::str::from_utf8(&flags.contents).unwrap();
+        let mut names: Vec<&str> = parsed_flags.parsed_flag.iter().map(|pf| pf.name()).collect();
+        names.sort();
+        for (index, name) in names.iter().enumerate() {
+            let constant = format!(
+                "    public static final int {}_INDEX = {};\n",
+                name.to_ascii_uppercase(),
+                index
+            );
+            assert!(flags.contains(&constant), "{}", flags);
+        }
+        assert!(flags.contains("    public static final int FLAG_COUNT = 8;\n"));
+
+        parsed_flags.parsed_flag[0].set_name("count".to_string());
+        let error =
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .err()
+                .unwrap();
+        assert_eq!(
+            format!("{:?}", error),
+            "flag com.android.aconfig.test.count clashes with the FLAG_COUNT constant"
+        );
+
+        // the index of one flag has the name of the folded value of another
+        let mut parsed_flags = crate::test::parse_test_flags();
+        assert!(parsed_flags.parsed_flag[0].is_compile_time_constant());
+        parsed_flags.parsed_flag[0].set_name("a_index".to_string());
+        parsed_flags.parsed_flag[1].set_name("value_a".to_string());
+        let generate = |fold_read_only| {
+            let config = JavaCodegenConfig { fold_read_only, ..config };
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+        };
+        assert!(generate(false).is_ok());
+        let error = generate(true).err().unwrap();
+        assert_eq!(
+            format!("{:?}", error),
+            "flag com.android.aconfig.test.value_a: constant VALUE_A_INDEX clashes with a constant of flag com.android.aconfig.test.a_index"
+        );
+    }
+
+    #[test]
+    fn test_check_member_name_clashes() {
+        let mut parsed_flags = crate::test::parse_test_flags();
+        assert!(check_member_name_clashes(parsed_flags.parsed_flag.iter()).is_ok());
+
+        parsed_flags.parsed_flag[0].set_name("feature_flags".to_string());
+        let error = check_member_name_clashes(parsed_flags.parsed_flag.iter()).unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "accessor featureFlags of flag com.android.aconfig.test.feature_flags clashes with generated member FEATURE_FLAGS"
+        );
+
+        parsed_flags.parsed_flag[0].set_name("disabled_ro".to_string());
+        parsed_flags.parsed_flag[0].alias.push("package".to_string());
+        let error = check_member_name_clashes(parsed_flags.parsed_flag.iter()).unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "accessor package of flag com.android.aconfig.test.disabled_ro clashes with generated member PACKAGE"
+        );
+        parsed_flags.parsed_flag[0].alias.clear();
+
+        parsed_flags.parsed_flag[1].set_name("flag_count".to_string());
+        let error = check_member_name_clashes(parsed_flags.parsed_flag.iter()).unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "accessor flagCount of flag com.android.aconfig.test.flag_count clashes with generated member FLAG_COUNT"
+        );
+
+        // the constants of two flags: VALUE_A_INDEX of read-only a_index, the index of value_a
+        assert!(parsed_flags.parsed_flag[0].is_compile_time_constant());
+        parsed_flags.parsed_flag[0].set_name("a_index".to_string());
+        parsed_flags.parsed_flag[1].set_name("value_a".to_string());
+        let error = check_member_name_clashes(parsed_flags.parsed_flag.iter()).unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "flag com.android.aconfig.test.value_a: constant VALUE_A_INDEX clashes with a constant of flag com.android.aconfig.test.a_index"
+        );
+    }
+
+    #[test]
+    fn test_generate_java_code_flags_class_name() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let generate = |flags_class_name| {
+            let config = JavaCodegenConfig {
+                flags_class_name,
+                generate_test_params: true,
+                ..JavaCodegenConfig::new(CodegenMode::Test)
+            };
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+        };
+        let generated_files = generate("MyFeatureFlags").unwrap();
+        let contents_of = |name: &str| {
+            let file = generated_files
+                .iter()
+                .find(|file| file.path == Path::new("com/android/aconfig/test").join(name))
+                .unwrap_or_else(|| panic!("{} not generated", name));
+            String::from_utf8(file.contents.clone()).unwrap()
+        };
+        assert!(!generated_files.iter().any(|file| file.path.ends_with("Flags.java")));
+        let flags = contents_of("MyFeatureFlags.java");
+        assert!(flags.contains("\npublic final class MyFeatureFlags {\n"));
+        assert!(flags.contains("        MyFeatureFlags.FEATURE_FLAGS = featureFlags;\n"));
+        assert!(contents_of("FakeFeatureFlagsImpl.java")
+            .contains("return getValue(MyFeatureFlags.FLAG_DISABLED_RO);"));
+        assert!(contents_of("FlagsTestParams.java").contains("MyFeatureFlags.FLAG_DISABLED_RO,"));
+        assert!(!contents_of("FeatureFlags.java").contains("MyFeatureFlags"));
+
+        for bad_name in ["", "1Flags", "My.Flags", "FeatureFlagsImpl"] {
+            let error = generate(bad_name).err().unwrap();
+            assert_eq!(format!("{:?}", error), format!("bad class name {}", bad_name));
+        }
+    }
+
+    #[test]
+    fn test_generate_java_code_file_names_match_class_names() {
+        let parsed_flags = crate::test::parse_test_flags();
+        for flags_class_name in ["Flags", "GeneratedFlags"] {
+            let config = JavaCodegenConfig {
+                flags_class_name,
+                generate_flag_count_test: true,
+                annotate_permissions: true,
+                ..JavaCodegenConfig::new(CodegenMode::Production)
+            };
+            let generated_files = generate_java_code(
+                crate::test::TEST_PACKAGE,
+                parsed_flags.parsed_flag.iter(),
+                config,
+            )
+            .unwrap();
+            assert_eq!(
+                Path::new(&format!("com/android/aconfig/test/{}.java", flags_class_name)),
+                generated_files[0].path
+            );
+            for file in generated_files.iter() {
+                let class_name = file.path.file_stem().unwrap().to_str().unwrap();
+                let contents = std::str::from_utf8(&file.contents).unwrap();
+                assert!(
+                    [" class ", " interface ", " @interface "]
+                        .iter()
+                        .any(|kind| contents.contains(&format!("{}{} {{", kind, class_name))
+                            || contents.contains(&format!("{}{} implements", kind, class_name))),
+                    "{} does not declare {}",
+                    file.path.display(),
+                    class_name
+                );
+            }
+        }
+    }
+
+    // 625 copies of each test flag, named e.g. disabled_ro_0042: the copies of a flag sort next to
+    // each other, in the order of the original flags
+    fn large_package_flags() -> Vec<ProtoParsedFlag> {
+        crate::test::parse_test_flags()
+            .parsed_flag
+            .into_iter()
+            .flat_map(|pf| {
+                (0..625).map(move |i| {
+                    let mut copy = pf.clone();
+                    copy.set_name(format!("{}_{:04}", pf.name(), i));
+                    copy
+                })
+            })
+            .collect()
+    }
+
+    // The expected content for large_package_flags: `golden` (for the test flags) with each block
+    // of lines about one flag repeated for every copy of that flag. Blocks end at the lines for
+    // which `is_block_end` holds; the lines up to and including `header_end` are not repeated.
+    fn expand_golden(golden: &str, header_end: &str, is_block_end: fn(&str) -> bool) -> String {
+        let names: Vec<String> = crate::test::parse_test_flags()
+            .parsed_flag
+            .iter()
+            .map(|pf| pf.name().to_string())
+            .collect();
+        let mut lines = golden.lines().map(str::trim).filter(|line| !line.is_empty());
+        let mut expected: Vec<String> =
+            lines.by_ref().take_while(|line| *line != header_end).map(String::from).collect();
+        expected.push(header_end.to_string());
+        let mut block = Vec::new();
+        for line in lines {
+            block.push(line);
+            if !is_block_end(line) {
+                continue;
+            }
+            let text = block.join("\n");
+            let forms =
+                |name: &str| [name.to_string(), format_java_method_name(name), name.to_uppercase()];
+            // the longest match, since e.g. disabled_rw is a prefix of disabled_rw_exported
+            let name = names
+                .iter()
+                .filter(|name| forms(name).iter().any(|form| text.contains(form.as_str())))
+                .max_by_key(|name| name.len());
+            match name {
+                Some(name) => {
+                    let [snake, camel, upper] = forms(name);
+                    expected.extend((0..625).map(|i| {
+                        text.replace(&snake, &format!("{}_{:04}", snake, i))
+                            .replace(&camel, &format!("{}{:04}", camel, i))
+                            .replace(&upper, &format!("{}_{:04}", upper, i))
+                    }));
+                }
+                None => expected.push(text),
+            }
+            block.clear();
+        }
+        expected.extend(block.into_iter().map(String::from));
+        expected.join("\n")
+    }
+
+    #[test]
+    fn test_generate_java_code_large_package() {
+        let parsed_flags = large_package_flags();
+        let config = JavaCodegenConfig::new(CodegenMode::Production);
+        let generated_files =
+            generate_java_code(crate::test::TEST_PACKAGE, parsed_flags.iter(), config).unwrap();
+        let contents_of = |name: &str| {
+            let file = generated_files
+                .iter()
+                .find(|file| file.path == Path::new("com/android/aconfig/test").join(name))
+                .unwrap();
+            std::str::from_utf8(&file.contents).unwrap().to_string()
+        };
+
+        let expected_feature_flags = expand_golden(
+            EXPECTED_FEATUREFLAGS_COMMON_CONTENT,
+            "public interface FeatureFlags {",
+            |line| line.ends_with("();"),
+        );
+        assert_eq!(
+            None,
+            crate::test::first_significant_code_diff(
+                &expected_feature_flags,
+                &contents_of("FeatureFlags.java")
+            )
+        );
+        let expected_flags = expand_golden(
+            &(EXPECTED_FLAG_COMMON_CONTENT.to_string()
+                + "private static FeatureFlags FEATURE_FLAGS = new FeatureFlagsImpl();\n}"),
+            "public static final String PACKAGE = \"com.android.aconfig.test\";",
+            |line| line.starts_with("public static final String FLAG_") || line == "}",
+        );
+        assert_eq!(
+            None,
+            crate::test::first_significant_code_diff(&expected_flags, &contents_of("Flags.java"))
+        );
+
+        let feature_flags_impl = contents_of("FeatureFlagsImpl.java");
+        assert_eq!(2, feature_flags_impl.matches("    private void load_overrides_").count());
+        // every read-write flag is loaded with the other flags in its namespace
+        assert_eq!(2500, feature_flags_impl.matches("properties.getBoolean(").count());
+    }
+
+    // Renders all templates with every combination of modes and options, so a template referring
+    // to a field missing from its context (which tinytemplate only detects when the template is
+    // rendered) fails here rather than in the build.
+    #[test]
+    fn test_all_templates_render() {
+        let all_flags = crate::test::parse_test_flags();
+        let read_only_flags: Vec<ProtoParsedFlag> = all_flags
+            .parsed_flag
+            .iter()
+            .filter(|pf| pf.is_compile_time_constant())
+            .cloned()
+            .collect();
+        for flags in [&all_flags.parsed_flag[..], &read_only_flags[..]] {
+            for codegen_mode in [CodegenMode::Production, CodegenMode::Test, CodegenMode::Exported]
+            {
+                for options in 0..512 {
+                    let config = JavaCodegenConfig {
+                        generate_test_params: options & 1 != 0,
+                        // both annotate the accessors in Flags
+                        annotate_permissions: options & 2 != 0,
+                        device_config_key_annotation: options & 2 != 0,
+                        // both change FeatureFlagsImpl
+                        log_reads: options & 4 != 0,
+                        value_provider: options & 4 != 0,
+                        // both change Flags.java
+                        package_private: options & 8 != 0,
+                        single_file: options & 8 != 0,
+                        // both generate a JUnit test
+                        generate_flag_count_test: options & 16 != 0,
+                        generate_read_only_test: options & 16 != 0,
+                        change_listeners: options & 32 != 0,
+                        // both add constants to Flags
+                        default_values_map: options & 64 != 0,
+                        flag_indices: options & 64 != 0,
+                        // both add code to the read-write accessors in FeatureFlagsImpl
+                        read_timing_helper: if options & 128 != 0 {
+                            Some("com.example.FlagMetrics")
+                        } else {
+                            None
+                        },
+                        kill_switch: if options & 128 != 0 {
+                            Some("aconfig_flags/com.example.kill_switch")
+                        } else {
+                            None
+                        },
+                        // both only add a file, which no other file depends on
+                        generate_values_snapshot: options & 256 != 0,
+                        generate_constants: options & 256 != 0,
+                        ..JavaCodegenConfig::new(codegen_mode)
+                    };
+                    let generated_files =
+                        generate_java_code(crate::test::TEST_PACKAGE, flags.iter(), config)
+                            .unwrap_or_else(|e| panic!("failed to render {:?}: {:?}", config, e));
+                    let expected_count = if config.single_file { 1 } else { 4 }
+                        + if config.generate_test_params || config.generate_flag_count_test {
+                            1
+                        } else {
+                            0
+                        }
+                        + if config.generate_flag_count_test { 1 } else { 0 }
+                        + if config.generate_read_only_test
+                            && codegen_mode == CodegenMode::Production
+                        {
+                            1
+                        } else {
+                            0
+                        }
+                        + if config.annotate_permissions { 2 } else { 0 }
+                        + if config.device_config_key_annotation { 1 } else { 0 }
+                        + if config.generate_values_snapshot { 1 } else { 0 }
+                        + if config.generate_constants && codegen_mode != CodegenMode::Exported {
+                            1
+                        } else {
+                            0
+                        };
+                    assert_eq!(expected_count, generated_files.len());
+                    for file in generated_files {
+                        let contents = std::str::from_utf8(&file.contents).unwrap();
+                        assert!(
+                            contents.contains("\npackage com.android.aconfig.test;\n"),
+                            "{}: unexpected contents for {:?}",
+                            file.path.display(),
+                            config
+                        );
+                    }
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_format_java_method_name() {
         let expected = "someSnakeName";
@@ -19,6 +19,9 @@ pub mod java;
 pub mod rust;
 
 use anyhow::{ensure, Result};
+use clap::ValueEnum;
+
+use crate::commands::OutputFile;
 
 pub fn is_valid_name_ident(s: &str) -> bool {
     // Identifiers must match [a-z][a-z0-9_]*, except consecutive underscores are not allowed
@@ -35,11 +38,17 @@ pub fn is_valid_name_ident(s: &str) -> bool {
     chars.all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '_')
 }
 
+/// The dot separated parts of a package name, e.g. ["com", "android", "foo"] for
+/// "com.android.foo", for output paths and module hierarchies derived from the package
+pub fn package_segments(package: &str) -> impl Iterator<Item = &str> {
+    package.split('.')
+}
+
 pub fn is_valid_package_ident(s: &str) -> bool {
     if !s.contains('.') {
         return false;
     }
-    s.split('.').all(is_valid_name_ident)
+    package_segments(s).all(is_valid_name_ident)
 }
 
 pub fn is_valid_container_ident(s: &str) -> bool {
@@ -47,15 +56,165 @@ pub fn is_valid_container_ident(s: &str) -> bool {
 }
 
 pub fn create_device_config_ident(package: &str, flag_name: &str) -> Result<String> {
-    ensure!(is_valid_package_ident(package), "bad package");
-    ensure!(is_valid_name_ident(flag_name), "bad flag name");
-    Ok(format!("{}.{}", package, flag_name))
+    // DeviceConfig keys are case sensitive: a key with upper case letters would silently never
+    // match the lower case key the server side tooling pushes
+    let key = format!("{}.{}", package, flag_name);
+    ensure!(
+        !key.chars().any(|ch| ch.is_ascii_uppercase()),
+        "flag {}: device config key {} is not lowercase",
+        flag_name,
+        key
+    );
+    ensure!(
+        is_valid_package_ident(package),
+        "flag {}: bad package in device config key {}",
+        flag_name,
+        key
+    );
+    ensure!(
+        is_valid_name_ident(flag_name),
+        "flag {}: bad flag name in device config key {}",
+        flag_name,
+        key
+    );
+    Ok(key)
+}
+
+/// Comment at the top of every generated source file. On purpose this does not include a
+/// timestamp: the output must only depend on the input and the version of aconfig.
+pub fn generated_file_header() -> String {
+    format!("// Generated by aconfig {}. DO NOT EDIT.\n", env!("CARGO_PKG_VERSION"))
+}
+
+/// Put `header` (e.g. a license) at the very top of each file. The header may only consist of
+/// comments and blank lines, so it cannot change the meaning of the generated code.
+pub fn prepend_license_header(files: &mut [OutputFile], header: &str) -> Result<()> {
+    let mut in_block_comment = false;
+    for line in header.lines().map(str::trim) {
+        let is_comment =
+            in_block_comment || line.is_empty() || line.starts_with("//") || line.starts_with("/*");
+        ensure!(is_comment, "bad license header: not a comment: {}", line);
+        if line.starts_with("/*") {
+            in_block_comment = true;
+        }
+        if in_block_comment && line.ends_with("*/") {
+            in_block_comment = false;
+        }
+    }
+    ensure!(!in_block_comment, "bad license header: unterminated comment");
+
+    let mut header = header.to_string();
+    if !header.is_empty() && !header.ends_with('\n') {
+        header.push('\n');
+    }
+    for file in files.iter_mut() {
+        file.contents.splice(0..0, header.bytes());
+    }
+    Ok(())
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+/// Use `line_ending` for every line of each file. The templates use LF, but a license header may
+/// already use CRLF: lines are normalized first, so the files never mix line endings.
+pub fn apply_line_ending(files: &mut [OutputFile], line_ending: LineEnding) {
+    for file in files.iter_mut() {
+        let mut contents = Vec::with_capacity(file.contents.len());
+        for (i, &b) in file.contents.iter().enumerate() {
+            if b == b'\r' && file.contents.get(i + 1) == Some(&b'\n') {
+                continue;
+            }
+            if b == b'\n' && line_ending == LineEnding::Crlf {
+                contents.push(b'\r');
+            }
+            contents.push(b);
+        }
+        file.contents = contents;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_prepend_license_header() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let mut generated_files = java::generate_java_code(
+            crate::test::TEST_PACKAGE,
+            parsed_flags.parsed_flag.iter(),
+            java::JavaCodegenConfig::new(crate::commands::CodegenMode::Production),
+        )
+        .unwrap();
+        let header =
+            "/*\n * Copyright (C) 2024 Example Corp.\n * Licensed under the MIT license.\n */";
+        prepend_license_header(&mut generated_files, header).unwrap();
+        for file in generated_files.iter() {
+            let contents = std::str::from_utf8(&file.contents).unwrap();
+            let expected = format!(
+                "{}\n{}package com.android.aconfig.test;\n",
+                header,
+                generated_file_header()
+            );
+            assert!(contents.starts_with(&expected), "{}", contents);
+        }
+    }
+
+    #[test]
+    fn test_apply_line_ending() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let mut generated_files = java::generate_java_code(
+            crate::test::TEST_PACKAGE,
+            parsed_flags.parsed_flag.iter(),
+            java::JavaCodegenConfig::new(crate::commands::CodegenMode::Production),
+        )
+        .unwrap();
+        let original: Vec<Vec<u8>> =
+            generated_files.iter().map(|file| file.contents.clone()).collect();
+        prepend_license_header(
+            &mut generated_files,
+            "// Copyright (C) 2024\r\n// Example Corp.\r\n",
+        )
+        .unwrap();
+
+        apply_line_ending(&mut generated_files, LineEnding::Crlf);
+        for file in generated_files.iter() {
+            let contents = std::str::from_utf8(&file.contents).unwrap();
+            assert!(
+                contents.starts_with("// Copyright (C) 2024\r\n// Example Corp.\r\n// Generated")
+            );
+            assert!(!contents.contains("\r\r"));
+            // no lone LF
+            assert_eq!(contents.matches('\n').count(), contents.matches("\r\n").count());
+        }
+
+        apply_line_ending(&mut generated_files, LineEnding::Lf);
+        for (file, original) in generated_files.iter().zip(original.iter()) {
+            let contents = std::str::from_utf8(&file.contents).unwrap();
+            assert!(!contents.contains('\r'));
+            assert!(contents.ends_with(std::str::from_utf8(original).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_prepend_license_header_rejects_code() {
+        let mut files =
+            [OutputFile { path: "a/Flags.java".into(), contents: b"package a;\n".to_vec() }];
+        let error = prepend_license_header(&mut files, "// License\npackage b;\n").unwrap_err();
+        assert_eq!(format!("{:?}", error), "bad license header: not a comment: package b;");
+        let error = prepend_license_header(&mut files, "/* License\n").unwrap_err();
+        assert_eq!(format!("{:?}", error), "bad license header: unterminated comment");
+        assert_eq!(b"package a;\n".to_vec(), files[0].contents);
+
+        prepend_license_header(&mut files, "").unwrap();
+        assert_eq!(b"package a;\n".to_vec(), files[0].contents);
+    }
+
     #[test]
     fn test_is_valid_name_ident() {
         assert!(is_valid_name_ident("foo"));
@@ -70,6 +229,14 @@ mod tests {
         assert!(!is_valid_name_ident("_foo"));
     }
 
+    #[test]
+    fn test_package_segments() {
+        assert_eq!(
+            vec!["com", "android", "aconfig", "test"],
+            package_segments("com.android.aconfig.test").collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn test_is_valid_package_ident() {
         assert!(is_valid_package_ident("foo.bar"));
@@ -121,5 +288,16 @@ mod tests {
             "com.foo.bar.some_flag",
             create_device_config_ident("com.foo.bar", "some_flag").unwrap()
         );
+
+        let error = create_device_config_ident("com.Foo.bar", "some_flag").unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "flag some_flag: device config key com.Foo.bar.some_flag is not lowercase"
+        );
+        let error = create_device_config_ident("com.foo.bar", "1flag").unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "flag 1flag: bad flag name in device config key com.foo.bar.1flag"
+        );
     }
 }
@@ -20,24 +20,40 @@ use tinytemplate::TinyTemplate;
 
 use crate::codegen;
 use crate::commands::{CodegenMode, OutputFile};
-use crate::protos::{ProtoFlagPermission, ProtoFlagState, ProtoParsedFlag};
+use crate::protos::{ParsedFlagExt, ProtoParsedFlag};
+
+#[derive(Copy, Clone, Debug)]
+pub struct RustCodegenConfig {
+    pub codegen_mode: CodegenMode,
+    /// Also generate a const for each read-only flag, for use in const contexts (production mode
+    /// only: in test mode every flag can be overridden)
+    pub read_only_consts: bool,
+}
+
+impl RustCodegenConfig {
+    pub fn new(codegen_mode: CodegenMode) -> Self {
+        RustCodegenConfig { codegen_mode, read_only_consts: false }
+    }
+}
 
 pub fn generate_rust_code<'a, I>(
     package: &str,
     parsed_flags_iter: I,
-    codegen_mode: CodegenMode,
+    config: RustCodegenConfig,
 ) -> Result<OutputFile>
 where
     I: Iterator<Item = &'a ProtoParsedFlag>,
 {
+    let codegen_mode = config.codegen_mode;
     let template_flags: Vec<TemplateParsedFlag> =
         parsed_flags_iter.map(|pf| TemplateParsedFlag::new(package, pf)).collect();
     let has_readwrite = template_flags.iter().any(|item| item.readwrite);
     let context = TemplateContext {
         package: package.to_string(),
         template_flags,
-        modules: package.split('.').map(|s| s.to_string()).collect::<Vec<_>>(),
+        modules: codegen::package_segments(package).map(|s| s.to_string()).collect::<Vec<_>>(),
         has_readwrite,
+        read_only_consts: config.read_only_consts,
     };
     let mut template = TinyTemplate::new();
     template.add_template(
@@ -50,7 +66,8 @@ where
             }
         },
     )?;
-    let contents = template.render("rust_code_gen", &context)?;
+    let contents =
+        codegen::generated_file_header() + &template.render("rust_code_gen", &context)?;
     let path = ["src", "lib.rs"].iter().collect();
     Ok(OutputFile { contents: contents.into(), path })
 }
@@ -61,6 +78,7 @@ struct TemplateContext {
     pub template_flags: Vec<TemplateParsedFlag>,
     pub modules: Vec<String>,
     pub has_readwrite: bool,
+    pub read_only_consts: bool,
 }
 
 #[derive(Serialize)]
@@ -68,6 +86,7 @@ struct TemplateParsedFlag {
     pub readwrite: bool,
     pub default_value: String,
     pub name: String,
+    pub const_name: String,
     pub device_config_namespace: String,
     pub device_config_flag: String,
 }
@@ -76,12 +95,10 @@ impl TemplateParsedFlag {
     #[allow(clippy::nonminimal_bool)]
     fn new(package: &str, pf: &ProtoParsedFlag) -> Self {
         let template = TemplateParsedFlag {
-            readwrite: pf.permission() == ProtoFlagPermission::READ_WRITE,
-            default_value: match pf.state() {
-                ProtoFlagState::ENABLED => "true".to_string(),
-                ProtoFlagState::DISABLED => "false".to_string(),
-            },
+            readwrite: !pf.is_compile_time_constant(),
+            default_value: pf.effective_default_value().to_string(),
             name: pf.name().to_string(),
+            const_name: pf.name().to_ascii_uppercase(),
             device_config_namespace: pf.namespace().to_string(),
             device_config_flag: codegen::create_device_config_ident(package, pf.name())
                 .expect("values checked at flag parse time"),
@@ -95,6 +112,7 @@ mod tests {
     use super::*;
 
     const PROD_EXPECTED: &str = r#"
+// Generated by aconfig 0.1.0. DO NOT EDIT.
 //! codegenerated rust flag lib
 
 /// flag provider
@@ -222,6 +240,7 @@ pub fn enabled_rw() -> bool {
 "#;
 
     const TEST_EXPECTED: &str = r#"
+// Generated by aconfig 0.1.0. DO NOT EDIT.
 //! codegenerated rust flag lib
 
 use std::collections::BTreeMap;
@@ -456,9 +475,12 @@ pub fn reset_flags() {
 
     fn test_generate_rust_code(mode: CodegenMode) {
         let parsed_flags = crate::test::parse_test_flags();
-        let generated =
-            generate_rust_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), mode)
-                .unwrap();
+        let generated = generate_rust_code(
+            crate::test::TEST_PACKAGE,
+            parsed_flags.parsed_flag.iter(),
+            RustCodegenConfig::new(mode),
+        )
+        .unwrap();
         assert_eq!("src/lib.rs", format!("{}", generated.path.display()));
         assert_eq!(
             None,
@@ -474,6 +496,45 @@ pub fn reset_flags() {
         );
     }
 
+    #[test]
+    fn test_generate_rust_code_read_only_consts() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let config = RustCodegenConfig {
+            read_only_consts: true,
+            ..RustCodegenConfig::new(CodegenMode::Production)
+        };
+        let generated =
+            generate_rust_code(crate::test::TEST_PACKAGE, parsed_flags.parsed_flag.iter(), config)
+                .unwrap();
+        let contents = String::from_utf8(generated.contents).unwrap();
+        for pf in parsed_flags.parsed_flag.iter() {
+            let const_decl = format!("pub const {}: bool = ", pf.name().to_ascii_uppercase());
+            assert_eq!(
+                pf.is_compile_time_constant(),
+                contents.contains(&const_decl),
+                "{}",
+                pf.name()
+            );
+            // every flag keeps its function
+            assert!(contents.contains(&format!("pub fn {}() -> bool {{", pf.name())));
+        }
+        assert!(contents.contains(
+            r#"
+/// query flag enabled_fixed_ro
+#[inline(always)]
+pub fn enabled_fixed_ro() -> bool {
+    ENABLED_FIXED_RO
+}
+"#
+        ));
+        assert!(contents.contains(
+            r#"
+/// value of flag disabled_ro, for use in const contexts
+pub const DISABLED_RO: bool = false;
+"#
+        ));
+    }
+
     #[test]
     fn test_generate_rust_code_for_prod() {
         test_generate_rust_code(CodegenMode::Production);
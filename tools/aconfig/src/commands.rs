@@ -14,20 +14,25 @@
  * limitations under the License.
  */
 
-use anyhow::{bail, ensure, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use clap::ValueEnum;
 use protobuf::Message;
-use std::io::Read;
-use std::path::PathBuf;
-
-use crate::codegen::cpp::generate_cpp_code;
-use crate::codegen::java::generate_java_code;
-use crate::codegen::rust::generate_rust_code;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::codegen::cpp::{generate_cpp_code, CppCodegenConfig};
+use crate::codegen::java::{generate_java_code, JavaCodegenConfig};
+use crate::codegen::rust::{generate_rust_code, RustCodegenConfig};
 use crate::storage::generate_storage_files;
 
+use crate::protos::parsed_flags::{ConflictPolicy, MergePolicy};
 use crate::protos::{
-    ParsedFlagExt, ProtoFlagMetadata, ProtoFlagPermission, ProtoFlagState, ProtoParsedFlag,
-    ProtoParsedFlags, ProtoTracepoint,
+    FlagDescriptor, ParsedFlagExt, ProtoFlagDeclarations, ProtoFlagMetadata, ProtoFlagPermission,
+    ProtoFlagState, ProtoFlagSummary, ProtoPackageSummary, ProtoParsedFlag, ProtoParsedFlags,
+    ProtoTracepoint,
 };
 
 pub struct Input {
@@ -55,6 +60,182 @@ pub struct OutputFile {
     pub contents: Vec<u8>,
 }
 
+impl OutputFile {
+    /// Write the contents to `root` joined with `path`, creating directories as needed
+    pub fn write_to(&self, root: &Path) -> Result<()> {
+        let path = root.join(&self.path);
+        ensure!(!path.is_dir(), "failed to write to {}: is a directory", path.display());
+        let parent = path
+            .parent()
+            .ok_or(anyhow!("unable to locate parent of output file {}", path.display()))?;
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create directory {}", parent.display()))?;
+        fs::write(&path, &self.contents)
+            .with_context(|| format!("failed to write to {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// How much to report besides errors
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Verbosity {
+    /// Nothing: warnings are still counted (and may fail the command), but not reported
+    Quiet,
+    /// Warnings, and a summary of each generated package
+    #[default]
+    Normal,
+    /// Warnings and informational messages, e.g. which files were generated
+    Verbose,
+}
+
+/// Non-fatal issues and informational messages collected while running a command
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    pub verbosity: Verbosity,
+    pub warnings: Vec<String>,
+    pub messages: Vec<String>,
+    /// One line per generated package, reported last
+    pub summaries: Vec<String>,
+    /// How many warnings there were when the current package started
+    package_start: usize,
+}
+
+impl Diagnostics {
+    pub fn new(verbosity: Verbosity) -> Self {
+        Diagnostics { verbosity, ..Default::default() }
+    }
+
+    pub fn warn(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
+
+    pub fn info(&mut self, message: String) {
+        if self.verbosity == Verbosity::Verbose {
+            self.messages.push(message);
+        }
+    }
+
+    /// Start a new package: summarize only counts the warnings from here on
+    pub fn start_package(&mut self) {
+        self.package_start = self.warnings.len();
+    }
+
+    pub fn summarize(&mut self, package: &str, parsed_flags: &[ProtoParsedFlag]) {
+        let read_write = crate::protos::parsed_flags::rw_flag_count(parsed_flags);
+        self.summaries.push(format!(
+            "{}: {} flag(s) generated, {} read-write, {} warning(s)",
+            package,
+            parsed_flags.len(),
+            read_write,
+            self.warnings.len() - self.package_start
+        ));
+    }
+
+    pub fn report(&self, out: &mut dyn Write) -> Result<()> {
+        if self.verbosity == Verbosity::Quiet {
+            return Ok(());
+        }
+        for message in self.messages.iter() {
+            writeln!(out, "{}", message)?;
+        }
+        for warning in self.warnings.iter() {
+            writeln!(out, "warning: {}", warning)?;
+        }
+        for summary in self.summaries.iter() {
+            writeln!(out, "{}", summary)?;
+        }
+        Ok(())
+    }
+
+    pub fn check(&self, fail_on_warnings: bool) -> Result<()> {
+        ensure!(
+            !fail_on_warnings || self.warnings.is_empty(),
+            "{} warning(s) treated as errors",
+            self.warnings.len()
+        );
+        Ok(())
+    }
+}
+
+/// What to do with fields of a flag declaration this version of aconfig does not know about
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum UnknownFieldPolicy {
+    /// Fail to parse the declaration
+    #[default]
+    Strict,
+    /// Ignore the field, with a warning: lets declarations using newer fields be built with an
+    /// older aconfig during a migration
+    Lenient,
+}
+
+/// Optional checks applied while creating a cache
+#[derive(Debug, Default)]
+pub struct ParseFlagsConfig {
+    /// Fail if the package declares more flags than this
+    pub max_flags_per_package: Option<usize>,
+    /// Fail if the package declares no flags at all, which usually means the wrong declaration
+    /// files were passed
+    pub fail_on_empty_package: bool,
+    /// Warn about namespaces which look like a typo of a more commonly used namespace
+    pub warn_namespace_typos: bool,
+    /// Also reject flags whose names only differ in separators, e.g. flag_1 and flag1: they end
+    /// up with the same name in generated code which drops the separators
+    pub normalize_separators: bool,
+    /// Warn when a values file changes the state an earlier values file set (the later file
+    /// still wins)
+    pub warn_overridden_states: bool,
+    /// Warn when a values file sets a flag of this package which is not declared, e.g. because of
+    /// a typo in the flag name
+    pub warn_undeclared_values: bool,
+    /// Fail if a declared flag is not one of these fully qualified flag names
+    pub approved_flags: Option<BTreeSet<String>>,
+    /// Fail if the description of a flag is longer than this many characters
+    pub max_description_length: Option<usize>,
+    /// Fail if "<package>.<flag>" is longer than this many characters, e.g. because an external
+    /// store uses it as key
+    pub max_qualified_name_length: Option<usize>,
+    /// Warn about flags whose Java accessors only differ by case, e.g. enableFoo and enablefoo
+    pub warn_case_clashes: bool,
+    /// Warn when a values file changes the state of a read-write flag, but the explicit default
+    /// an earlier values file set no longer matches it
+    pub warn_default_mismatches: bool,
+    /// Whether fields of the declarations this version of aconfig does not know about are an
+    /// error, or only a warning
+    pub unknown_fields: UnknownFieldPolicy,
+}
+
+/// Optional checks applied while validating caches
+#[derive(Debug, Default)]
+pub struct ValidateConfig {
+    /// Fail if the Java accessor of a flag clashes with another generated member
+    pub check_java_member_names: bool,
+    /// (namespace, fully qualified flag name) -> the value the server sends: warn about
+    /// read-write flags which default to that value anyway
+    pub server_defaults: BTreeMap<(String, String), bool>,
+    /// Fail if a read-write flag uses a namespace which is not one of these
+    pub provisioned_namespaces: Option<BTreeSet<String>>,
+}
+
+/// Read a list of names, e.g. approved flags: one name per line, blank lines and lines starting
+/// with '#' are ignored
+pub fn read_name_list(mut input: Input) -> Result<BTreeSet<String>> {
+    let mut contents = String::new();
+    input
+        .reader
+        .read_to_string(&mut contents)
+        .with_context(|| format!("failed to read {}", input.source))?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+fn normalize_separators(name: &str) -> String {
+    name.replace('_', "")
+}
+
 pub const DEFAULT_FLAG_STATE: ProtoFlagState = ProtoFlagState::DISABLED;
 pub const DEFAULT_FLAG_PERMISSION: ProtoFlagPermission = ProtoFlagPermission::READ_WRITE;
 
@@ -64,6 +245,8 @@ pub fn parse_flags(
     declarations: Vec<Input>,
     values: Vec<Input>,
     default_permission: ProtoFlagPermission,
+    config: &ParseFlagsConfig,
+    diagnostics: &mut Diagnostics,
 ) -> Result<Vec<u8>> {
     let mut parsed_flags = ProtoParsedFlags::new();
 
@@ -74,8 +257,24 @@ pub fn parse_flags(
             .read_to_string(&mut contents)
             .with_context(|| format!("failed to read {}", input.source))?;
 
-        let flag_declarations = crate::protos::flag_declarations::try_from_text_proto(&contents)
-            .with_context(|| input.error_context())?;
+        let flag_declarations = match config.unknown_fields {
+            UnknownFieldPolicy::Strict => {
+                crate::protos::flag_declarations::try_from_text_proto(&contents)
+            }
+            UnknownFieldPolicy::Lenient => {
+                crate::protos::flag_declarations::try_from_text_proto_ignoring_unknown_fields(
+                    &contents,
+                )
+                .map(|(flag_declarations, unknown_fields)| {
+                    for field in unknown_fields {
+                        diagnostics
+                            .warn(format!("{}: ignoring unknown field {}", input.source, field));
+                    }
+                    flag_declarations
+                })
+            }
+        }
+        .with_context(|| input.error_context())?;
         ensure!(
             package == flag_declarations.package(),
             "failed to parse {}: expected package {}, got {}",
@@ -92,6 +291,11 @@ pub fn parse_flags(
                 flag_declarations.container()
             );
         }
+        diagnostics.info(format!(
+            "read {} flag declaration(s) from {}",
+            flag_declarations.flag.len(),
+            input.source
+        ));
         for mut flag_declaration in flag_declarations.flag.into_iter() {
             crate::protos::flag_declaration::verify_fields(&flag_declaration)
                 .with_context(|| input.error_context())?;
@@ -106,6 +310,17 @@ pub fn parse_flags(
             parsed_flag.set_namespace(flag_declaration.take_namespace());
             parsed_flag.set_description(flag_declaration.take_description());
             parsed_flag.bug.append(&mut flag_declaration.bug);
+            parsed_flag.alias.append(&mut flag_declaration.alias);
+            parsed_flag.fallback_namespace.append(&mut flag_declaration.fallback_namespace);
+            if flag_declaration.has_group() {
+                parsed_flag.set_group(flag_declaration.take_group());
+            }
+            if flag_declaration.has_since() {
+                parsed_flag.set_since(flag_declaration.since());
+            }
+            if flag_declaration.has_owner() {
+                parsed_flag.set_owner(flag_declaration.take_owner());
+            }
             parsed_flag.set_state(DEFAULT_FLAG_STATE);
             let flag_permission = if flag_declaration.is_fixed_read_only() {
                 ProtoFlagPermission::READ_ONLY
@@ -128,6 +343,29 @@ pub fn parse_flags(
 
             // verify ParsedFlag looks reasonable
             crate::protos::parsed_flag::verify_fields(&parsed_flag)?;
+            if let Some(max) = config.max_description_length {
+                let length = parsed_flag.description().chars().count();
+                ensure!(
+                    length <= max,
+                    "failed to declare flag {} from {}: description is {} characters, more than the maximum of {}",
+                    parsed_flag.name(),
+                    input.source,
+                    length,
+                    max
+                );
+            }
+            if let Some(max) = config.max_qualified_name_length {
+                let qualified_name = parsed_flag.fully_qualified_name();
+                ensure!(
+                    qualified_name.len() <= max,
+                    "failed to declare flag {} from {}: {} is {} characters, more than the maximum of {}",
+                    parsed_flag.name(),
+                    input.source,
+                    qualified_name,
+                    qualified_name.len(),
+                    max
+                );
+            }
 
             // verify ParsedFlag can be added
             ensure!(
@@ -136,12 +374,59 @@ pub fn parse_flags(
                 parsed_flag.name(),
                 input.source
             );
+            if config.normalize_separators {
+                let normalized_name = normalize_separators(parsed_flag.name());
+                if let Some(other) = parsed_flags
+                    .parsed_flag
+                    .iter()
+                    .find(|other| normalize_separators(other.name()) == normalized_name)
+                {
+                    bail!(
+                        "failed to declare flag {} from {}: flag {} already declared, and the names only differ in separators",
+                        parsed_flag.name(),
+                        input.source,
+                        other.name()
+                    );
+                }
+            }
 
             // add ParsedFlag to ParsedFlags
             parsed_flags.parsed_flag.push(parsed_flag);
         }
     }
 
+    ensure!(
+        !config.fail_on_empty_package || !parsed_flags.parsed_flag.is_empty(),
+        "package {} declares no flags",
+        package
+    );
+    if let Some(max) = config.max_flags_per_package {
+        ensure!(
+            parsed_flags.parsed_flag.len() <= max,
+            "package {} declares {} flags, more than the maximum of {}",
+            package,
+            parsed_flags.parsed_flag.len(),
+            max
+        );
+    }
+    if let Some(approved_flags) = &config.approved_flags {
+        if let Some(pf) = parsed_flags
+            .parsed_flag
+            .iter()
+            .find(|pf| !approved_flags.contains(&pf.fully_qualified_name()))
+        {
+            bail!("flag {} is not in the list of approved flags", pf.fully_qualified_name());
+        }
+    }
+    if config.warn_namespace_typos {
+        warn_about_namespace_typos(&parsed_flags, diagnostics);
+    }
+    if config.warn_case_clashes {
+        warn_about_case_clashes(&parsed_flags, diagnostics);
+    }
+
+    // flag -> the values file which last set its state, if it kept an explicit default set before
+    let mut inherited_defaults: BTreeMap<String, String> = BTreeMap::new();
     for mut input in values {
         let mut contents = String::new();
         input
@@ -159,7 +444,16 @@ pub fn parse_flags(
                 .iter_mut()
                 .find(|pf| pf.package() == flag_value.package() && pf.name() == flag_value.name())
             else {
-                // (silently) skip unknown flags
+                // skip unknown flags: values files usually contain values for many packages, but
+                // a value for an undeclared flag in this package is most likely a mistake
+                if config.warn_undeclared_values && flag_value.package() == package {
+                    diagnostics.warn(format!(
+                        "{}: ignoring value for undeclared flag {}.{}",
+                        input.source,
+                        flag_value.package(),
+                        flag_value.name()
+                    ));
+                }
                 continue;
             };
 
@@ -170,8 +464,28 @@ pub fn parse_flags(
                 flag_value.name()
             );
 
+            // the first tracepoint is the declaration, any later one an earlier values file
+            if config.warn_overridden_states
+                && parsed_flag.trace.len() > 1
+                && parsed_flag.state() != flag_value.state()
+            {
+                diagnostics.warn(format!(
+                    "{}: state of flag {} overridden: {:?} (set in {}) replaced by {:?}",
+                    input.source,
+                    parsed_flag.fully_qualified_name(),
+                    parsed_flag.state(),
+                    parsed_flag.trace.last().unwrap().source(),
+                    flag_value.state()
+                ));
+            }
             parsed_flag.set_state(flag_value.state());
             parsed_flag.set_permission(flag_value.permission());
+            if flag_value.has_default_value() {
+                parsed_flag.set_default_value(flag_value.default_value());
+                inherited_defaults.remove(&parsed_flag.fully_qualified_name());
+            } else if parsed_flag.has_default_value() {
+                inherited_defaults.insert(parsed_flag.fully_qualified_name(), input.source.clone());
+            }
             let mut tracepoint = ProtoTracepoint::new();
             tracepoint.set_source(input.source.clone());
             tracepoint.set_state(flag_value.state());
@@ -180,6 +494,36 @@ pub fn parse_flags(
         }
     }
 
+    // values files are applied in order, the last one to mention a flag wins
+    for parsed_flag in parsed_flags.parsed_flag.iter().filter(|pf| pf.trace.len() > 1) {
+        diagnostics.info(format!(
+            "flag {}: {:?}, {:?} (set in {})",
+            parsed_flag.fully_qualified_name(),
+            parsed_flag.state(),
+            parsed_flag.permission(),
+            parsed_flag.trace.last().unwrap().source()
+        ));
+    }
+
+    if config.warn_default_mismatches {
+        for (name, source) in inherited_defaults.iter() {
+            let Some(pf) = crate::protos::parsed_flags::iter_read_write(&parsed_flags)
+                .find(|pf| &pf.fully_qualified_name() == name)
+            else {
+                continue;
+            };
+            if pf.effective_default_value() != (pf.state() == ProtoFlagState::ENABLED) {
+                diagnostics.warn(format!(
+                    "{}: read-write flag {} is {:?}, but defaults to {} as set by an earlier values file",
+                    source,
+                    name,
+                    pf.state(),
+                    pf.effective_default_value()
+                ));
+            }
+        }
+    }
+
     // Create a sorted parsed_flags
     crate::protos::parsed_flags::sort_parsed_flags(&mut parsed_flags);
     crate::protos::parsed_flags::verify_fields(&parsed_flags)?;
@@ -188,6 +532,139 @@ pub fn parse_flags(
     Ok(output)
 }
 
+/// Like `parse_flags`, but for a file declaring the flags of several packages. Returns a cache
+/// per package, in the order the packages are declared.
+pub fn parse_multi_package_flags(
+    mut declarations: Input,
+    values: Vec<Input>,
+    default_permission: ProtoFlagPermission,
+    config: &ParseFlagsConfig,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut contents = String::new();
+    declarations
+        .reader
+        .read_to_string(&mut contents)
+        .with_context(|| format!("failed to read {}", declarations.source))?;
+    let declarations_set = match config.unknown_fields {
+        UnknownFieldPolicy::Strict => {
+            crate::protos::flag_declarations_set::try_from_text_proto(&contents)
+        }
+        UnknownFieldPolicy::Lenient => {
+            crate::protos::flag_declarations_set::try_from_text_proto_ignoring_unknown_fields(
+                &contents,
+            )
+            .map(|(declarations_set, unknown_fields)| {
+                for field in unknown_fields {
+                    diagnostics
+                        .warn(format!("{}: ignoring unknown field {}", declarations.source, field));
+                }
+                declarations_set
+            })
+        }
+    }
+    .with_context(|| declarations.error_context())?;
+
+    // the values are needed once per package
+    let mut values_contents = Vec::new();
+    for mut input in values {
+        let mut contents = Vec::new();
+        input
+            .reader
+            .read_to_end(&mut contents)
+            .with_context(|| format!("failed to read {}", input.source))?;
+        values_contents.push((input.source, contents));
+    }
+
+    let mut caches = Vec::new();
+    for flag_declarations in declarations_set.declarations.iter() {
+        let package = flag_declarations.package();
+        let container = flag_declarations.has_container().then(|| flag_declarations.container());
+        let declarations = vec![Input {
+            source: declarations.source.clone(),
+            reader: Box::new(std::io::Cursor::new(protobuf::text_format::print_to_string(
+                flag_declarations,
+            ))),
+        }];
+        let values = values_contents
+            .iter()
+            .map(|(source, contents)| Input {
+                source: source.clone(),
+                reader: Box::new(std::io::Cursor::new(contents.clone())),
+            })
+            .collect();
+        let cache = parse_flags(
+            package,
+            container,
+            declarations,
+            values,
+            default_permission,
+            config,
+            diagnostics,
+        )
+        .with_context(|| format!("failed to parse flags of package {}", package))?;
+        caches.push((package.to_string(), cache));
+    }
+    Ok(caches)
+}
+
+// A namespace used by a single flag, one edit away from a namespace used by several flags, is
+// most likely a typo: the flag would be looked up in the wrong DeviceConfig namespace.
+fn warn_about_namespace_typos(parsed_flags: &ProtoParsedFlags, diagnostics: &mut Diagnostics) {
+    let mut namespaces: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for pf in parsed_flags.parsed_flag.iter() {
+        namespaces.entry(pf.namespace()).or_default().push(pf.name());
+    }
+    for (namespace, flags) in namespaces.iter().filter(|(_, flags)| flags.len() == 1) {
+        let Some((common, _)) = namespaces
+            .iter()
+            .find(|(other, flags)| flags.len() > 1 && is_one_edit_away(namespace, other))
+        else {
+            continue;
+        };
+        diagnostics.warn(format!(
+            "namespace {} of flag {} looks like a typo of namespace {}",
+            namespace, flags[0], common
+        ));
+    }
+}
+
+// Java tells enableFoo and enablefoo apart, but someone reading a review most likely won't
+fn warn_about_case_clashes(parsed_flags: &ProtoParsedFlags, diagnostics: &mut Diagnostics) {
+    let mut methods: BTreeMap<String, (&str, String)> = BTreeMap::new();
+    for pf in parsed_flags.parsed_flag.iter() {
+        let method = crate::codegen::java::format_java_method_name(pf.name());
+        match methods.get(&method.to_ascii_lowercase()) {
+            Some((other, other_method)) => diagnostics.warn(format!(
+                "flags {} and {} have accessors {} and {}, which only differ by case",
+                other,
+                pf.name(),
+                other_method,
+                method
+            )),
+            None => {
+                methods.insert(method.to_ascii_lowercase(), (pf.name(), method));
+            }
+        }
+    }
+}
+
+fn is_one_edit_away(a: &str, b: &str) -> bool {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let (shorter, longer) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    if longer.len() - shorter.len() > 1 {
+        return false;
+    }
+    let prefix = shorter.iter().zip(longer.iter()).take_while(|(x, y)| x == y).count();
+    if shorter.len() == longer.len() {
+        // substitution
+        prefix < shorter.len() && shorter[prefix + 1..] == longer[prefix + 1..]
+    } else {
+        // insertion
+        shorter[prefix..] == longer[prefix + 1..]
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
 pub enum CodegenMode {
     Production,
@@ -195,31 +672,143 @@ pub enum CodegenMode {
     Exported,
 }
 
-pub fn create_java_lib(mut input: Input, codegen_mode: CodegenMode) -> Result<Vec<OutputFile>> {
+pub fn create_java_lib(
+    mut input: Input,
+    config: JavaCodegenConfig,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<OutputFile>> {
+    diagnostics.start_package();
     let parsed_flags = input.try_parse_flags()?;
-    let filtered_parsed_flags = filter_parsed_flags(parsed_flags, codegen_mode);
+    let filtered_parsed_flags = filter_parsed_flags(parsed_flags, config.codegen_mode);
     let Some(package) = find_unique_package(&filtered_parsed_flags) else {
         bail!("no parsed flags, or the parsed flags use different packages");
     };
-    generate_java_code(package, filtered_parsed_flags.iter(), codegen_mode)
+    let output = generate_java_code(package, filtered_parsed_flags.iter(), config)?;
+    diagnostics.summarize(package, &filtered_parsed_flags);
+    Ok(output)
 }
 
-pub fn create_cpp_lib(mut input: Input, codegen_mode: CodegenMode) -> Result<Vec<OutputFile>> {
+pub fn create_cpp_lib(
+    mut input: Input,
+    config: CppCodegenConfig,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<OutputFile>> {
+    diagnostics.start_package();
     let parsed_flags = input.try_parse_flags()?;
-    let filtered_parsed_flags = filter_parsed_flags(parsed_flags, codegen_mode);
+    let filtered_parsed_flags = filter_parsed_flags(parsed_flags, config.codegen_mode);
     let Some(package) = find_unique_package(&filtered_parsed_flags) else {
         bail!("no parsed flags, or the parsed flags use different packages");
     };
-    generate_cpp_code(package, filtered_parsed_flags.iter(), codegen_mode)
+    let output = generate_cpp_code(package, filtered_parsed_flags.iter(), config)?;
+    diagnostics.summarize(package, &filtered_parsed_flags);
+    Ok(output)
 }
 
-pub fn create_rust_lib(mut input: Input, codegen_mode: CodegenMode) -> Result<OutputFile> {
+pub fn create_rust_lib(
+    mut input: Input,
+    config: RustCodegenConfig,
+    diagnostics: &mut Diagnostics,
+) -> Result<OutputFile> {
+    diagnostics.start_package();
     let parsed_flags = input.try_parse_flags()?;
-    let filtered_parsed_flags = filter_parsed_flags(parsed_flags, codegen_mode);
+    let filtered_parsed_flags = filter_parsed_flags(parsed_flags, config.codegen_mode);
     let Some(package) = find_unique_package(&filtered_parsed_flags) else {
         bail!("no parsed flags, or the parsed flags use different packages");
     };
-    generate_rust_code(package, filtered_parsed_flags.iter(), codegen_mode)
+    let output = generate_rust_code(package, filtered_parsed_flags.iter(), config)?;
+    diagnostics.summarize(package, &filtered_parsed_flags);
+    Ok(output)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    Java,
+    Cpp,
+    Rust,
+}
+
+/// Generate the code of `backend` with its default configuration
+pub fn generate(
+    input: Input,
+    backend: Backend,
+    codegen_mode: CodegenMode,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<OutputFile>> {
+    match backend {
+        Backend::Java => create_java_lib(input, JavaCodegenConfig::new(codegen_mode), diagnostics),
+        Backend::Cpp => create_cpp_lib(input, CppCodegenConfig::new(codegen_mode), diagnostics),
+        Backend::Rust => {
+            Ok(vec![create_rust_lib(input, RustCodegenConfig::new(codegen_mode), diagnostics)?])
+        }
+    }
+}
+
+/// Generate the code of each of `backends` (in that order) with the default configuration, each
+/// file tagged with the backend which generated it
+pub fn generate_all(
+    mut input: Input,
+    backends: &[Backend],
+    codegen_mode: CodegenMode,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<(Backend, OutputFile)>> {
+    // every backend consumes an input of its own
+    let mut bytes = Vec::new();
+    input
+        .reader
+        .read_to_end(&mut bytes)
+        .with_context(|| format!("failed to read {}", input.source))?;
+    let mut output = Vec::new();
+    for backend in backends {
+        let input = Input {
+            source: input.source.clone(),
+            reader: Box::new(std::io::Cursor::new(bytes.clone())),
+        };
+        let generated_files = generate(input, *backend, codegen_mode, diagnostics)?;
+        output.extend(generated_files.into_iter().map(|file| (*backend, file)));
+    }
+    Ok(output)
+}
+
+/// The paths generate_all would write for each of `backends`, relative to the directory of that
+/// backend, as a JSON object keyed by backend name: for build systems which declare all outputs
+/// up front
+pub fn list_generated_files(
+    input: Input,
+    backends: &[Backend],
+    codegen_mode: CodegenMode,
+) -> Result<Vec<u8>> {
+    let generated_files =
+        generate_all(input, backends, codegen_mode, &mut Diagnostics::new(Verbosity::Quiet))?;
+    let mut paths: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (backend, file) in generated_files.iter() {
+        let name = backend.to_possible_value().unwrap().get_name().to_string();
+        paths.entry(name).or_default().push(file.path.display().to_string());
+    }
+    let mut output = serde_json::to_vec_pretty(&paths)?;
+    output.push(b'\n');
+    Ok(output)
+}
+
+/// Used when the output directory is expected to be the directory of the package itself (e.g.
+/// `out/com/android/foo` for package `com.android.foo`) rather than the root of the source
+/// tree. Returns the root of the source tree the generated files should be written to, or an
+/// error if the output directory does not match the package of the generated files.
+pub fn strip_package_dir(out: &Path, generated_files: &[OutputFile]) -> Result<PathBuf> {
+    let mut root = None;
+    for file in generated_files {
+        let package_dir = file.path.parent().unwrap_or(Path::new(""));
+        ensure!(
+            out.ends_with(package_dir),
+            "output directory {} does not match package directory {}",
+            out.display(),
+            package_dir.display()
+        );
+        let file_root = out.ancestors().nth(package_dir.components().count()).unwrap_or(out);
+        if *root.get_or_insert(file_root) != file_root {
+            bail!("generated files do not share a package directory");
+        }
+    }
+    Ok(root.unwrap_or(out).to_path_buf())
 }
 
 pub fn create_storage(caches: Vec<Input>, container: &str) -> Result<Vec<OutputFile>> {
@@ -236,11 +825,7 @@ pub fn create_storage(caches: Vec<Input>, container: &str) -> Result<Vec<OutputF
 pub fn create_device_config_defaults(mut input: Input) -> Result<Vec<u8>> {
     let parsed_flags = input.try_parse_flags()?;
     let mut output = Vec::new();
-    for parsed_flag in parsed_flags
-        .parsed_flag
-        .into_iter()
-        .filter(|pf| pf.permission() == ProtoFlagPermission::READ_WRITE)
-    {
+    for parsed_flag in crate::protos::parsed_flags::iter_read_write(&parsed_flags) {
         let line = format!(
             "{}:{}={}\n",
             parsed_flag.namespace(),
@@ -258,11 +843,7 @@ pub fn create_device_config_defaults(mut input: Input) -> Result<Vec<u8>> {
 pub fn create_device_config_sysprops(mut input: Input) -> Result<Vec<u8>> {
     let parsed_flags = input.try_parse_flags()?;
     let mut output = Vec::new();
-    for parsed_flag in parsed_flags
-        .parsed_flag
-        .into_iter()
-        .filter(|pf| pf.permission() == ProtoFlagPermission::READ_WRITE)
-    {
+    for parsed_flag in crate::protos::parsed_flags::iter_read_write(&parsed_flags) {
         let line = format!(
             "persist.device_config.{}={}\n",
             parsed_flag.fully_qualified_name(),
@@ -276,116 +857,810 @@ pub fn create_device_config_sysprops(mut input: Input) -> Result<Vec<u8>> {
     Ok(output)
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
-pub enum DumpFormat {
-    Text,
-    Verbose,
-    Protobuf,
-    Textproto,
-    Bool,
+pub fn create_read_only_golden(mut input: Input) -> Result<Vec<u8>> {
+    let parsed_flags = input.try_parse_flags()?;
+    let mut lines: Vec<String> = crate::protos::parsed_flags::iter_read_only(&parsed_flags)
+        .map(|pf| {
+            format!(
+                "{}={}\n",
+                pf.fully_qualified_name(),
+                match pf.state() {
+                    ProtoFlagState::ENABLED => "true",
+                    ProtoFlagState::DISABLED => "false",
+                }
+            )
+        })
+        .collect();
+    // the output is signed: keep it byte-for-byte stable regardless of the input order
+    lines.sort();
+    Ok(lines.concat().into_bytes())
 }
 
-pub fn dump_parsed_flags(
-    mut input: Vec<Input>,
-    format: DumpFormat,
-    dedup: bool,
-) -> Result<Vec<u8>> {
-    let individually_parsed_flags: Result<Vec<ProtoParsedFlags>> =
-        input.iter_mut().map(|i| i.try_parse_flags()).collect();
-    let parsed_flags: ProtoParsedFlags =
-        crate::protos::parsed_flags::merge(individually_parsed_flags?, dedup)?;
+pub fn create_values_template(mut input: Input) -> Result<Vec<u8>> {
+    let parsed_flags = input.try_parse_flags()?;
+    let mut output = String::new();
+    for pf in parsed_flags.parsed_flag.iter() {
+        output.push_str(&format!(
+            "flag_value {{\n    package: \"{}\"\n    name: \"{}\"\n    state: {:?}\n    permission: {:?}\n}}\n",
+            pf.package(),
+            pf.name(),
+            pf.state(),
+            pf.permission()
+        ));
+    }
+    Ok(output.into_bytes())
+}
 
+pub fn merge_caches(mut left: Input, mut right: Input, policy: ConflictPolicy) -> Result<Vec<u8>> {
+    let left = left.try_parse_flags()?;
+    let right = right.try_parse_flags()?;
+    let merged = crate::protos::parsed_flags::merge_pair(left, right, policy)?;
     let mut output = Vec::new();
-    match format {
-        DumpFormat::Text => {
-            for parsed_flag in parsed_flags.parsed_flag.into_iter() {
-                let line = format!(
-                    "{} [{}]: {:?} + {:?}\n",
-                    parsed_flag.fully_qualified_name(),
-                    parsed_flag.container(),
-                    parsed_flag.permission(),
-                    parsed_flag.state()
-                );
-                output.extend_from_slice(line.as_bytes());
-            }
-        }
-        DumpFormat::Verbose => {
-            for parsed_flag in parsed_flags.parsed_flag.into_iter() {
-                let sources: Vec<_> =
-                    parsed_flag.trace.iter().map(|tracepoint| tracepoint.source()).collect();
-                let line = format!(
-                    "{} [{}]: {:?} + {:?} ({})\n",
-                    parsed_flag.fully_qualified_name(),
-                    parsed_flag.container(),
-                    parsed_flag.permission(),
-                    parsed_flag.state(),
-                    sources.join(", ")
-                );
-                output.extend_from_slice(line.as_bytes());
-            }
-        }
-        DumpFormat::Protobuf => {
-            parsed_flags.write_to_vec(&mut output)?;
-        }
-        DumpFormat::Textproto => {
-            let s = protobuf::text_format::print_to_string_pretty(&parsed_flags);
-            output.extend_from_slice(s.as_bytes());
-        }
-        DumpFormat::Bool => {
-            for parsed_flag in parsed_flags.parsed_flag.into_iter() {
-                let line = format!(
-                    "{}={:?}\n",
-                    parsed_flag.fully_qualified_name(),
-                    parsed_flag.state() == ProtoFlagState::ENABLED
-                );
-                output.extend_from_slice(line.as_bytes());
-            }
-        }
-    }
+    merged.write_to_vec(&mut output)?;
     Ok(output)
 }
 
-fn find_unique_package(parsed_flags: &[ProtoParsedFlag]) -> Option<&str> {
-    let Some(package) = parsed_flags.first().map(|pf| pf.package()) else {
-        return None;
-    };
-    if parsed_flags.iter().any(|pf| pf.package() != package) {
-        return None;
-    }
-    Some(package)
+// e.g. to only rebuild what depends on a cache if the flags in it changed
+pub fn compare_caches(mut left: Input, mut right: Input) -> Result<()> {
+    let left_flags = left.try_parse_flags()?;
+    let right_flags = right.try_parse_flags()?;
+    ensure!(
+        crate::protos::parsed_flags::eq_ignoring_order(&left_flags, &right_flags),
+        "{} and {} have different flags",
+        left.source,
+        right.source
+    );
+    Ok(())
 }
 
-fn find_unique_container(parsed_flags: &ProtoParsedFlags) -> Option<&str> {
-    let Some(container) = parsed_flags.parsed_flag.first().map(|pf| pf.container()) else {
-        return None;
-    };
-    if parsed_flags.parsed_flag.iter().any(|pf| pf.container() != container) {
-        return None;
-    }
-    Some(container)
+pub fn set_namespace_read_only(mut input: Input, namespace: &str) -> Result<Vec<u8>> {
+    let parsed_flags = input.try_parse_flags()?;
+    let result = crate::protos::parsed_flags::with_namespace_read_only(&parsed_flags, namespace)?;
+    let mut output = Vec::new();
+    result.write_to_vec(&mut output)?;
+    Ok(output)
 }
 
-fn filter_parsed_flags(
-    parsed_flags: ProtoParsedFlags,
-    codegen_mode: CodegenMode,
-) -> Vec<ProtoParsedFlag> {
-    match codegen_mode {
-        CodegenMode::Exported => {
-            parsed_flags.parsed_flag.into_iter().filter(|pf| pf.is_exported()).collect()
-        }
-        _ => parsed_flags.parsed_flag,
+pub fn create_removed_flags_report(mut baseline: Input, mut current: Input) -> Result<Vec<u8>> {
+    let baseline = baseline.try_parse_flags()?;
+    let current = current.try_parse_flags()?;
+    let mut output = Vec::new();
+    for parsed_flag in baseline.parsed_flag.iter().filter(|baseline_flag| {
+        !current
+            .parsed_flag
+            .iter()
+            .any(|pf| pf.package() == baseline_flag.package() && pf.name() == baseline_flag.name())
+    }) {
+        let line = format!(
+            "{}: {:?} + {:?}\n",
+            parsed_flag.fully_qualified_name(),
+            parsed_flag.permission(),
+            parsed_flag.state()
+        );
+        output.extend_from_slice(line.as_bytes());
     }
+    Ok(output)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::protos::ProtoFlagPurpose;
-
-    #[test]
-    fn test_parse_flags() {
-        let parsed_flags = crate::test::parse_test_flags(); // calls parse_flags
-        crate::protos::parsed_flags::verify_fields(&parsed_flags).unwrap();
+/// Make style dependency file: the `targets` generated from `input` depend on the declaration
+/// files of its flags
+pub fn create_depfile(mut input: Input, targets: &[PathBuf]) -> Result<Vec<u8>> {
+    let parsed_flags = input.try_parse_flags()?;
+    let prerequisites: BTreeSet<&str> = parsed_flags
+        .parsed_flag
+        .iter()
+        .map(crate::protos::parsed_flag::path_to_declaration)
+        .collect();
+    let escape = |path: &str| path.replace('$', "$$").replace(' ', "\\ ").replace('#', "\\#");
+    let targets: Vec<String> =
+        targets.iter().map(|target| escape(&target.display().to_string())).collect();
+    let prerequisites: Vec<String> = prerequisites.into_iter().map(escape).collect();
+    Ok(format!("{}: {}\n", targets.join(" \\\n  "), prerequisites.join(" \\\n  ")).into_bytes())
+}
+
+pub fn create_permission_changes_report(
+    mut baseline: Input,
+    mut current: Input,
+) -> Result<Vec<u8>> {
+    let baseline = baseline.try_parse_flags()?;
+    let current = current.try_parse_flags()?;
+    let mut output = Vec::new();
+    for (name, old, new) in crate::protos::parsed_flags::diff_permissions(&baseline, &current) {
+        let line = format!("{}: {:?} -> {:?}\n", name, old, new);
+        output.extend_from_slice(line.as_bytes());
+    }
+    Ok(output)
+}
+
+// Flag ids file: one "<id> <package>.<flag>" line per flag, with " retired" appended for flags no
+// longer in the cache. Retired ids are kept so they are never handed out again.
+struct FlagId {
+    name: String,
+    retired: bool,
+}
+
+fn parse_flag_ids(mut input: Input) -> Result<BTreeMap<u32, FlagId>> {
+    let mut contents = String::new();
+    input
+        .reader
+        .read_to_string(&mut contents)
+        .with_context(|| format!("failed to read {}", input.source))?;
+    let mut ids = BTreeMap::new();
+    for (index, line) in contents.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (id, name, retired) = match fields[..] {
+            [id, name] => (id, name, false),
+            [id, name, "retired"] => (id, name, true),
+            _ => bail!("failed to parse {}: bad line {}: {}", input.source, index + 1, line),
+        };
+        let id: u32 = id.parse().with_context(|| {
+            format!("failed to parse {}: bad id on line {}: {}", input.source, index + 1, line)
+        })?;
+        ensure!(
+            ids.values().all(|other: &FlagId| other.name != name),
+            "failed to parse {}: flag {} has more than one id",
+            input.source,
+            name
+        );
+        let flag_id = FlagId { name: name.to_string(), retired };
+        ensure!(
+            ids.insert(id, flag_id).is_none(),
+            "failed to parse {}: id {} is used more than once",
+            input.source,
+            id
+        );
+    }
+    Ok(ids)
+}
+
+pub fn assign_flag_ids(mut input: Input, previous_ids: Option<Input>) -> Result<Vec<u8>> {
+    let parsed_flags = input.try_parse_flags()?;
+    let mut ids = match previous_ids {
+        Some(previous_ids) => parse_flag_ids(previous_ids)?,
+        None => BTreeMap::new(),
+    };
+    let names: BTreeSet<String> =
+        parsed_flags.parsed_flag.iter().map(|pf| pf.fully_qualified_name()).collect();
+    for flag_id in ids.values_mut() {
+        flag_id.retired = !names.contains(&flag_id.name);
+    }
+    let assigned: BTreeSet<String> = ids.values().map(|flag_id| flag_id.name.clone()).collect();
+    let next_id = ids.keys().next_back().map_or(0, |id| id + 1);
+    let new_names = names.into_iter().filter(|name| !assigned.contains(name));
+    for (id, name) in (next_id..).zip(new_names) {
+        ids.insert(id, FlagId { name, retired: false });
+    }
+    let mut output = Vec::new();
+    for (id, flag_id) in ids.iter() {
+        let line = if flag_id.retired {
+            format!("{} {} retired\n", id, flag_id.name)
+        } else {
+            format!("{} {}\n", id, flag_id.name)
+        };
+        output.extend_from_slice(line.as_bytes());
+    }
+    Ok(output)
+}
+
+pub fn list_namespaces(inputs: Vec<Input>) -> Result<Vec<u8>> {
+    let mut namespaces = BTreeSet::new();
+    for mut input in inputs {
+        let parsed_flags = input.try_parse_flags()?;
+        namespaces.extend(
+            crate::protos::parsed_flags::iter_read_write(&parsed_flags)
+                .map(|pf| pf.namespace().to_string()),
+        );
+    }
+    let mut output = Vec::new();
+    for namespace in namespaces {
+        output.extend_from_slice(namespace.as_bytes());
+        output.push(b'\n');
+    }
+    Ok(output)
+}
+
+/// For reviewers: every namespace, followed by the state and permission of each of its flags
+pub fn create_namespace_report(inputs: Vec<Input>) -> Result<Vec<u8>> {
+    let mut namespaces: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for mut input in inputs {
+        let parsed_flags = input.try_parse_flags()?;
+        for pf in parsed_flags.parsed_flag.iter() {
+            namespaces.entry(pf.namespace().to_string()).or_default().insert(format!(
+                "{} {:?} {:?}",
+                pf.fully_qualified_name(),
+                pf.state(),
+                pf.permission()
+            ));
+        }
+    }
+    let mut output = String::new();
+    for (namespace, flags) in namespaces.iter() {
+        output.push_str(&format!("{}:\n", namespace));
+        for flag in flags.iter() {
+            output.push_str(&format!("  {}\n", flag));
+        }
+    }
+    Ok(output.into_bytes())
+}
+
+// Parse server defaults in the format of create_device_config_defaults
+pub fn parse_server_defaults(mut input: Input) -> Result<BTreeMap<(String, String), bool>> {
+    let mut contents = String::new();
+    input
+        .reader
+        .read_to_string(&mut contents)
+        .with_context(|| format!("failed to read {}", input.source))?;
+    let mut defaults = BTreeMap::new();
+    for (index, line) in contents.lines().enumerate().filter(|(_, line)| !line.is_empty()) {
+        let parsed = line.split_once(':').and_then(|(namespace, rest)| {
+            let (name, value) = rest.split_once('=')?;
+            let value = match value {
+                "enabled" => true,
+                "disabled" => false,
+                _ => return None,
+            };
+            Some(((namespace.to_string(), name.to_string()), value))
+        });
+        let Some((key, value)) = parsed else {
+            bail!("failed to parse {}: bad line {}: {}", input.source, index + 1, line);
+        };
+        defaults.insert(key, value);
+    }
+    Ok(defaults)
+}
+
+// The device config key of each read-write flag, to the flag which uses it: two flags of one
+// cache with the same key would read each other's value
+fn device_config_keys_of(parsed_flags: &ProtoParsedFlags) -> Result<BTreeMap<String, String>> {
+    let mut keys: BTreeMap<String, String> = BTreeMap::new();
+    for pf in crate::protos::parsed_flags::iter_read_write(parsed_flags) {
+        let key = crate::codegen::create_device_config_ident(pf.package(), pf.name())?;
+        if let Some(other) = keys.get(&key) {
+            bail!(
+                "read-write flags {} and {} have the same device config key {}",
+                other,
+                pf.fully_qualified_name(),
+                key
+            );
+        }
+        keys.insert(key, pf.fully_qualified_name());
+    }
+    Ok(keys)
+}
+
+pub fn validate_caches(
+    inputs: Vec<Input>,
+    config: &ValidateConfig,
+    diagnostics: &mut Diagnostics,
+) -> Result<()> {
+    // device config key -> cache which uses it, across all caches
+    let mut device_config_keys: BTreeMap<String, String> = BTreeMap::new();
+    for mut input in inputs {
+        let parsed_flags = input.try_parse_flags()?;
+        let keys = device_config_keys_of(&parsed_flags).with_context(|| input.error_context())?;
+        // e.g. the same library built into two modules: only one of them can be configured
+        for (key, fully_qualified_name) in keys {
+            if let Some(other) = device_config_keys.get(&key) {
+                diagnostics.warn(format!(
+                    "{}: read-write flag {} has the same device config key {} as a flag in {}",
+                    input.source, fully_qualified_name, key, other
+                ));
+            } else {
+                device_config_keys.insert(key, input.source.clone());
+            }
+        }
+        if config.check_java_member_names {
+            crate::codegen::java::check_member_name_clashes(parsed_flags.parsed_flag.iter())
+                .with_context(|| input.error_context())?;
+        }
+        // the server can only override read-write flags in namespaces the device knows about
+        if let Some(namespaces) = &config.provisioned_namespaces {
+            if let Some(pf) = crate::protos::parsed_flags::iter_read_write(&parsed_flags)
+                .find(|pf| !namespaces.contains(pf.namespace()))
+            {
+                bail!(
+                    "{}: read-write flag {} uses namespace {}, which is not provisioned",
+                    input.source,
+                    pf.fully_qualified_name(),
+                    pf.namespace()
+                );
+            }
+        }
+        // a read-write flag which defaults to what the server sends anyway may as well be
+        // read-only
+        for pf in parsed_flags.parsed_flag.iter().filter(|pf| !pf.is_compile_time_constant()) {
+            let key = (pf.namespace().to_string(), pf.fully_qualified_name());
+            if config.server_defaults.get(&key) == Some(&pf.effective_default_value()) {
+                diagnostics.warn(format!(
+                    "{}: read-write flag {} defaults to {}, the same as the server default",
+                    input.source,
+                    pf.fully_qualified_name(),
+                    if pf.effective_default_value() { "enabled" } else { "disabled" }
+                ));
+            }
+        }
+        diagnostics.info(format!(
+            "{}: {} valid flag(s)",
+            input.source,
+            parsed_flags.parsed_flag.len()
+        ));
+    }
+    Ok(())
+}
+
+pub fn rename_package(mut input: Input, new_package: &str) -> Result<Vec<u8>> {
+    let parsed_flags = input.try_parse_flags()?;
+    let renamed = crate::protos::parsed_flags::rename_package(&parsed_flags, new_package)?;
+    let mut output = Vec::new();
+    renamed.write_to_vec(&mut output)?;
+    Ok(output)
+}
+
+pub fn rename_flag(
+    mut input: Input,
+    old_name: &str,
+    new_name: &str,
+    keep_alias: bool,
+) -> Result<Vec<u8>> {
+    let parsed_flags = input.try_parse_flags()?;
+    let renamed =
+        crate::protos::parsed_flags::rename_flag(&parsed_flags, old_name, new_name, keep_alias)?;
+    let mut output = Vec::new();
+    renamed.write_to_vec(&mut output)?;
+    Ok(output)
+}
+
+pub fn prefix_namespaces(mut input: Input, prefix: &str) -> Result<Vec<u8>> {
+    let parsed_flags = input.try_parse_flags()?;
+    let prefixed = crate::protos::parsed_flags::prefix_namespaces(&parsed_flags, prefix)?;
+    let mut output = Vec::new();
+    prefixed.write_to_vec(&mut output)?;
+    Ok(output)
+}
+
+#[derive(Serialize)]
+struct DeviceConfigKeyTarget {
+    package: String,
+    flag: String,
+    namespace: String,
+}
+
+pub fn create_device_config_key_map(mut input: Input) -> Result<Vec<u8>> {
+    let parsed_flags = input.try_parse_flags()?;
+    let mut map = BTreeMap::new();
+    for parsed_flag in crate::protos::parsed_flags::iter_read_write(&parsed_flags) {
+        let key =
+            crate::codegen::create_device_config_ident(parsed_flag.package(), parsed_flag.name())?;
+        let target = DeviceConfigKeyTarget {
+            package: parsed_flag.package().to_string(),
+            flag: parsed_flag.name().to_string(),
+            namespace: parsed_flag.namespace().to_string(),
+        };
+        ensure!(map.insert(key.clone(), target).is_none(), "duplicate device config key {}", key);
+    }
+    let mut output = serde_json::to_vec_pretty(&map)?;
+    output.push(b'\n');
+    Ok(output)
+}
+
+/// Map the fully qualified name of each flag to its owner; flags without an owner are left out
+pub fn create_owner_map(mut input: Input) -> Result<Vec<u8>> {
+    let parsed_flags = input.try_parse_flags()?;
+    let map: BTreeMap<String, &str> = parsed_flags
+        .parsed_flag
+        .iter()
+        .filter(|pf| pf.has_owner())
+        .map(|pf| (pf.fully_qualified_name(), pf.owner()))
+        .collect();
+    let mut output = serde_json::to_vec_pretty(&map)?;
+    output.push(b'\n');
+    Ok(output)
+}
+
+pub fn create_device_config_keys(mut input: Input) -> Result<Vec<u8>> {
+    let parsed_flags = input.try_parse_flags()?;
+    let mut keys = BTreeSet::new();
+    for parsed_flag in crate::protos::parsed_flags::iter_read_write(&parsed_flags) {
+        let key =
+            crate::codegen::create_device_config_ident(parsed_flag.package(), parsed_flag.name())?;
+        ensure!(keys.insert(key.clone()), "duplicate device config key {}", key);
+    }
+    let mut output = serde_json::to_vec_pretty(&keys)?;
+    output.push(b'\n');
+    Ok(output)
+}
+
+pub fn create_package_summary(mut input: Input) -> Result<Vec<u8>> {
+    let parsed_flags = input.try_parse_flags()?;
+    let Some(package) = find_unique_package(&parsed_flags.parsed_flag) else {
+        bail!("no parsed flags, or the parsed flags use different packages");
+    };
+    let mut summary = ProtoPackageSummary::new();
+    summary.set_package(package.to_string());
+    // the cache is sorted, so the flags (and hence the bytes) do not depend on the input order
+    for pf in parsed_flags.parsed_flag.iter() {
+        let mut flag = ProtoFlagSummary::new();
+        flag.set_name(pf.name().to_string());
+        flag.set_namespace(pf.namespace().to_string());
+        flag.set_state(pf.state());
+        flag.set_permission(pf.permission());
+        summary.flag.push(flag);
+    }
+    let mut output = Vec::new();
+    summary.write_to_vec(&mut output)?;
+    Ok(output)
+}
+
+// field order is the order the admin UI expects; serde keeps declaration order
+#[derive(Serialize)]
+struct AdminUiDocument {
+    package: String,
+    flags: Vec<AdminUiFlag>,
+}
+
+#[derive(Serialize)]
+struct AdminUiFlag {
+    name: String,
+    namespace: String,
+    state: String,
+    permission: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    description: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bugs: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_config_key: Option<String>,
+}
+
+pub fn create_admin_ui_json(mut input: Input) -> Result<OutputFile> {
+    let parsed_flags = input.try_parse_flags()?;
+    let Some(package) = find_unique_package(&parsed_flags.parsed_flag) else {
+        bail!("no parsed flags, or the parsed flags use different packages");
+    };
+    let mut flags = Vec::new();
+    for pf in parsed_flags.parsed_flag.iter() {
+        let device_config_key = if pf.is_compile_time_constant() {
+            None
+        } else {
+            Some(crate::codegen::create_device_config_ident(pf.package(), pf.name())?)
+        };
+        flags.push(AdminUiFlag {
+            name: pf.name().to_string(),
+            namespace: pf.namespace().to_string(),
+            state: format!("{:?}", pf.state()),
+            permission: format!("{:?}", pf.permission()),
+            description: pf.description().to_string(),
+            bugs: pf.bug.iter().filter(|bug| !bug.is_empty()).cloned().collect(),
+            device_config_key,
+        });
+    }
+    let document = AdminUiDocument { package: package.to_string(), flags };
+    let mut contents = serde_json::to_vec_pretty(&document)?;
+    contents.push(b'\n');
+    Ok(OutputFile { path: PathBuf::from(format!("{}.json", package)), contents })
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BugMapFormat {
+    Text,
+    Json,
+}
+
+pub fn create_bug_map(mut input: Input, format: BugMapFormat) -> Result<Vec<u8>> {
+    let parsed_flags = input.try_parse_flags()?;
+    // every flag has exactly one bug, but it may be the empty string
+    let map: BTreeMap<String, &str> = parsed_flags
+        .parsed_flag
+        .iter()
+        .filter_map(|pf| match pf.bug.first() {
+            Some(bug) if !bug.is_empty() => Some((pf.fully_qualified_name(), bug.as_str())),
+            _ => None,
+        })
+        .collect();
+    let mut output = Vec::new();
+    match format {
+        BugMapFormat::Text => {
+            for (flag, bug) in map {
+                output.extend_from_slice(format!("{}={}\n", flag, bug).as_bytes());
+            }
+        }
+        BugMapFormat::Json => {
+            output = serde_json::to_vec_pretty(&map)?;
+            output.push(b'\n');
+        }
+    }
+    Ok(output)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DumpFormat {
+    Text,
+    Verbose,
+    Protobuf,
+    Textproto,
+    Bool,
+    Canonical,
+    Json,
+    JsonCompact,
+    Names,
+}
+
+pub fn dump_parsed_flags(
+    mut input: Vec<Input>,
+    format: DumpFormat,
+    dedup: bool,
+    merge_policy: MergePolicy,
+) -> Result<Vec<u8>> {
+    let individually_parsed_flags: Result<Vec<ProtoParsedFlags>> =
+        input.iter_mut().map(|i| i.try_parse_flags()).collect();
+    let parsed_flags: ProtoParsedFlags =
+        crate::protos::parsed_flags::merge(individually_parsed_flags?, dedup, merge_policy)?;
+
+    let mut output = Vec::new();
+    match format {
+        DumpFormat::Text => {
+            for parsed_flag in parsed_flags.parsed_flag.into_iter() {
+                let line = format!(
+                    "{} [{}]: {:?} + {:?}\n",
+                    parsed_flag.fully_qualified_name(),
+                    parsed_flag.container(),
+                    parsed_flag.permission(),
+                    parsed_flag.state()
+                );
+                output.extend_from_slice(line.as_bytes());
+            }
+        }
+        DumpFormat::Verbose => {
+            for parsed_flag in parsed_flags.parsed_flag.into_iter() {
+                let sources: Vec<_> =
+                    parsed_flag.trace.iter().map(|tracepoint| tracepoint.source()).collect();
+                let line = format!(
+                    "{} [{}]: {:?} + {:?} ({})\n",
+                    parsed_flag.fully_qualified_name(),
+                    parsed_flag.container(),
+                    parsed_flag.permission(),
+                    parsed_flag.state(),
+                    sources.join(", ")
+                );
+                output.extend_from_slice(line.as_bytes());
+            }
+        }
+        DumpFormat::Protobuf => {
+            parsed_flags.write_to_vec(&mut output)?;
+        }
+        DumpFormat::Textproto => {
+            let s = protobuf::text_format::print_to_string_pretty(&parsed_flags);
+            output.extend_from_slice(s.as_bytes());
+        }
+        DumpFormat::Bool => {
+            for parsed_flag in parsed_flags.parsed_flag.into_iter() {
+                let line = format!(
+                    "{}={:?}\n",
+                    parsed_flag.fully_qualified_name(),
+                    parsed_flag.state() == ProtoFlagState::ENABLED
+                );
+                output.extend_from_slice(line.as_bytes());
+            }
+        }
+        DumpFormat::Names => {
+            for name in crate::protos::parsed_flags::flag_names(&parsed_flags) {
+                output.extend_from_slice(name.as_bytes());
+                output.push(b'\n');
+            }
+        }
+        DumpFormat::Canonical => {
+            for parsed_flag in parsed_flags.parsed_flag.into_iter() {
+                output.extend_from_slice(parsed_flag.canonical_string().as_bytes());
+                output.push(b'\n');
+            }
+        }
+        DumpFormat::Json | DumpFormat::JsonCompact => {
+            let descriptors: Vec<FlagDescriptor> =
+                parsed_flags.parsed_flag.iter().map(FlagDescriptor::from).collect();
+            output = if format == DumpFormat::Json {
+                serde_json::to_vec_pretty(&descriptors)?
+            } else {
+                serde_json::to_vec(&descriptors)?
+            };
+            output.push(b'\n');
+        }
+    }
+    Ok(output)
+}
+
+pub fn format_flag_declarations(mut input: Input) -> Result<Vec<u8>> {
+    let mut contents = String::new();
+    input
+        .reader
+        .read_to_string(&mut contents)
+        .with_context(|| format!("failed to read {}", input.source))?;
+    crate::protos::flag_declarations::try_from_text_proto(&contents)
+        .with_context(|| input.error_context())?;
+
+    // Split the file into top-level flag blocks and everything else. Comment lines preceding a
+    // flag block are considered part of the block, so that they travel with the flag when the
+    // blocks are sorted. Before the first flag, a blank line detaches comments from the flag.
+    let mut preamble: Vec<&str> = vec![];
+    let mut trailer: Vec<&str> = vec![];
+    let mut flags: Vec<(String, Vec<&str>)> = vec![];
+    let mut pending_comments: Vec<&str> = vec![];
+    let mut current_flag: Option<Vec<&str>> = None;
+    let mut depth = 0;
+    for line in contents.lines() {
+        let trimmed = line.trim_start();
+        if current_flag.is_none() && depth == 0 {
+            if trimmed.starts_with('#') {
+                pending_comments.push(line);
+                continue;
+            }
+            if trimmed.is_empty() {
+                // once the first flag has been seen, loose comments stay with the next flag
+                if flags.is_empty() {
+                    preamble.append(&mut pending_comments);
+                    preamble.push(line);
+                } else {
+                    pending_comments.push(line);
+                }
+                continue;
+            }
+            let is_flag = trimmed.strip_prefix("flag").is_some_and(|rest| {
+                rest.is_empty() || rest.starts_with(|ch: char| ch == '{' || ch.is_whitespace())
+            });
+            if is_flag {
+                current_flag = Some(std::mem::take(&mut pending_comments));
+            } else {
+                let other = if flags.is_empty() { &mut preamble } else { &mut trailer };
+                other.append(&mut pending_comments);
+                other.push(line);
+                depth += count_braces(line);
+                continue;
+            }
+        }
+        depth += count_braces(line);
+        if let Some(block) = current_flag.as_mut() {
+            block.push(line);
+            if depth == 0 && line.contains('}') {
+                let block = current_flag.take().unwrap();
+                let declarations: ProtoFlagDeclarations =
+                    protobuf::text_format::parse_from_str(&block.join("\n"))
+                        .with_context(|| input.error_context())?;
+                let name = declarations.flag.first().map(|fd| fd.name().to_string());
+                flags.push((name.unwrap_or_default(), block));
+            }
+        }
+    }
+    ensure!(current_flag.is_none(), "failed to format {}: unterminated flag", input.source);
+    trailer.append(&mut pending_comments);
+
+    flags.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let trim_blank_lines = |lines: &[&str]| -> Vec<String> {
+        let start = lines.iter().position(|l| !l.trim().is_empty()).unwrap_or(lines.len());
+        let end = lines.iter().rposition(|l| !l.trim().is_empty()).map_or(start, |i| i + 1);
+        lines[start..end].iter().map(|l| l.to_string()).collect()
+    };
+    let mut sections: Vec<Vec<String>> = vec![trim_blank_lines(&preamble)];
+    sections.extend(flags.iter().map(|(_, block)| trim_blank_lines(block)));
+    sections.push(trim_blank_lines(&trailer));
+    let formatted = sections
+        .into_iter()
+        .filter(|section| !section.is_empty())
+        .map(|section| section.join("\n") + "\n")
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(formatted.into_bytes())
+}
+
+// Net number of opening braces on a line of text proto, ignoring braces in strings and comments
+fn count_braces(line: &str) -> i32 {
+    let mut count = 0;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for ch in line.chars() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if ch == '\\' {
+                    escaped = true;
+                } else if ch == q {
+                    quote = None;
+                }
+            }
+            None => match ch {
+                '"' | '\'' => quote = Some(ch),
+                '#' => break,
+                '{' => count += 1,
+                '}' => count -= 1,
+                _ => {}
+            },
+        }
+    }
+    count
+}
+
+fn find_unique_package(parsed_flags: &[ProtoParsedFlag]) -> Option<&str> {
+    let Some(package) = parsed_flags.first().map(|pf| pf.package()) else {
+        return None;
+    };
+    if parsed_flags.iter().any(|pf| pf.package() != package) {
+        return None;
+    }
+    Some(package)
+}
+
+fn find_unique_container(parsed_flags: &ProtoParsedFlags) -> Option<&str> {
+    let Some(container) = parsed_flags.parsed_flag.first().map(|pf| pf.container()) else {
+        return None;
+    };
+    if parsed_flags.parsed_flag.iter().any(|pf| pf.container() != container) {
+        return None;
+    }
+    Some(container)
+}
+
+/// Leave out the flags introduced after `version`, e.g. to generate the API surface of an older
+/// SDK. Flags which do not say when they were introduced are always kept.
+pub fn filter_since_version(mut input: Input, version: u32) -> Result<Input> {
+    let mut parsed_flags = input.try_parse_flags()?;
+    parsed_flags.parsed_flag.retain(|pf| !pf.has_since() || pf.since() <= version);
+    Ok(Input {
+        source: input.source,
+        reader: Box::new(std::io::Cursor::new(parsed_flags.write_to_bytes()?)),
+    })
+}
+
+fn filter_parsed_flags(
+    parsed_flags: ProtoParsedFlags,
+    codegen_mode: CodegenMode,
+) -> Vec<ProtoParsedFlag> {
+    match codegen_mode {
+        CodegenMode::Exported => {
+            parsed_flags.parsed_flag.into_iter().filter(|pf| pf.is_exported()).collect()
+        }
+        _ => parsed_flags.parsed_flag,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protos::ProtoFlagPurpose;
+
+    #[test]
+    fn test_filter_since_version() {
+        let mut parsed_flags = crate::test::parse_test_flags();
+        for pf in parsed_flags.parsed_flag.iter_mut() {
+            match pf.name() {
+                "disabled_ro" => pf.set_since(34),
+                "enabled_ro" => pf.set_since(35),
+                _ => {}
+            }
+        }
+        let filter = |version| {
+            let input = Input {
+                source: "cache".to_string(),
+                reader: Box::new(std::io::Cursor::new(parsed_flags.write_to_bytes().unwrap())),
+            };
+            let mut input = filter_since_version(input, version).unwrap();
+            let filtered = input.try_parse_flags().unwrap();
+            filtered.parsed_flag.iter().map(|pf| pf.name().to_string()).collect::<Vec<_>>()
+        };
+
+        let names = filter(34);
+        assert_eq!(7, names.len());
+        assert!(names.contains(&"disabled_ro".to_string()));
+        assert!(!names.contains(&"enabled_ro".to_string()));
+        // flags without since are always kept
+        assert!(names.contains(&"enabled_rw".to_string()));
+        assert_eq!(8, filter(35).len());
+        assert_eq!(6, filter(33).len());
+    }
+
+    #[test]
+    fn test_parse_flags() {
+        let parsed_flags = crate::test::parse_test_flags(); // calls parse_flags
+        crate::protos::parsed_flags::verify_fields(&parsed_flags).unwrap();
 
         let enabled_ro =
             parsed_flags.parsed_flag.iter().find(|pf| pf.name() == "enabled_ro").unwrap();
@@ -408,163 +1683,1084 @@ mod tests {
         assert_eq!(ProtoFlagState::ENABLED, enabled_ro.trace[2].state());
         assert_eq!(ProtoFlagPermission::READ_ONLY, enabled_ro.trace[2].permission());
 
-        assert_eq!(8, parsed_flags.parsed_flag.len());
-        for pf in parsed_flags.parsed_flag.iter() {
-            if pf.name() == "enabled_fixed_ro" {
-                continue;
+        assert_eq!(8, parsed_flags.parsed_flag.len());
+        for pf in parsed_flags.parsed_flag.iter() {
+            if pf.name() == "enabled_fixed_ro" {
+                continue;
+            }
+            let first = pf.trace.first().unwrap();
+            assert_eq!(DEFAULT_FLAG_STATE, first.state());
+            assert_eq!(DEFAULT_FLAG_PERMISSION, first.permission());
+
+            let last = pf.trace.last().unwrap();
+            assert_eq!(pf.state(), last.state());
+            assert_eq!(pf.permission(), last.permission());
+        }
+
+        let enabled_fixed_ro =
+            parsed_flags.parsed_flag.iter().find(|pf| pf.name() == "enabled_fixed_ro").unwrap();
+        assert!(enabled_fixed_ro.is_fixed_read_only());
+        assert_eq!(ProtoFlagState::ENABLED, enabled_fixed_ro.state());
+        assert_eq!(ProtoFlagPermission::READ_ONLY, enabled_fixed_ro.permission());
+        assert_eq!(2, enabled_fixed_ro.trace.len());
+        assert_eq!(ProtoFlagPermission::READ_ONLY, enabled_fixed_ro.trace[0].permission());
+        assert_eq!(ProtoFlagPermission::READ_ONLY, enabled_fixed_ro.trace[1].permission());
+    }
+
+    #[test]
+    fn test_parse_flags_setting_default() {
+        let first_flag = r#"
+        package: "com.first"
+        flag {
+            name: "first"
+            namespace: "first_ns"
+            description: "This is the description of the first flag."
+            bug: "123"
+        }
+        "#;
+        let declaration =
+            vec![Input { source: "momery".to_string(), reader: Box::new(first_flag.as_bytes()) }];
+        let value: Vec<Input> = vec![];
+
+        let flags_bytes = crate::commands::parse_flags(
+            "com.first",
+            None,
+            declaration,
+            value,
+            ProtoFlagPermission::READ_ONLY,
+            &ParseFlagsConfig::default(),
+            &mut Diagnostics::default(),
+        )
+        .unwrap();
+        let parsed_flags =
+            crate::protos::parsed_flags::try_from_binary_proto(&flags_bytes).unwrap();
+        assert_eq!(1, parsed_flags.parsed_flag.len());
+        let parsed_flag = parsed_flags.parsed_flag.first().unwrap();
+        assert_eq!(ProtoFlagState::DISABLED, parsed_flag.state());
+        assert_eq!(ProtoFlagPermission::READ_ONLY, parsed_flag.permission());
+    }
+
+    #[test]
+    fn test_parse_flags_package_mismatch_between_declaration_and_command_line() {
+        let first_flag = r#"
+        package: "com.declaration.package"
+        container: "first.container"
+        flag {
+            name: "first"
+            namespace: "first_ns"
+            description: "This is the description of the first flag."
+            bug: "123"
+        }
+        "#;
+        let declaration =
+            vec![Input { source: "memory".to_string(), reader: Box::new(first_flag.as_bytes()) }];
+
+        let value: Vec<Input> = vec![];
+
+        let error = crate::commands::parse_flags(
+            "com.argument.package",
+            Some("first.container"),
+            declaration,
+            value,
+            ProtoFlagPermission::READ_WRITE,
+            &ParseFlagsConfig::default(),
+            &mut Diagnostics::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "failed to parse memory: expected package com.argument.package, got com.declaration.package"
+        );
+    }
+
+    #[test]
+    fn test_parse_flags_container_mismatch_between_declaration_and_command_line() {
+        let first_flag = r#"
+        package: "com.first"
+        container: "declaration.container"
+        flag {
+            name: "first"
+            namespace: "first_ns"
+            description: "This is the description of the first flag."
+            bug: "123"
+        }
+        "#;
+        let declaration =
+            vec![Input { source: "memory".to_string(), reader: Box::new(first_flag.as_bytes()) }];
+
+        let value: Vec<Input> = vec![];
+
+        let error = crate::commands::parse_flags(
+            "com.first",
+            Some("argument.container"),
+            declaration,
+            value,
+            ProtoFlagPermission::READ_WRITE,
+            &ParseFlagsConfig::default(),
+            &mut Diagnostics::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "failed to parse memory: expected container argument.container, got declaration.container"
+        );
+    }
+
+    #[test]
+    fn test_parse_flags_override_fixed_read_only() {
+        let first_flag = r#"
+        package: "com.first"
+        container: "com.first.container"
+        flag {
+            name: "first"
+            namespace: "first_ns"
+            description: "This is the description of the first flag."
+            bug: "123"
+            is_fixed_read_only: true
+        }
+        "#;
+        let declaration =
+            vec![Input { source: "memory".to_string(), reader: Box::new(first_flag.as_bytes()) }];
+
+        let first_flag_value = r#"
+        flag_value {
+            package: "com.first"
+            name: "first"
+            state: DISABLED
+            permission: READ_WRITE
+        }
+        "#;
+        let value = vec![Input {
+            source: "memory".to_string(),
+            reader: Box::new(first_flag_value.as_bytes()),
+        }];
+        let error = crate::commands::parse_flags(
+            "com.first",
+            Some("com.first.container"),
+            declaration,
+            value,
+            ProtoFlagPermission::READ_WRITE,
+            &ParseFlagsConfig::default(),
+            &mut Diagnostics::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "failed to set permission of flag first, since this flag is fixed read only flag"
+        );
+    }
+
+    #[test]
+    fn test_parse_flags_namespace_required_for_read_only_flags() {
+        // namespaces are required for all flags, including (fixed) read-only flags which do not
+        // use the namespace at runtime
+        let first_flag = r#"
+        package: "com.first"
+        flag {
+            name: "first"
+            description: "This is the description of the first flag."
+            bug: "123"
+            is_fixed_read_only: true
+        }
+        "#;
+        let declaration =
+            vec![Input { source: "memory".to_string(), reader: Box::new(first_flag.as_bytes()) }];
+        let error = crate::commands::parse_flags(
+            "com.first",
+            None,
+            declaration,
+            vec![],
+            ProtoFlagPermission::READ_ONLY,
+            &ParseFlagsConfig::default(),
+            &mut Diagnostics::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "failed to parse memory\n\nCaused by:\n    bad flag declaration: missing namespace"
+        );
+
+        let first_flag = r#"
+        package: "com.first"
+        flag {
+            name: "first"
+            namespace: "first_ns"
+            description: "This is the description of the first flag."
+            bug: "123"
+            is_fixed_read_only: true
+        }
+        "#;
+        let declaration =
+            vec![Input { source: "memory".to_string(), reader: Box::new(first_flag.as_bytes()) }];
+        assert!(crate::commands::parse_flags(
+            "com.first",
+            None,
+            declaration,
+            vec![],
+            ProtoFlagPermission::READ_ONLY,
+            &ParseFlagsConfig::default(),
+            &mut Diagnostics::default(),
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_parse_flags_layered_values() {
+        let base = r#"
+        flag_value {
+            package: "com.android.aconfig.test"
+            name: "disabled_rw"
+            state: ENABLED
+            permission: READ_WRITE
+        }
+        flag_value {
+            package: "com.android.aconfig.test"
+            name: "enabled_ro"
+            state: DISABLED
+            permission: READ_ONLY
+        }
+        "#;
+        let product = r#"
+        flag_value {
+            package: "com.android.aconfig.test"
+            name: "disabled_rw"
+            state: DISABLED
+            permission: READ_ONLY
+        }
+        "#;
+        let mut diagnostics = Diagnostics::new(Verbosity::Verbose);
+        let bytes = crate::commands::parse_flags(
+            crate::test::TEST_PACKAGE,
+            Some("system"),
+            vec![Input {
+                source: "tests/test.aconfig".to_string(),
+                reader: Box::new(include_bytes!("../tests/test.aconfig").as_slice()),
+            }],
+            vec![
+                Input { source: "base".to_string(), reader: Box::new(base.as_bytes()) },
+                Input { source: "product".to_string(), reader: Box::new(product.as_bytes()) },
+            ],
+            ProtoFlagPermission::READ_WRITE,
+            &ParseFlagsConfig::default(),
+            &mut diagnostics,
+        )
+        .unwrap();
+        let parsed_flags = crate::protos::parsed_flags::try_from_binary_proto(&bytes).unwrap();
+
+        let disabled_rw = parsed_flags.parsed_flag.iter().find(|pf| pf.name() == "disabled_rw");
+        let disabled_rw = disabled_rw.unwrap();
+        assert_eq!(ProtoFlagState::DISABLED, disabled_rw.state());
+        assert_eq!(ProtoFlagPermission::READ_ONLY, disabled_rw.permission());
+        let enabled_ro = parsed_flags.parsed_flag.iter().find(|pf| pf.name() == "enabled_ro");
+        assert_eq!(ProtoFlagState::DISABLED, enabled_ro.unwrap().state());
+
+        let messages: Vec<_> =
+            diagnostics.messages.iter().filter(|m| m.starts_with("flag ")).collect();
+        assert_eq!(
+            vec![
+                "flag com.android.aconfig.test.enabled_ro: DISABLED, READ_ONLY (set in base)",
+                "flag com.android.aconfig.test.disabled_rw: DISABLED, READ_ONLY (set in product)",
+            ],
+            messages
+        );
+    }
+
+    #[test]
+    fn test_parse_flags_warn_default_mismatches() {
+        let first_values = r#"
+        flag_value {
+            package: "com.android.aconfig.test"
+            name: "disabled_rw"
+            state: DISABLED
+            permission: READ_WRITE
+            default_value: false
+        }
+        "#;
+        let parse = |second_values: &'static str, warn_default_mismatches| {
+            let mut diagnostics = Diagnostics::default();
+            crate::commands::parse_flags(
+                crate::test::TEST_PACKAGE,
+                Some("system"),
+                vec![Input {
+                    source: "tests/test.aconfig".to_string(),
+                    reader: Box::new(include_bytes!("../tests/test.aconfig").as_slice()),
+                }],
+                vec![
+                    Input {
+                        source: "first".to_string(),
+                        reader: Box::new(first_values.as_bytes()),
+                    },
+                    Input {
+                        source: "second".to_string(),
+                        reader: Box::new(second_values.as_bytes()),
+                    },
+                ],
+                ProtoFlagPermission::READ_WRITE,
+                &ParseFlagsConfig { warn_default_mismatches, ..Default::default() },
+                &mut diagnostics,
+            )
+            .unwrap();
+            diagnostics.warnings
+        };
+
+        // enabled, but still using the default from the first values file
+        let enabled = r#"
+        flag_value {
+            package: "com.android.aconfig.test"
+            name: "disabled_rw"
+            state: ENABLED
+            permission: READ_WRITE
+        }
+        "#;
+        assert_eq!(
+            vec!["second: read-write flag com.android.aconfig.test.disabled_rw is ENABLED, but defaults to false as set by an earlier values file".to_string()],
+            parse(enabled, true)
+        );
+        assert!(parse(enabled, false).is_empty());
+
+        // the default is overridden as well
+        let enabled_with_default = r#"
+        flag_value {
+            package: "com.android.aconfig.test"
+            name: "disabled_rw"
+            state: ENABLED
+            permission: READ_WRITE
+            default_value: true
+        }
+        "#;
+        assert!(parse(enabled_with_default, true).is_empty());
+
+        // the inherited default still matches the state
+        let disabled = r#"
+        flag_value {
+            package: "com.android.aconfig.test"
+            name: "disabled_rw"
+            state: DISABLED
+            permission: READ_WRITE
+        }
+        "#;
+        assert!(parse(disabled, true).is_empty());
+    }
+
+    #[test]
+    fn test_parse_flags_explicit_default_value() {
+        let flag_values = r#"
+        flag_value {
+            package: "com.android.aconfig.test"
+            name: "disabled_rw"
+            state: DISABLED
+            permission: READ_WRITE
+            default_value: true
+        }
+        flag_value {
+            package: "com.android.aconfig.test"
+            name: "disabled_ro"
+            state: DISABLED
+            permission: READ_ONLY
+            default_value: true
+        }
+        "#;
+        let bytes = crate::commands::parse_flags(
+            crate::test::TEST_PACKAGE,
+            Some("system"),
+            vec![Input {
+                source: "tests/test.aconfig".to_string(),
+                reader: Box::new(include_bytes!("../tests/test.aconfig").as_slice()),
+            }],
+            vec![Input { source: "values".to_string(), reader: Box::new(flag_values.as_bytes()) }],
+            ProtoFlagPermission::READ_WRITE,
+            &ParseFlagsConfig::default(),
+            &mut Diagnostics::default(),
+        )
+        .unwrap();
+        let parsed_flags = crate::protos::parsed_flags::try_from_binary_proto(&bytes).unwrap();
+        let flag =
+            |name: &str| parsed_flags.parsed_flag.iter().find(|pf| pf.name() == name).unwrap();
+        assert_eq!(ProtoFlagState::DISABLED, flag("disabled_rw").state());
+        assert!(flag("disabled_rw").effective_default_value());
+        // read-only flags always use their state
+        assert!(!flag("disabled_ro").effective_default_value());
+        // no explicit default: derived from the state
+        assert!(!flag("disabled_rw_exported").effective_default_value());
+
+        let input =
+            Input { source: "cache".to_string(), reader: Box::new(std::io::Cursor::new(bytes)) };
+        let generated_files = create_java_lib(
+            input,
+            JavaCodegenConfig::new(CodegenMode::Production),
+            &mut Diagnostics::default(),
+        )
+        .unwrap();
+        let feature_flags_impl = generated_files
+            .iter()
+            .find(|file| file.path.ends_with("FeatureFlagsImpl.java"))
+            .unwrap();
+        let feature_flags_impl = std::str::from_utf8(&feature_flags_impl.contents).unwrap();
+        assert!(feature_flags_impl.contains("private static boolean disabledRw = true;"));
+        assert!(feature_flags_impl
+            .contains("properties.getBoolean(\"com.android.aconfig.test.disabled_rw\", true);"));
+    }
+
+    #[test]
+    fn test_parse_multi_package_flags() {
+        let declarations = r#"
+        declarations {
+            package: "com.first"
+            container: "system"
+            flag {
+                name: "shared_name"
+                namespace: "first_ns"
+                description: "This is the description of the first flag."
+                bug: "123"
             }
-            let first = pf.trace.first().unwrap();
-            assert_eq!(DEFAULT_FLAG_STATE, first.state());
-            assert_eq!(DEFAULT_FLAG_PERMISSION, first.permission());
+        }
+        declarations {
+            package: "com.second"
+            container: "system"
+            flag {
+                name: "shared_name"
+                namespace: "second_ns"
+                description: "This is the description of the second flag."
+                bug: "123"
+            }
+            flag {
+                name: "other"
+                namespace: "second_ns"
+                description: "This is the description of the other flag."
+                bug: "123"
+            }
+        }
+        "#;
+        let flag_values = r#"
+        flag_value {
+            package: "com.second"
+            name: "shared_name"
+            state: ENABLED
+            permission: READ_ONLY
+        }
+        "#;
+        let caches = parse_multi_package_flags(
+            Input { source: "memory".to_string(), reader: Box::new(declarations.as_bytes()) },
+            vec![Input { source: "values".to_string(), reader: Box::new(flag_values.as_bytes()) }],
+            ProtoFlagPermission::READ_WRITE,
+            &ParseFlagsConfig::default(),
+            &mut Diagnostics::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["com.first", "com.second"],
+            caches.iter().map(|(package, _)| package.as_str()).collect::<Vec<_>>()
+        );
 
-            let last = pf.trace.last().unwrap();
-            assert_eq!(pf.state(), last.state());
-            assert_eq!(pf.permission(), last.permission());
+        let first = crate::protos::parsed_flags::try_from_binary_proto(&caches[0].1).unwrap();
+        assert_eq!(1, first.parsed_flag.len());
+        assert_eq!("com.first.shared_name", first.parsed_flag[0].fully_qualified_name());
+        assert_eq!("system", first.parsed_flag[0].container());
+        assert_eq!(ProtoFlagState::DISABLED, first.parsed_flag[0].state());
+
+        let second = crate::protos::parsed_flags::try_from_binary_proto(&caches[1].1).unwrap();
+        assert_eq!(2, second.parsed_flag.len());
+        assert_eq!("com.second.other", second.parsed_flag[0].fully_qualified_name());
+        assert_eq!("com.second.shared_name", second.parsed_flag[1].fully_qualified_name());
+        assert_eq!(ProtoFlagState::ENABLED, second.parsed_flag[1].state());
+
+        // each cache generates into the directory of its own package
+        let input = Input {
+            source: "cache".to_string(),
+            reader: Box::new(std::io::Cursor::new(caches[1].1.clone())),
+        };
+        let generated_files = create_java_lib(
+            input,
+            JavaCodegenConfig::new(CodegenMode::Production),
+            &mut Diagnostics::default(),
+        )
+        .unwrap();
+        assert!(generated_files.iter().all(|file| file.path.starts_with("com/second")));
+    }
+
+    #[test]
+    fn test_parse_multi_package_flags_unknown_fields() {
+        let declarations = r#"
+        declarations {
+            package: "com.first"
+            flag {
+                name: "first"
+                namespace: "first_ns"
+                description: "This is the description of the first flag."
+                bug: "123"
+                new_field: "x"
+            }
         }
+        "#;
+        let parse = |unknown_fields| {
+            let mut diagnostics = Diagnostics::default();
+            parse_multi_package_flags(
+                Input { source: "memory".to_string(), reader: Box::new(declarations.as_bytes()) },
+                vec![],
+                ProtoFlagPermission::READ_WRITE,
+                &ParseFlagsConfig { unknown_fields, ..Default::default() },
+                &mut diagnostics,
+            )
+            .map(|caches| (caches, diagnostics.warnings))
+        };
+
+        assert!(parse(UnknownFieldPolicy::Strict).is_err());
+        let (caches, warnings) = parse(UnknownFieldPolicy::Lenient).unwrap();
+        assert_eq!(vec!["memory: ignoring unknown field declarations.flag.new_field"], warnings);
+        let first = crate::protos::parsed_flags::try_from_binary_proto(&caches[0].1).unwrap();
+        assert_eq!("com.first.first", first.parsed_flag[0].fully_qualified_name());
+    }
 
-        let enabled_fixed_ro =
-            parsed_flags.parsed_flag.iter().find(|pf| pf.name() == "enabled_fixed_ro").unwrap();
-        assert!(enabled_fixed_ro.is_fixed_read_only());
-        assert_eq!(ProtoFlagState::ENABLED, enabled_fixed_ro.state());
-        assert_eq!(ProtoFlagPermission::READ_ONLY, enabled_fixed_ro.permission());
-        assert_eq!(2, enabled_fixed_ro.trace.len());
-        assert_eq!(ProtoFlagPermission::READ_ONLY, enabled_fixed_ro.trace[0].permission());
-        assert_eq!(ProtoFlagPermission::READ_ONLY, enabled_fixed_ro.trace[1].permission());
+    #[test]
+    fn test_parse_flags_warns_about_namespace_typos() {
+        let declarations = r#"
+        package: "com.first"
+        flag {
+            name: "first"
+            namespace: "aconfig_test"
+            description: "This is the description of the first flag."
+            bug: "123"
+        }
+        flag {
+            name: "second"
+            namespace: "aconfig_test"
+            description: "This is the description of the second flag."
+            bug: "123"
+        }
+        flag {
+            name: "third"
+            namespace: "aconfig_tst"
+            description: "This is the description of the third flag."
+            bug: "123"
+        }
+        flag {
+            name: "fourth"
+            namespace: "unrelated"
+            description: "This is the description of the fourth flag."
+            bug: "123"
+        }
+        "#;
+        let parse = |warn_namespace_typos| {
+            let mut diagnostics = Diagnostics::default();
+            crate::commands::parse_flags(
+                "com.first",
+                None,
+                vec![Input {
+                    source: "memory".to_string(),
+                    reader: Box::new(declarations.as_bytes()),
+                }],
+                vec![],
+                ProtoFlagPermission::READ_WRITE,
+                &ParseFlagsConfig { warn_namespace_typos, ..Default::default() },
+                &mut diagnostics,
+            )
+            .unwrap();
+            diagnostics.warnings
+        };
+        assert_eq!(
+            vec!["namespace aconfig_tst of flag third looks like a typo of namespace aconfig_test"
+                .to_string()],
+            parse(true)
+        );
+        assert!(parse(false).is_empty());
     }
 
     #[test]
-    fn test_parse_flags_setting_default() {
-        let first_flag = r#"
+    fn test_parse_flags_unknown_fields() {
+        let declarations = r#"
         package: "com.first"
+        container: "com.first.container"
         flag {
             name: "first"
             namespace: "first_ns"
             description: "This is the description of the first flag."
             bug: "123"
+            field_from_the_future: true
         }
         "#;
-        let declaration =
-            vec![Input { source: "momery".to_string(), reader: Box::new(first_flag.as_bytes()) }];
-        let value: Vec<Input> = vec![];
+        let parse = |unknown_fields| {
+            let mut diagnostics = Diagnostics::default();
+            crate::commands::parse_flags(
+                "com.first",
+                None,
+                vec![Input {
+                    source: "memory".to_string(),
+                    reader: Box::new(declarations.as_bytes()),
+                }],
+                vec![],
+                ProtoFlagPermission::READ_WRITE,
+                &ParseFlagsConfig { unknown_fields, ..Default::default() },
+                &mut diagnostics,
+            )
+            .map(|bytes| {
+                (
+                    crate::protos::parsed_flags::try_from_binary_proto(&bytes).unwrap(),
+                    diagnostics.warnings,
+                )
+            })
+        };
+
+        let error = parse(UnknownFieldPolicy::Strict).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "failed to parse memory: 9:13: Unknown field: `field_from_the_future`"
+        );
 
-        let flags_bytes = crate::commands::parse_flags(
-            "com.first",
-            None,
-            declaration,
-            value,
-            ProtoFlagPermission::READ_ONLY,
-        )
-        .unwrap();
-        let parsed_flags =
-            crate::protos::parsed_flags::try_from_binary_proto(&flags_bytes).unwrap();
+        let (parsed_flags, warnings) = parse(UnknownFieldPolicy::Lenient).unwrap();
         assert_eq!(1, parsed_flags.parsed_flag.len());
-        let parsed_flag = parsed_flags.parsed_flag.first().unwrap();
-        assert_eq!(ProtoFlagState::DISABLED, parsed_flag.state());
-        assert_eq!(ProtoFlagPermission::READ_ONLY, parsed_flag.permission());
+        assert_eq!("first", parsed_flags.parsed_flag[0].name());
+        assert_eq!(
+            vec!["memory: ignoring unknown field flag.field_from_the_future".to_string()],
+            warnings
+        );
     }
 
     #[test]
-    fn test_parse_flags_package_mismatch_between_declaration_and_command_line() {
-        let first_flag = r#"
-        package: "com.declaration.package"
-        container: "first.container"
+    fn test_parse_flags_warn_case_clashes() {
+        let declarations = r#"
+        package: "com.first"
+        container: "com.first.container"
         flag {
-            name: "first"
+            name: "enable_foo"
             namespace: "first_ns"
             description: "This is the description of the first flag."
             bug: "123"
         }
+        flag {
+            name: "enablefoo"
+            namespace: "first_ns"
+            description: "This is the description of the second flag."
+            bug: "123"
+        }
+        flag {
+            name: "enable_bar"
+            namespace: "first_ns"
+            description: "This is the description of the third flag."
+            bug: "123"
+        }
         "#;
-        let declaration =
-            vec![Input { source: "memory".to_string(), reader: Box::new(first_flag.as_bytes()) }];
+        let parse = |warn_case_clashes| {
+            let mut diagnostics = Diagnostics::default();
+            crate::commands::parse_flags(
+                "com.first",
+                None,
+                vec![Input {
+                    source: "memory".to_string(),
+                    reader: Box::new(declarations.as_bytes()),
+                }],
+                vec![],
+                ProtoFlagPermission::READ_WRITE,
+                &ParseFlagsConfig { warn_case_clashes, ..Default::default() },
+                &mut diagnostics,
+            )
+            .unwrap();
+            diagnostics.warnings
+        };
+        assert_eq!(
+            vec!["flags enable_foo and enablefoo have accessors enableFoo and enablefoo, which only differ by case"
+                .to_string()],
+            parse(true)
+        );
+        assert!(parse(false).is_empty());
+    }
 
-        let value: Vec<Input> = vec![];
+    #[test]
+    fn test_is_one_edit_away() {
+        assert!(is_one_edit_away("aconfig_test", "aconfig_tst"));
+        assert!(is_one_edit_away("aconfig_tst", "aconfig_test"));
+        assert!(is_one_edit_away("aconfig_test", "aconfig_text"));
+        assert!(is_one_edit_away("aconfig_test", "aconfig_tests"));
+        assert!(!is_one_edit_away("aconfig_test", "aconfig_test"));
+        assert!(!is_one_edit_away("aconfig_test", "aconfig_txst_"));
+        assert!(!is_one_edit_away("aconfig_test", "aconfig"));
+    }
 
-        let error = crate::commands::parse_flags(
-            "com.argument.package",
-            Some("first.container"),
-            declaration,
-            value,
-            ProtoFlagPermission::READ_WRITE,
+    #[test]
+    fn test_parse_flags_fail_on_empty_package() {
+        let parse = |fail_on_empty_package| {
+            let declarations = r#"
+            package: "com.first"
+            container: "com.first.container"
+            "#;
+            crate::commands::parse_flags(
+                "com.first",
+                None,
+                vec![Input {
+                    source: "memory".to_string(),
+                    reader: Box::new(declarations.as_bytes()),
+                }],
+                vec![],
+                ProtoFlagPermission::READ_WRITE,
+                &ParseFlagsConfig { fail_on_empty_package, ..Default::default() },
+                &mut Diagnostics::default(),
+            )
+        };
+        let error = parse(true).unwrap_err();
+        assert_eq!(format!("{:?}", error), "package com.first declares no flags");
+
+        // an empty cache is still allowed by default, even though no code can be generated
+        let bytes = parse(false).unwrap();
+        let input =
+            Input { source: "cache".to_string(), reader: Box::new(std::io::Cursor::new(bytes)) };
+        let error = create_java_lib(
+            input,
+            JavaCodegenConfig::new(CodegenMode::Production),
+            &mut Diagnostics::default(),
         )
-        .unwrap_err();
+        .err()
+        .unwrap();
         assert_eq!(
             format!("{:?}", error),
-            "failed to parse memory: expected package com.argument.package, got com.declaration.package"
+            "no parsed flags, or the parsed flags use different packages"
         );
     }
 
     #[test]
-    fn test_parse_flags_container_mismatch_between_declaration_and_command_line() {
-        let first_flag = r#"
+    fn test_parse_flags_max_flags_per_package() {
+        let parse_with_max = |max| {
+            let declarations = vec![Input {
+                source: "tests/test.aconfig".to_string(),
+                reader: Box::new(include_bytes!("../tests/test.aconfig").as_slice()),
+            }];
+            crate::commands::parse_flags(
+                crate::test::TEST_PACKAGE,
+                Some("system"),
+                declarations,
+                vec![],
+                ProtoFlagPermission::READ_WRITE,
+                &ParseFlagsConfig { max_flags_per_package: Some(max), ..Default::default() },
+                &mut Diagnostics::default(),
+            )
+        };
+
+        assert!(parse_with_max(8).is_ok());
+        let error = parse_with_max(3).unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "package com.android.aconfig.test declares 8 flags, more than the maximum of 3"
+        );
+    }
+
+    #[test]
+    fn test_parse_flags_max_description_length() {
+        let parse_with_max = |max| {
+            let declarations = vec![Input {
+                source: "tests/test.aconfig".to_string(),
+                reader: Box::new(include_bytes!("../tests/test.aconfig").as_slice()),
+            }];
+            crate::commands::parse_flags(
+                crate::test::TEST_PACKAGE,
+                Some("system"),
+                declarations,
+                vec![],
+                ProtoFlagPermission::READ_WRITE,
+                &ParseFlagsConfig { max_description_length: Some(max), ..Default::default() },
+                &mut Diagnostics::default(),
+            )
+        };
+
+        assert!(parse_with_max(80).is_ok());
+        let error = parse_with_max(20).unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "failed to declare flag enabled_ro from tests/test.aconfig: description is 32 characters, more than the maximum of 20"
+        );
+    }
+
+    #[test]
+    fn test_parse_flags_max_qualified_name_length() {
+        let parse_with_max = |max| {
+            let declarations = vec![Input {
+                source: "tests/test.aconfig".to_string(),
+                reader: Box::new(include_bytes!("../tests/test.aconfig").as_slice()),
+            }];
+            crate::commands::parse_flags(
+                crate::test::TEST_PACKAGE,
+                Some("system"),
+                declarations,
+                vec![],
+                ProtoFlagPermission::READ_WRITE,
+                &ParseFlagsConfig { max_qualified_name_length: Some(max), ..Default::default() },
+                &mut Diagnostics::default(),
+            )
+        };
+
+        // the longest is com.android.aconfig.test.disabled_rw_in_other_namespace
+        assert!(parse_with_max(55).is_ok());
+        let error = parse_with_max(54).unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "failed to declare flag disabled_rw_in_other_namespace from tests/test.aconfig: com.android.aconfig.test.disabled_rw_in_other_namespace is 55 characters, more than the maximum of 54"
+        );
+    }
+
+    #[test]
+    fn test_parse_flags_approved_flags() {
+        let parse_with_approved = |approved: &str| {
+            let declarations = vec![Input {
+                source: "tests/test.aconfig".to_string(),
+                reader: Box::new(include_bytes!("../tests/test.aconfig").as_slice()),
+            }];
+            let approved_flags = read_name_list(Input {
+                source: "approved.txt".to_string(),
+                reader: Box::new(std::io::Cursor::new(approved.to_string())),
+            })
+            .unwrap();
+            crate::commands::parse_flags(
+                crate::test::TEST_PACKAGE,
+                Some("system"),
+                declarations,
+                vec![],
+                ProtoFlagPermission::READ_WRITE,
+                &ParseFlagsConfig { approved_flags: Some(approved_flags), ..Default::default() },
+                &mut Diagnostics::default(),
+            )
+        };
+
+        let all_flags = r#"
+        # frozen for the release
+        com.android.aconfig.test.disabled_ro
+        com.android.aconfig.test.disabled_rw
+        com.android.aconfig.test.disabled_rw_exported
+        com.android.aconfig.test.disabled_rw_in_other_namespace
+        com.android.aconfig.test.enabled_fixed_ro
+        com.android.aconfig.test.enabled_ro
+        com.android.aconfig.test.enabled_ro_exported
+        com.android.aconfig.test.enabled_rw
+        "#;
+        assert!(parse_with_approved(all_flags).is_ok());
+
+        let missing_one = all_flags.replace("com.android.aconfig.test.enabled_rw\n", "");
+        let error = parse_with_approved(&missing_one).unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "flag com.android.aconfig.test.enabled_rw is not in the list of approved flags"
+        );
+    }
+
+    #[test]
+    fn test_generation_summary() {
+        let generate = |verbosity| {
+            let mut diagnostics = Diagnostics::new(verbosity);
+            create_java_lib(
+                parse_test_flags_as_input(),
+                JavaCodegenConfig::new(CodegenMode::Production),
+                &mut diagnostics,
+            )
+            .unwrap();
+            let mut out = Vec::new();
+            diagnostics.report(&mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            "com.android.aconfig.test: 8 flag(s) generated, 4 read-write, 0 warning(s)\n",
+            generate(Verbosity::Normal)
+        );
+        assert_eq!("", generate(Verbosity::Quiet));
+    }
+
+    #[test]
+    fn test_generation_summary_counts_warnings_per_package() {
+        let parsed_flags = crate::test::parse_test_flags().parsed_flag;
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.start_package();
+        diagnostics.warn("something looks off".to_string());
+        diagnostics.summarize("com.first", &parsed_flags);
+        diagnostics.start_package();
+        diagnostics.summarize("com.second", &parsed_flags);
+        assert_eq!(
+            vec![
+                "com.first: 8 flag(s) generated, 4 read-write, 1 warning(s)".to_string(),
+                "com.second: 8 flag(s) generated, 4 read-write, 0 warning(s)".to_string(),
+            ],
+            diagnostics.summaries
+        );
+    }
+
+    #[test]
+    fn test_diagnostics_verbosity() {
+        let parse = |verbosity| {
+            let mut diagnostics = Diagnostics::new(verbosity);
+            let declarations = vec![Input {
+                source: "tests/test.aconfig".to_string(),
+                reader: Box::new(include_bytes!("../tests/test.aconfig").as_slice()),
+            }];
+            crate::commands::parse_flags(
+                crate::test::TEST_PACKAGE,
+                Some("system"),
+                declarations,
+                vec![],
+                ProtoFlagPermission::READ_WRITE,
+                &ParseFlagsConfig::default(),
+                &mut diagnostics,
+            )
+            .unwrap();
+            diagnostics.warn("something looks off".to_string());
+            let mut out = Vec::new();
+            diagnostics.report(&mut out).unwrap();
+            (diagnostics, String::from_utf8(out).unwrap())
+        };
+
+        let (diagnostics, out) = parse(Verbosity::Quiet);
+        assert_eq!("", out);
+        // warnings are still counted
+        assert!(diagnostics.check(true).is_err());
+
+        let (_, out) = parse(Verbosity::Normal);
+        assert_eq!("warning: something looks off\n", out);
+
+        let (_, out) = parse(Verbosity::Verbose);
+        assert_eq!(
+            "read 8 flag declaration(s) from tests/test.aconfig\nwarning: something looks off\n",
+            out
+        );
+    }
+
+    #[test]
+    fn test_parse_flags_warns_about_overridden_states() {
+        let parse = |warn_overridden_states| {
+            let mut diagnostics = Diagnostics::default();
+            let declarations = vec![Input {
+                source: "tests/test.aconfig".to_string(),
+                reader: Box::new(include_bytes!("../tests/test.aconfig").as_slice()),
+            }];
+            let values = vec![
+                Input {
+                    source: "tests/first.values".to_string(),
+                    reader: Box::new(include_bytes!("../tests/first.values").as_slice()),
+                },
+                Input {
+                    source: "tests/second.values".to_string(),
+                    reader: Box::new(include_bytes!("../tests/second.values").as_slice()),
+                },
+            ];
+            let bytes = crate::commands::parse_flags(
+                crate::test::TEST_PACKAGE,
+                Some("system"),
+                declarations,
+                values,
+                ProtoFlagPermission::READ_WRITE,
+                &ParseFlagsConfig { warn_overridden_states, ..Default::default() },
+                &mut diagnostics,
+            )
+            .unwrap();
+            let parsed_flags = crate::protos::parsed_flags::try_from_binary_proto(&bytes).unwrap();
+            let enabled_ro =
+                parsed_flags.parsed_flag.iter().find(|pf| pf.name() == "enabled_ro").unwrap();
+            // the override wins either way
+            assert_eq!(ProtoFlagState::ENABLED, enabled_ro.state());
+            diagnostics.warnings
+        };
+
+        assert!(parse(false).is_empty());
+        assert_eq!(
+            vec!["tests/second.values: state of flag com.android.aconfig.test.enabled_ro overridden: DISABLED (set in tests/first.values) replaced by ENABLED"],
+            parse(true)
+        );
+    }
+
+    #[test]
+    fn test_parse_flags_normalize_separators() {
+        let declarations = r#"
         package: "com.first"
-        container: "declaration.container"
         flag {
-            name: "first"
-            namespace: "first_ns"
+            name: "my_flag"
+            namespace: "aconfig_test"
             description: "This is the description of the first flag."
             bug: "123"
         }
+        flag {
+            name: "myflag"
+            namespace: "aconfig_test"
+            description: "This is the description of the second flag."
+            bug: "123"
+        }
         "#;
-        let declaration =
-            vec![Input { source: "memory".to_string(), reader: Box::new(first_flag.as_bytes()) }];
-
-        let value: Vec<Input> = vec![];
-
-        let error = crate::commands::parse_flags(
-            "com.first",
-            Some("argument.container"),
-            declaration,
-            value,
-            ProtoFlagPermission::READ_WRITE,
-        )
-        .unwrap_err();
+        let parse = |normalize_separators| {
+            crate::commands::parse_flags(
+                "com.first",
+                None,
+                vec![Input {
+                    source: "memory".to_string(),
+                    reader: Box::new(declarations.as_bytes()),
+                }],
+                vec![],
+                ProtoFlagPermission::READ_WRITE,
+                &ParseFlagsConfig { normalize_separators, ..Default::default() },
+                &mut Diagnostics::default(),
+            )
+        };
+
+        assert!(parse(false).is_ok());
+        let error = parse(true).unwrap_err();
         assert_eq!(
             format!("{:?}", error),
-            "failed to parse memory: expected container argument.container, got declaration.container"
+            "failed to declare flag myflag from memory: flag my_flag already declared, and the names only differ in separators"
         );
     }
 
     #[test]
-    fn test_parse_flags_override_fixed_read_only() {
+    fn test_parse_flags_warns_about_undeclared_flag_values() {
         let first_flag = r#"
         package: "com.first"
-        container: "com.first.container"
         flag {
             name: "first"
             namespace: "first_ns"
             description: "This is the description of the first flag."
             bug: "123"
-            is_fixed_read_only: true
         }
         "#;
-        let declaration =
-            vec![Input { source: "memory".to_string(), reader: Box::new(first_flag.as_bytes()) }];
-
-        let first_flag_value = r#"
+        let flag_values = r#"
         flag_value {
             package: "com.first"
-            name: "first"
-            state: DISABLED
-            permission: READ_WRITE
+            name: "frist"
+            state: ENABLED
+            permission: READ_ONLY
         }
-        "#;
-        let value = vec![Input {
-            source: "memory".to_string(),
-            reader: Box::new(first_flag_value.as_bytes()),
-        }];
-        let error = crate::commands::parse_flags(
-            "com.first",
-            Some("com.first.container"),
-            declaration,
-            value,
+        flag_value {
+            package: "com.second"
+            name: "second"
+            state: ENABLED
+            permission: READ_ONLY
+        }
+        "#;
+        let mut diagnostics = Diagnostics::default();
+        crate::commands::parse_flags(
+            "com.first",
+            None,
+            vec![Input { source: "memory".to_string(), reader: Box::new(first_flag.as_bytes()) }],
+            vec![Input { source: "values".to_string(), reader: Box::new(flag_values.as_bytes()) }],
             ProtoFlagPermission::READ_WRITE,
+            &ParseFlagsConfig { warn_undeclared_values: true, ..Default::default() },
+            &mut diagnostics,
         )
-        .unwrap_err();
+        .unwrap();
+        // only the value for the unknown flag in the same package is reported
         assert_eq!(
-            format!("{:?}", error),
-            "failed to set permission of flag first, since this flag is fixed read only flag"
+            vec!["values: ignoring value for undeclared flag com.first.frist".to_string()],
+            diagnostics.warnings
         );
+
+        assert!(diagnostics.check(false).is_ok());
+        let error = diagnostics.check(true).unwrap_err();
+        assert_eq!(format!("{:?}", error), "1 warning(s) treated as errors");
+
+        assert!(Diagnostics::default().check(true).is_ok());
+
+        // off by default: values files usually cover flags of many packages
+        let mut diagnostics = Diagnostics::default();
+        crate::commands::parse_flags(
+            "com.first",
+            None,
+            vec![Input { source: "memory".to_string(), reader: Box::new(first_flag.as_bytes()) }],
+            vec![Input { source: "values".to_string(), reader: Box::new(flag_values.as_bytes()) }],
+            ProtoFlagPermission::READ_WRITE,
+            &ParseFlagsConfig::default(),
+            &mut diagnostics,
+        )
+        .unwrap();
+        assert!(diagnostics.warnings.is_empty());
     }
 
     #[test]
@@ -593,6 +2789,8 @@ mod tests {
             declaration,
             value,
             ProtoFlagPermission::READ_ONLY,
+            &ParseFlagsConfig::default(),
+            &mut Diagnostics::default(),
         )
         .unwrap();
         let parsed_flags =
@@ -618,16 +2816,795 @@ mod tests {
         assert_eq!("persist.device_config.com.android.aconfig.test.disabled_rw=false\npersist.device_config.com.android.aconfig.test.disabled_rw_exported=false\npersist.device_config.com.android.aconfig.test.disabled_rw_in_other_namespace=false\npersist.device_config.com.android.aconfig.test.enabled_rw=true\n", text);
     }
 
+    #[test]
+    fn test_create_read_only_golden() {
+        let input = parse_test_flags_as_input();
+        let bytes = create_read_only_golden(input).unwrap();
+        let text = std::str::from_utf8(&bytes).unwrap();
+        assert_eq!("com.android.aconfig.test.disabled_ro=false\ncom.android.aconfig.test.enabled_fixed_ro=true\ncom.android.aconfig.test.enabled_ro=true\ncom.android.aconfig.test.enabled_ro_exported=true\n", text);
+    }
+
+    #[test]
+    fn test_create_values_template() {
+        let input = parse_test_flags_as_input();
+        let bytes = create_values_template(input).unwrap();
+        let text = std::str::from_utf8(&bytes).unwrap();
+        assert!(text.starts_with("flag_value {\n    package: \"com.android.aconfig.test\"\n    name: \"disabled_ro\"\n    state: DISABLED\n    permission: READ_ONLY\n}\n"));
+
+        // the template is a valid values file which reproduces the current state of every flag
+        let flag_values = crate::protos::flag_values::try_from_text_proto(text).unwrap();
+        let actual: Vec<_> = flag_values
+            .flag_value
+            .iter()
+            .map(|fv| (fv.name(), fv.state(), fv.permission()))
+            .collect();
+        let parsed_flags = crate::test::parse_test_flags();
+        let expected: Vec<_> = parsed_flags
+            .parsed_flag
+            .iter()
+            .map(|pf| (pf.name(), pf.state(), pf.permission()))
+            .collect();
+        assert_eq!(8, actual.len());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_generated_files_are_reproducible() {
+        let generate_all = || -> Vec<OutputFile> {
+            let mut files = create_java_lib(
+                parse_test_flags_as_input(),
+                JavaCodegenConfig::new(CodegenMode::Production),
+                &mut Diagnostics::default(),
+            )
+            .unwrap();
+            files.extend(
+                create_cpp_lib(
+                    parse_test_flags_as_input(),
+                    CppCodegenConfig::new(CodegenMode::Production),
+                    &mut Diagnostics::default(),
+                )
+                .unwrap(),
+            );
+            files.push(
+                create_rust_lib(
+                    parse_test_flags_as_input(),
+                    RustCodegenConfig::new(CodegenMode::Production),
+                    &mut Diagnostics::default(),
+                )
+                .unwrap(),
+            );
+            files
+        };
+        let header =
+            format!("// Generated by aconfig {}. DO NOT EDIT.\n", env!("CARGO_PKG_VERSION"));
+        let first = generate_all();
+        let second = generate_all();
+        assert_eq!(first.len(), second.len());
+        for (a, b) in first.iter().zip(second.iter()) {
+            assert_eq!(a.path, b.path);
+            assert!(a.contents.starts_with(header.as_bytes()), "{}: no header", a.path.display());
+            assert_eq!(a.contents, b.contents, "{}: output differs", a.path.display());
+        }
+    }
+
+    #[test]
+    fn test_create_removed_flags_report() {
+        let as_input = |parsed_flags: &ProtoParsedFlags| Input {
+            source: "test.data".to_string(),
+            reader: Box::new(std::io::Cursor::new(parsed_flags.write_to_bytes().unwrap())),
+        };
+        let mut baseline = crate::test::parse_test_flags();
+        baseline.parsed_flag.truncate(3);
+        let mut current = baseline.clone();
+        current.parsed_flag.remove(1);
+
+        let bytes = create_removed_flags_report(as_input(&baseline), as_input(&current)).unwrap();
+        let text = std::str::from_utf8(&bytes).unwrap();
+        assert_eq!("com.android.aconfig.test.disabled_rw: READ_WRITE + DISABLED\n", text);
+
+        let bytes = create_removed_flags_report(as_input(&baseline), as_input(&baseline)).unwrap();
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn test_strip_package_dir() {
+        let generated_files = create_java_lib(
+            parse_test_flags_as_input(),
+            JavaCodegenConfig::new(CodegenMode::Production),
+            &mut Diagnostics::default(),
+        )
+        .unwrap();
+
+        let root =
+            strip_package_dir(Path::new("out/java/com/android/aconfig/test"), &generated_files)
+                .unwrap();
+        assert_eq!(Path::new("out/java"), root);
+        let root =
+            strip_package_dir(Path::new("com/android/aconfig/test/"), &generated_files).unwrap();
+        assert_eq!(Path::new(""), root);
+
+        let error = strip_package_dir(Path::new("out/java/com/android/aconfig"), &generated_files)
+            .unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "output directory out/java/com/android/aconfig does not match package directory com/android/aconfig/test"
+        );
+        let error =
+            strip_package_dir(Path::new("out/java/com/android/aconfig/tests"), &generated_files)
+                .unwrap_err();
+        assert!(format!("{:?}", error)
+            .starts_with("output directory out/java/com/android/aconfig/tests does not match"));
+    }
+
+    #[test]
+    fn test_compare_caches() {
+        assert!(compare_caches(parse_test_flags_as_input(), parse_test_flags_as_input()).is_ok());
+
+        let mut parsed_flags = crate::test::parse_test_flags();
+        parsed_flags.parsed_flag[0].set_state(ProtoFlagState::ENABLED);
+        let changed = Input {
+            source: "changed.data".to_string(),
+            reader: Box::new(std::io::Cursor::new(parsed_flags.write_to_bytes().unwrap())),
+        };
+        let error = compare_caches(parse_test_flags_as_input(), changed).unwrap_err();
+        assert_eq!(format!("{:?}", error), "test.data and changed.data have different flags");
+    }
+
+    #[test]
+    fn test_rename_package() {
+        let input = parse_test_flags_as_input();
+        let bytes = rename_package(input, "com.example.renamed").unwrap();
+        let input =
+            Input { source: "renamed".to_string(), reader: Box::new(std::io::Cursor::new(bytes)) };
+        let generated_files = create_java_lib(
+            input,
+            JavaCodegenConfig::new(CodegenMode::Production),
+            &mut Diagnostics::default(),
+        )
+        .unwrap();
+        let flags = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/example/renamed/Flags.java"))
+            .unwrap();
+        let flags = std::str::from_utf8(&flags.contents).unwrap();
+        assert!(flags.contains("\npackage com.example.renamed;\n"));
+        assert!(flags.contains(
+            "public static final String FLAG_DISABLED_RW = \"com.example.renamed.disabled_rw\";"
+        ));
+        assert!(!flags.contains("com.android.aconfig.test"));
+
+        let input = parse_test_flags_as_input();
+        let error = rename_package(input, "Not a package").unwrap_err();
+        assert_eq!(format!("{:?}", error), "bad package name Not a package");
+    }
+
+    #[test]
+    fn test_rename_flag() {
+        let input = parse_test_flags_as_input();
+        let bytes = rename_flag(input, "disabled_rw", "renamed_rw", true).unwrap();
+        let parsed_flags = crate::protos::parsed_flags::try_from_binary_proto(&bytes).unwrap();
+        assert!(!parsed_flags.parsed_flag.iter().any(|pf| pf.name() == "disabled_rw"));
+        // the cache stays sorted
+        let pf = parsed_flags.parsed_flag.last().unwrap();
+        assert_eq!("renamed_rw", pf.name());
+        assert_eq!(vec!["disabled_rw".to_string()], pf.alias);
+
+        let input =
+            Input { source: "renamed".to_string(), reader: Box::new(std::io::Cursor::new(bytes)) };
+        let keys = create_device_config_keys(input).unwrap();
+        let keys = std::str::from_utf8(&keys).unwrap();
+        assert!(keys.contains("\"com.android.aconfig.test.renamed_rw\""));
+        assert!(!keys.contains("\"com.android.aconfig.test.disabled_rw\""));
+
+        let input = parse_test_flags_as_input();
+        let bytes = rename_flag(input, "disabled_rw", "renamed_rw", false).unwrap();
+        let parsed_flags = crate::protos::parsed_flags::try_from_binary_proto(&bytes).unwrap();
+        assert!(parsed_flags.parsed_flag.last().unwrap().alias.is_empty());
+
+        let input = parse_test_flags_as_input();
+        let error = rename_flag(input, "disabled_rw", "enabled_rw", true).unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "cannot rename flag disabled_rw to enabled_rw: flag com.android.aconfig.test.enabled_rw already exists"
+        );
+
+        let input = parse_test_flags_as_input();
+        let error = rename_flag(input, "no_such_flag", "renamed_rw", true).unwrap_err();
+        assert_eq!(format!("{:?}", error), "expected exactly one flag no_such_flag, found 0");
+    }
+
+    #[test]
+    fn test_prefix_namespaces() {
+        let input = parse_test_flags_as_input();
+        let bytes = prefix_namespaces(input, "tenant_a_").unwrap();
+        let parsed_flags = crate::protos::parsed_flags::try_from_binary_proto(&bytes).unwrap();
+        assert_eq!(8, parsed_flags.parsed_flag.len());
+        assert!(parsed_flags
+            .parsed_flag
+            .iter()
+            .all(|pf| pf.namespace() == "tenant_a_aconfig_test"
+                || pf.namespace() == "tenant_a_other_namespace"));
+
+        let input =
+            Input { source: "prefixed".to_string(), reader: Box::new(std::io::Cursor::new(bytes)) };
+        let generated_files = create_java_lib(
+            input,
+            JavaCodegenConfig::new(CodegenMode::Production),
+            &mut Diagnostics::default(),
+        )
+        .unwrap();
+        let feature_flags_impl = generated_files
+            .iter()
+            .find(|file| file.path == Path::new("com/android/aconfig/test/FeatureFlagsImpl.java"))
+            .unwrap();
+        let feature_flags_impl = std::str::from_utf8(&feature_flags_impl.contents).unwrap();
+        assert!(feature_flags_impl.contains(
+            "Properties properties = DeviceConfig.getProperties(\"tenant_a_aconfig_test\");"
+        ));
+        assert!(feature_flags_impl.contains(
+            "Properties properties = DeviceConfig.getProperties(\"tenant_a_other_namespace\");"
+        ));
+        // the device config keys are unchanged
+        assert!(feature_flags_impl
+            .contains("properties.getBoolean(\"com.android.aconfig.test.disabled_rw\", false);"));
+        assert!(!feature_flags_impl.contains("\"aconfig_test\""));
+
+        let input = parse_test_flags_as_input();
+        let error = prefix_namespaces(input, "tenantA_").unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "bad namespace prefix tenantA_: tenantA_aconfig_test is not a valid namespace"
+        );
+    }
+
+    #[test]
+    fn test_create_device_config_key_map() {
+        let input = parse_test_flags_as_input();
+        let bytes = create_device_config_key_map(input).unwrap();
+        let map: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let map = map.as_object().unwrap();
+        assert_eq!(
+            vec![
+                "com.android.aconfig.test.disabled_rw",
+                "com.android.aconfig.test.disabled_rw_exported",
+                "com.android.aconfig.test.disabled_rw_in_other_namespace",
+                "com.android.aconfig.test.enabled_rw",
+            ],
+            map.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            serde_json::json!({
+                "package": "com.android.aconfig.test",
+                "flag": "disabled_rw_in_other_namespace",
+                "namespace": "other_namespace",
+            }),
+            map["com.android.aconfig.test.disabled_rw_in_other_namespace"]
+        );
+        assert!(!map.contains_key("com.android.aconfig.test.enabled_ro"));
+        assert!(!map.contains_key("com.android.aconfig.test.disabled_ro"));
+    }
+
+    #[test]
+    fn test_create_owner_map() {
+        let declarations = r#"
+        package: "com.example.owners"
+        container: "system"
+        flag {
+            name: "owned"
+            namespace: "ns"
+            description: "A flag with an owner"
+            bug: "123"
+            owner: "team-networking"
+        }
+        flag {
+            name: "orphan"
+            namespace: "ns"
+            description: "A flag without an owner"
+            bug: "123"
+        }
+        flag {
+            name: "also_owned"
+            namespace: "ns"
+            description: "Another flag with an owner"
+            bug: "123"
+            owner: "team-ui"
+        }
+        "#;
+        let cache = crate::commands::parse_flags(
+            "com.example.owners",
+            Some("system"),
+            vec![Input {
+                source: "owners.aconfig".to_string(),
+                reader: Box::new(declarations.as_bytes()),
+            }],
+            vec![],
+            ProtoFlagPermission::READ_WRITE,
+            &ParseFlagsConfig::default(),
+            &mut Diagnostics::default(),
+        )
+        .unwrap();
+        let input =
+            Input { source: "cache".to_string(), reader: Box::new(std::io::Cursor::new(cache)) };
+        let bytes = create_owner_map(input).unwrap();
+        let map: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "com.example.owners.also_owned": "team-ui",
+                "com.example.owners.owned": "team-networking",
+            }),
+            map
+        );
+
+        // no owners at all
+        let bytes = create_owner_map(parse_test_flags_as_input()).unwrap();
+        assert_eq!("{}\n", std::str::from_utf8(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_output_file_write_to() {
+        let temp_dir = crate::test::TempDir::new("write-to-test");
+        let root = temp_dir.path();
+        let file = OutputFile {
+            path: PathBuf::from("com/android/aconfig/test/Flags.java"),
+            contents: b"package com.android.aconfig.test;\n".to_vec(),
+        };
+        file.write_to(root).unwrap();
+        assert_eq!(file.contents, fs::read(root.join(&file.path)).unwrap());
+
+        // overwriting an existing file is fine, a directory is not
+        file.write_to(root).unwrap();
+        let dir = OutputFile { path: PathBuf::from("com/android"), contents: vec![] };
+        let error = dir.write_to(root).unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            format!("failed to write to {}: is a directory", root.join("com/android").display())
+        );
+    }
+
+    #[test]
+    fn test_list_namespaces() {
+        let mut parsed_flags = crate::test::parse_test_flags();
+        for pf in parsed_flags.parsed_flag.iter_mut() {
+            match pf.name() {
+                "enabled_rw" => pf.set_namespace("third_namespace".to_string()),
+                "enabled_ro" => pf.set_namespace("read_only_namespace".to_string()),
+                _ => {}
+            }
+        }
+        let second = Input {
+            source: "second".to_string(),
+            reader: Box::new(std::io::Cursor::new(parsed_flags.write_to_bytes().unwrap())),
+        };
+        let bytes = list_namespaces(vec![parse_test_flags_as_input(), second]).unwrap();
+        assert_eq!(
+            "aconfig_test\nother_namespace\nthird_namespace\n",
+            std::str::from_utf8(&bytes).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_create_namespace_report() {
+        let bytes = create_namespace_report(vec![parse_test_flags_as_input()]).unwrap();
+        let expected = r#"aconfig_test:
+  com.android.aconfig.test.disabled_ro DISABLED READ_ONLY
+  com.android.aconfig.test.disabled_rw DISABLED READ_WRITE
+  com.android.aconfig.test.disabled_rw_exported DISABLED READ_WRITE
+  com.android.aconfig.test.enabled_fixed_ro ENABLED READ_ONLY
+  com.android.aconfig.test.enabled_ro ENABLED READ_ONLY
+  com.android.aconfig.test.enabled_ro_exported ENABLED READ_ONLY
+  com.android.aconfig.test.enabled_rw ENABLED READ_WRITE
+other_namespace:
+  com.android.aconfig.test.disabled_rw_in_other_namespace DISABLED READ_WRITE
+"#;
+        assert_eq!(expected, std::str::from_utf8(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_validate_caches() {
+        let mut diagnostics = Diagnostics::new(Verbosity::Verbose);
+        let config = ValidateConfig { check_java_member_names: true, ..Default::default() };
+        validate_caches(vec![parse_test_flags_as_input()], &config, &mut diagnostics).unwrap();
+        assert_eq!(vec!["test.data: 8 valid flag(s)"], diagnostics.messages);
+
+        // a cache built by hand, with the same flag twice
+        let mut parsed_flags = crate::test::parse_test_flags();
+        let duplicate = parsed_flags.parsed_flag[0].clone();
+        parsed_flags.parsed_flag.insert(0, duplicate);
+        let input = Input {
+            source: "cache".to_string(),
+            reader: Box::new(std::io::Cursor::new(parsed_flags.write_to_bytes().unwrap())),
+        };
+        let error =
+            validate_caches(vec![input], &ValidateConfig::default(), &mut diagnostics).unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "failed to parse cache\n\nCaused by:\n    bad parsed flags: duplicate flag com.android.aconfig.test.disabled_ro (defined in tests/test.aconfig and tests/test.aconfig)"
+        );
+    }
+
+    #[test]
+    fn test_validate_caches_server_defaults() {
+        let server_defaults = r#"
+aconfig_test:com.android.aconfig.test.disabled_rw=disabled
+aconfig_test:com.android.aconfig.test.enabled_rw=disabled
+other_namespace:com.android.aconfig.test.disabled_rw_exported=disabled
+aconfig_test:com.android.aconfig.test.disabled_ro=disabled
+"#;
+        let server_defaults =
+            Input { source: "server".to_string(), reader: Box::new(server_defaults.as_bytes()) };
+        let config = ValidateConfig {
+            server_defaults: parse_server_defaults(server_defaults).unwrap(),
+            ..Default::default()
+        };
+        let mut diagnostics = Diagnostics::default();
+        validate_caches(vec![parse_test_flags_as_input()], &config, &mut diagnostics).unwrap();
+        // enabled_rw has a different default, the namespace of disabled_rw_exported differs, and
+        // disabled_ro is read-only already
+        assert_eq!(
+            vec!["test.data: read-write flag com.android.aconfig.test.disabled_rw defaults to disabled, the same as the server default"],
+            diagnostics.warnings
+        );
+
+        let server_defaults =
+            Input { source: "server".to_string(), reader: Box::new("a:b.c=on".as_bytes()) };
+        let error = parse_server_defaults(server_defaults).unwrap_err();
+        assert_eq!(format!("{:?}", error), "failed to parse server: bad line 1: a:b.c=on");
+    }
+
+    #[test]
+    fn test_validate_caches_device_config_key_collisions() {
+        // the same flag name in different packages
+        let mut parsed_flags = crate::test::parse_test_flags();
+        parsed_flags.parsed_flag.retain(|pf| pf.name() == "enabled_rw");
+        let other = crate::protos::parsed_flags::rename_package(&parsed_flags, "com.example.other")
+            .unwrap();
+        let cache = |source: &str, parsed_flags: &ProtoParsedFlags| Input {
+            source: source.to_string(),
+            reader: Box::new(std::io::Cursor::new(parsed_flags.write_to_bytes().unwrap())),
+        };
+        let validate = |inputs| {
+            validate_caches(inputs, &ValidateConfig::default(), &mut Diagnostics::default())
+        };
+        assert!(validate(vec![cache("first", &parsed_flags), cache("second", &other)]).is_ok());
+
+        // the same flag in two caches, e.g. a library built into two modules
+        let mut diagnostics = Diagnostics::default();
+        validate_caches(
+            vec![cache("first", &parsed_flags), cache("second", &parsed_flags)],
+            &ValidateConfig::default(),
+            &mut diagnostics,
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["second: read-write flag com.android.aconfig.test.enabled_rw has the same device config key com.android.aconfig.test.enabled_rw as a flag in first"],
+            diagnostics.warnings
+        );
+
+        // within one cache, e.g. a cache which was not created by aconfig
+        let mut twice = parsed_flags.clone();
+        twice.parsed_flag.push(parsed_flags.parsed_flag[0].clone());
+        let error = device_config_keys_of(&twice).unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "read-write flags com.android.aconfig.test.enabled_rw and com.android.aconfig.test.enabled_rw have the same device config key com.android.aconfig.test.enabled_rw"
+        );
+
+        // read-only flags have no device config key
+        let mut read_only = parsed_flags.clone();
+        read_only.parsed_flag[0].set_permission(ProtoFlagPermission::READ_ONLY);
+        assert!(validate(vec![cache("first", &parsed_flags), cache("second", &read_only)]).is_ok());
+    }
+
+    #[test]
+    fn test_validate_caches_provisioned_namespaces() {
+        let validate = |namespaces: &'static str| {
+            let namespaces =
+                Input { source: "namespaces".to_string(), reader: Box::new(namespaces.as_bytes()) };
+            let config = ValidateConfig {
+                provisioned_namespaces: Some(read_name_list(namespaces).unwrap()),
+                ..Default::default()
+            };
+            validate_caches(vec![parse_test_flags_as_input()], &config, &mut Diagnostics::default())
+        };
+
+        assert!(validate("aconfig_test\nother_namespace\n").is_ok());
+        let error = validate("aconfig_test\n").unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "test.data: read-write flag com.android.aconfig.test.disabled_rw_in_other_namespace uses namespace other_namespace, which is not provisioned"
+        );
+    }
+
+    #[test]
+    fn test_assign_flag_ids() {
+        let ids_input = |ids: &[u8]| Input {
+            source: "ids.txt".to_string(),
+            reader: Box::new(std::io::Cursor::new(ids.to_vec())),
+        };
+        let ids = assign_flag_ids(parse_test_flags_as_input(), None).unwrap();
+        let expected = r#"0 com.android.aconfig.test.disabled_ro
+1 com.android.aconfig.test.disabled_rw
+2 com.android.aconfig.test.disabled_rw_exported
+3 com.android.aconfig.test.disabled_rw_in_other_namespace
+4 com.android.aconfig.test.enabled_fixed_ro
+5 com.android.aconfig.test.enabled_ro
+6 com.android.aconfig.test.enabled_ro_exported
+7 com.android.aconfig.test.enabled_rw
+"#;
+        assert_eq!(expected, std::str::from_utf8(&ids).unwrap());
+
+        // regenerating does not change the ids
+        let same_ids = assign_flag_ids(parse_test_flags_as_input(), Some(ids_input(&ids))).unwrap();
+        assert_eq!(ids, same_ids);
+
+        // a new flag gets the next free id, even if it sorts first; a removed flag's id is retired
+        let mut parsed_flags = crate::test::parse_test_flags();
+        parsed_flags.parsed_flag.retain(|pf| pf.name() != "enabled_rw");
+        let mut new_flag = parsed_flags.parsed_flag[0].clone();
+        new_flag.set_name("a_new_flag".to_string());
+        parsed_flags.parsed_flag.insert(0, new_flag);
+        let input = Input {
+            source: "cache".to_string(),
+            reader: Box::new(std::io::Cursor::new(parsed_flags.write_to_bytes().unwrap())),
+        };
+        let new_ids = assign_flag_ids(input, Some(ids_input(&ids))).unwrap();
+        let expected = r#"0 com.android.aconfig.test.disabled_ro
+1 com.android.aconfig.test.disabled_rw
+2 com.android.aconfig.test.disabled_rw_exported
+3 com.android.aconfig.test.disabled_rw_in_other_namespace
+4 com.android.aconfig.test.enabled_fixed_ro
+5 com.android.aconfig.test.enabled_ro
+6 com.android.aconfig.test.enabled_ro_exported
+7 com.android.aconfig.test.enabled_rw retired
+8 com.android.aconfig.test.a_new_flag
+"#;
+        assert_eq!(expected, std::str::from_utf8(&new_ids).unwrap());
+
+        // retired ids are not reused, and are revived if the flag comes back
+        let revived_ids =
+            assign_flag_ids(parse_test_flags_as_input(), Some(ids_input(&new_ids))).unwrap();
+        assert!(std::str::from_utf8(&revived_ids)
+            .unwrap()
+            .ends_with("7 com.android.aconfig.test.enabled_rw\n8 com.android.aconfig.test.a_new_flag retired\n"));
+    }
+
+    #[test]
+    fn test_assign_flag_ids_bad_ids_file() {
+        let assign = |ids: &'static str| {
+            let previous_ids =
+                Input { source: "ids.txt".to_string(), reader: Box::new(ids.as_bytes()) };
+            assign_flag_ids(parse_test_flags_as_input(), Some(previous_ids)).unwrap_err()
+        };
+        assert_eq!(
+            format!("{:?}", assign("0 com.a.b\n0 com.a.c\n")),
+            "failed to parse ids.txt: id 0 is used more than once"
+        );
+        assert_eq!(
+            format!("{:?}", assign("0 com.a.b\n1 com.a.b retired\n")),
+            "failed to parse ids.txt: flag com.a.b has more than one id"
+        );
+        assert_eq!(
+            format!("{:?}", assign("0 com.a.b deleted\n")),
+            "failed to parse ids.txt: bad line 1: 0 com.a.b deleted"
+        );
+    }
+
+    #[test]
+    fn test_create_device_config_keys() {
+        let input = parse_test_flags_as_input();
+        let bytes = create_device_config_keys(input).unwrap();
+        let keys: Vec<String> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            vec![
+                "com.android.aconfig.test.disabled_rw",
+                "com.android.aconfig.test.disabled_rw_exported",
+                "com.android.aconfig.test.disabled_rw_in_other_namespace",
+                "com.android.aconfig.test.enabled_rw",
+            ],
+            keys
+        );
+    }
+
+    #[test]
+    fn test_create_package_summary() {
+        let bytes = create_package_summary(parse_test_flags_as_input()).unwrap();
+        let summary = ProtoPackageSummary::parse_from_bytes(&bytes).unwrap();
+        assert_eq!("com.android.aconfig.test", summary.package());
+
+        // the summary holds the same flags as the cache, minus the fields it does not cover
+        let parsed_flags = crate::test::parse_test_flags();
+        let expected: Vec<_> = parsed_flags
+            .parsed_flag
+            .iter()
+            .map(|pf| (pf.name(), pf.namespace(), pf.state(), pf.permission()))
+            .collect();
+        let actual: Vec<_> = summary
+            .flag
+            .iter()
+            .map(|flag| (flag.name(), flag.namespace(), flag.state(), flag.permission()))
+            .collect();
+        assert_eq!(expected, actual);
+
+        // deterministic: the same flags, declared in the reverse order, give the same bytes
+        let mut declarations = crate::protos::flag_declarations::try_from_text_proto(include_str!(
+            "../tests/test.aconfig"
+        ))
+        .unwrap();
+        declarations.flag.reverse();
+        let cache = parse_flags(
+            "com.android.aconfig.test",
+            Some("system"),
+            vec![Input {
+                source: "tests/test.aconfig".to_string(),
+                reader: Box::new(std::io::Cursor::new(protobuf::text_format::print_to_string(
+                    &declarations,
+                ))),
+            }],
+            vec![
+                Input {
+                    source: "tests/first.values".to_string(),
+                    reader: Box::new(include_bytes!("../tests/first.values").as_slice()),
+                },
+                Input {
+                    source: "tests/second.values".to_string(),
+                    reader: Box::new(include_bytes!("../tests/second.values").as_slice()),
+                },
+            ],
+            DEFAULT_FLAG_PERMISSION,
+            &ParseFlagsConfig::default(),
+            &mut Diagnostics::default(),
+        )
+        .unwrap();
+        let input =
+            Input { source: "reversed".to_string(), reader: Box::new(std::io::Cursor::new(cache)) };
+        assert_eq!(bytes, create_package_summary(input).unwrap());
+        assert_eq!(bytes, summary.write_to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_create_admin_ui_json() {
+        let input = parse_test_flags_as_input();
+        let file = create_admin_ui_json(input).unwrap();
+        assert_eq!(PathBuf::from("com.android.aconfig.test.json"), file.path);
+        let document: serde_json::Value = serde_json::from_slice(&file.contents).unwrap();
+        assert_eq!("com.android.aconfig.test", document["package"]);
+        let flags = document["flags"].as_array().unwrap();
+        assert_eq!(8, flags.len());
+
+        let disabled_rw = flags.iter().find(|f| f["name"] == "disabled_rw").unwrap();
+        assert!(std::str::from_utf8(&file.contents).unwrap().contains(
+            r#"
+    {
+      "name": "disabled_rw",
+      "namespace": "aconfig_test",
+      "state": "DISABLED",
+      "permission": "READ_WRITE",
+      "description": "This flag is DISABLED + READ_WRITE",
+      "bugs": [
+        "456"
+      ],
+      "device_config_key": "com.android.aconfig.test.disabled_rw"
+    }"#
+        ));
+        assert_eq!("aconfig_test", disabled_rw["namespace"]);
+        assert_eq!("DISABLED", disabled_rw["state"]);
+        assert_eq!("READ_WRITE", disabled_rw["permission"]);
+        assert_eq!("com.android.aconfig.test.disabled_rw", disabled_rw["device_config_key"]);
+
+        // read-only flags are not backed by device config
+        let enabled_ro = flags.iter().find(|f| f["name"] == "enabled_ro").unwrap();
+        assert_eq!("READ_ONLY", enabled_ro["permission"]);
+        assert!(enabled_ro.get("device_config_key").is_none());
+    }
+
+    #[test]
+    fn test_create_depfile() {
+        let generated_files = create_java_lib(
+            parse_test_flags_as_input(),
+            JavaCodegenConfig::new(CodegenMode::Production),
+            &mut Diagnostics::default(),
+        )
+        .unwrap();
+        let targets: Vec<PathBuf> =
+            generated_files.iter().map(|file| Path::new("out").join(&file.path)).collect();
+        let depfile = create_depfile(parse_test_flags_as_input(), &targets).unwrap();
+        assert_eq!(
+            "out/com/android/aconfig/test/Flags.java \\
+  out/com/android/aconfig/test/FeatureFlags.java \\
+  out/com/android/aconfig/test/FeatureFlagsImpl.java \\
+  out/com/android/aconfig/test/FakeFeatureFlagsImpl.java: tests/test.aconfig
+",
+            std::str::from_utf8(&depfile).unwrap()
+        );
+
+        let depfile =
+            create_depfile(parse_test_flags_as_input(), &[PathBuf::from("my out.zip")]).unwrap();
+        assert_eq!("my\\ out.zip: tests/test.aconfig\n", std::str::from_utf8(&depfile).unwrap());
+    }
+
+    #[test]
+    fn test_create_bug_map() {
+        let text = create_bug_map(parse_test_flags_as_input(), BugMapFormat::Text).unwrap();
+        assert_eq!(
+            "com.android.aconfig.test.disabled_ro=123
+com.android.aconfig.test.disabled_rw=456
+com.android.aconfig.test.disabled_rw_exported=111
+com.android.aconfig.test.disabled_rw_in_other_namespace=999
+com.android.aconfig.test.enabled_ro=abc
+com.android.aconfig.test.enabled_ro_exported=111
+",
+            std::str::from_utf8(&text).unwrap()
+        );
+
+        // flags with an empty bug are left out
+        let json = create_bug_map(parse_test_flags_as_input(), BugMapFormat::Json).unwrap();
+        let map: BTreeMap<String, String> = serde_json::from_slice(&json).unwrap();
+        assert_eq!(6, map.len());
+        assert!(!map.contains_key("com.android.aconfig.test.enabled_fixed_ro"));
+        assert!(!map.contains_key("com.android.aconfig.test.enabled_rw"));
+    }
+
     #[test]
     fn test_dump_text_format() {
         let input = parse_test_flags_as_input();
-        let bytes = dump_parsed_flags(vec![input], DumpFormat::Text, false).unwrap();
+        let bytes =
+            dump_parsed_flags(vec![input], DumpFormat::Text, false, MergePolicy::Strict).unwrap();
         let text = std::str::from_utf8(&bytes).unwrap();
         assert!(
             text.contains("com.android.aconfig.test.disabled_ro [system]: READ_ONLY + DISABLED")
         );
     }
 
+    #[test]
+    fn test_dump_names_format() {
+        let input = parse_test_flags_as_input();
+        let bytes =
+            dump_parsed_flags(vec![input], DumpFormat::Names, false, MergePolicy::Strict).unwrap();
+        let text = std::str::from_utf8(&bytes).unwrap();
+        assert_eq!(8, text.lines().count());
+        assert!(text.starts_with("disabled_ro\ndisabled_rw\n"));
+        assert!(text.ends_with("enabled_ro_exported\nenabled_rw\n"));
+    }
+
+    #[test]
+    fn test_dump_canonical_format() {
+        let input = parse_test_flags_as_input();
+        let bytes =
+            dump_parsed_flags(vec![input], DumpFormat::Canonical, false, MergePolicy::Strict)
+                .unwrap();
+        let text = std::str::from_utf8(&bytes).unwrap();
+        assert_eq!(8, text.lines().count());
+        assert!(text.contains("package=com.android.aconfig.test name=disabled_rw_in_other_namespace state=disabled permission=read_write namespace=other_namespace\n"));
+    }
+
+    #[test]
+    fn test_dump_json_format() {
+        let input = parse_test_flags_as_input();
+        let bytes =
+            dump_parsed_flags(vec![input], DumpFormat::Json, false, MergePolicy::Strict).unwrap();
+        let descriptors: Vec<serde_json::Value> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(8, descriptors.len());
+        assert_eq!("disabled_ro", descriptors[0]["name"]);
+        assert_eq!(false, descriptors[0]["enabled"]);
+        assert_eq!(true, descriptors[0]["read_only"]);
+    }
+
+    #[test]
+    fn test_dump_json_compact_format() {
+        let dump = |format| {
+            let input = parse_test_flags_as_input();
+            dump_parsed_flags(vec![input], format, false, MergePolicy::Strict).unwrap()
+        };
+        let pretty = dump(DumpFormat::Json);
+        let compact = dump(DumpFormat::JsonCompact);
+        assert_eq!(1, std::str::from_utf8(&compact).unwrap().lines().count());
+        assert!(compact.len() < pretty.len());
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&pretty).unwrap(),
+            serde_json::from_slice::<serde_json::Value>(&compact).unwrap()
+        );
+    }
+
     #[test]
     fn test_dump_protobuf_format() {
         let expected = protobuf::text_format::parse_from_str::<ProtoParsedFlags>(
@@ -638,7 +3615,9 @@ mod tests {
         .unwrap();
 
         let input = parse_test_flags_as_input();
-        let actual = dump_parsed_flags(vec![input], DumpFormat::Protobuf, false).unwrap();
+        let actual =
+            dump_parsed_flags(vec![input], DumpFormat::Protobuf, false, MergePolicy::Strict)
+                .unwrap();
 
         assert_eq!(expected, actual);
     }
@@ -646,7 +3625,9 @@ mod tests {
     #[test]
     fn test_dump_textproto_format() {
         let input = parse_test_flags_as_input();
-        let bytes = dump_parsed_flags(vec![input], DumpFormat::Textproto, false).unwrap();
+        let bytes =
+            dump_parsed_flags(vec![input], DumpFormat::Textproto, false, MergePolicy::Strict)
+                .unwrap();
         let text = std::str::from_utf8(&bytes).unwrap();
         assert_eq!(crate::test::TEST_FLAGS_TEXTPROTO.trim(), text.trim());
     }
@@ -655,7 +3636,13 @@ mod tests {
     fn test_dump_textproto_format_dedup() {
         let input = parse_test_flags_as_input();
         let input2 = parse_test_flags_as_input();
-        let bytes = dump_parsed_flags(vec![input, input2], DumpFormat::Textproto, true).unwrap();
+        let bytes = dump_parsed_flags(
+            vec![input, input2],
+            DumpFormat::Textproto,
+            true,
+            MergePolicy::Strict,
+        )
+        .unwrap();
         let text = std::str::from_utf8(&bytes).unwrap();
         assert_eq!(crate::test::TEST_FLAGS_TEXTPROTO.trim(), text.trim());
     }
@@ -677,6 +3664,142 @@ mod tests {
         assert_eq!(8, filtered_parsed_flags.len());
     }
 
+    #[test]
+    fn test_generate_all() {
+        let generate = |backends: &[Backend]| {
+            generate_all(
+                parse_test_flags_as_input(),
+                backends,
+                CodegenMode::Production,
+                &mut Diagnostics::default(),
+            )
+            .unwrap()
+        };
+
+        let generated_files = generate(&[Backend::Java]);
+        assert_eq!(4, generated_files.len());
+        assert!(generated_files.iter().all(|(backend, _)| *backend == Backend::Java));
+        assert!(generated_files
+            .iter()
+            .any(|(_, file)| file.path == Path::new("com/android/aconfig/test/Flags.java")));
+
+        let generated_files = generate(&[Backend::Rust, Backend::Cpp]);
+        let backends: Vec<Backend> = generated_files.iter().map(|(backend, _)| *backend).collect();
+        assert_eq!(vec![Backend::Rust, Backend::Cpp, Backend::Cpp], backends);
+        assert_eq!(Path::new("src/lib.rs"), generated_files[0].1.path);
+
+        assert!(generate(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_list_generated_files() {
+        let bytes = list_generated_files(
+            parse_test_flags_as_input(),
+            &[Backend::Java],
+            CodegenMode::Production,
+        )
+        .unwrap();
+        let paths: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "java": [
+                    "com/android/aconfig/test/Flags.java",
+                    "com/android/aconfig/test/FeatureFlags.java",
+                    "com/android/aconfig/test/FeatureFlagsImpl.java",
+                    "com/android/aconfig/test/FakeFeatureFlagsImpl.java",
+                ],
+            }),
+            paths
+        );
+
+        let bytes = list_generated_files(
+            parse_test_flags_as_input(),
+            &[Backend::Rust, Backend::Cpp],
+            CodegenMode::Production,
+        )
+        .unwrap();
+        let paths: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec!["cpp", "rust"], paths.as_object().unwrap().keys().collect::<Vec<_>>());
+        assert_eq!(serde_json::json!(["src/lib.rs"]), paths["rust"]);
+    }
+
+    #[test]
+    fn test_generate_dispatches_to_backend() {
+        let paths = |backend| {
+            generate(
+                parse_test_flags_as_input(),
+                backend,
+                CodegenMode::Production,
+                &mut Diagnostics::default(),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|file| file.path)
+            .collect::<Vec<_>>()
+        };
+        assert!(
+            paths(Backend::Java).contains(&PathBuf::from("com/android/aconfig/test/Flags.java"))
+        );
+        assert_eq!(vec![PathBuf::from("src/lib.rs")], paths(Backend::Rust));
+    }
+
+    #[test]
+    fn test_format_flag_declarations() {
+        let declarations = r#"
+# top of file comment
+package: "com.first"
+container: "system"
+
+# this comment belongs to the second flag
+# and so does this one
+flag {
+    name: "second"
+    namespace: "ns"
+    description: "The second flag {with braces}" # trailing comment
+    bug: "123"
+}
+
+flag {
+    name: "first"
+    namespace: "ns"
+    # comment inside a flag
+    description: "The first flag"
+    bug: "123"
+}
+"#;
+        let input =
+            Input { source: "memory".to_string(), reader: Box::new(declarations.as_bytes()) };
+        let bytes = format_flag_declarations(input).unwrap();
+        let text = std::str::from_utf8(&bytes).unwrap();
+        let expected = r#"# top of file comment
+package: "com.first"
+container: "system"
+
+flag {
+    name: "first"
+    namespace: "ns"
+    # comment inside a flag
+    description: "The first flag"
+    bug: "123"
+}
+
+# this comment belongs to the second flag
+# and so does this one
+flag {
+    name: "second"
+    namespace: "ns"
+    description: "The second flag {with braces}" # trailing comment
+    bug: "123"
+}
+"#;
+        assert_eq!(expected, text);
+
+        // formatting is idempotent
+        let input = Input { source: "memory".to_string(), reader: Box::new(expected.as_bytes()) };
+        let bytes = format_flag_declarations(input).unwrap();
+        assert_eq!(expected, std::str::from_utf8(&bytes).unwrap());
+    }
+
     fn parse_test_flags_as_input() -> Input {
         let parsed_flags = crate::test::parse_test_flags();
         let binary_proto = parsed_flags.write_to_bytes().unwrap();
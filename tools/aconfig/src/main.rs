@@ -17,7 +17,7 @@
 //! `aconfig` is a build time tool to manage build time configurations, such as feature flags.
 
 use anyhow::{anyhow, bail, Context, Result};
-use clap::{builder::ArgAction, builder::EnumValueParser, Arg, ArgMatches, Command};
+use clap::{builder::ArgAction, builder::EnumValueParser, Arg, ArgMatches, Command, ValueEnum};
 use core::any::Any;
 use std::fs;
 use std::io;
@@ -28,21 +28,56 @@ mod codegen;
 mod commands;
 mod protos;
 mod storage;
+mod zip;
 
 #[cfg(test)]
 mod test;
 
-use commands::{CodegenMode, DumpFormat, Input, OutputFile};
+use codegen::cpp::CppCodegenConfig;
+use codegen::java::{JavaCodegenConfig, JavaStyle};
+use codegen::rust::RustCodegenConfig;
+use commands::{CodegenMode, Diagnostics, DumpFormat, Input, OutputFile, Verbosity};
+use protos::parsed_flags::ConflictPolicy;
 
 fn cli() -> Command {
     Command::new("aconfig")
         .subcommand_required(true)
+        .arg(
+            Arg::new("fail-on-warnings")
+                .long("fail-on-warnings")
+                .global(true)
+                .num_args(0)
+                .action(ArgAction::SetTrue)
+                .help("Treat warnings as errors."),
+        )
+        .arg(
+            Arg::new("quiet")
+                .long("quiet")
+                .global(true)
+                .num_args(0)
+                .action(ArgAction::SetTrue)
+                .conflicts_with("verbose")
+                .help("Only report errors."),
+        )
+        .arg(
+            Arg::new("verbose")
+                .long("verbose")
+                .global(true)
+                .num_args(0)
+                .action(ArgAction::SetTrue)
+                .help("Also report informational messages, e.g. which files were generated."),
+        )
         .subcommand(
-            Command::new("create-cache")
+            add_parse_flags_config_args(Command::new("create-cache"))
                 .arg(Arg::new("package").long("package").required(true))
                 // TODO(b/312769710): Make this argument required.
                 .arg(Arg::new("container").long("container"))
-                .arg(Arg::new("declarations").long("declarations").action(ArgAction::Append))
+                .arg(
+                    Arg::new("declarations")
+                        .long("declarations")
+                        .action(ArgAction::Append)
+                        .help("Flag declarations file, or - to read from stdin."),
+                )
                 .arg(Arg::new("values").long("values").action(ArgAction::Append))
                 .arg(
                     Arg::new("default-permission")
@@ -54,32 +89,397 @@ fn cli() -> Command {
                 )
                 .arg(Arg::new("cache").long("cache").required(true)),
         )
+        .subcommand(
+            add_parse_flags_config_args(Command::new("create-caches"))
+                .arg(Arg::new("declarations").long("declarations").required(true))
+                .arg(Arg::new("values").long("values").action(ArgAction::Append))
+                .arg(
+                    Arg::new("default-permission")
+                        .long("default-permission")
+                        .value_parser(protos::flag_permission::parse_from_str)
+                        .default_value(protos::flag_permission::to_string(
+                            &commands::DEFAULT_FLAG_PERMISSION,
+                        )),
+                )
+                .arg(
+                    Arg::new("out-dir")
+                        .long("out-dir")
+                        .required(true)
+                        .help("Directory to write <package>.pb for each package to."),
+                ),
+        )
         .subcommand(
             Command::new("create-java-lib")
                 .arg(Arg::new("cache").long("cache").required(true))
+                .arg(
+                    Arg::new("since-version")
+                        .long("since-version")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Leave out flags introduced after this version."),
+                )
                 .arg(Arg::new("out").long("out").required(true))
+                .arg(
+                    Arg::new("license-header")
+                        .long("license-header")
+                        .help("File with a comment to put at the top of each generated file."),
+                )
+                .arg(
+                    Arg::new("line-endings")
+                        .long("line-endings")
+                        .value_parser(EnumValueParser::<codegen::LineEnding>::new())
+                        .default_value("lf")
+                        .help("Line endings of the generated files."),
+                )
+                .arg(
+                    Arg::new("depfile")
+                        .long("depfile")
+                        .help("Also write a Make style depfile, from the generated files to the flag declarations."),
+                )
                 .arg(
                     Arg::new("mode")
                         .long("mode")
                         .value_parser(EnumValueParser::<commands::CodegenMode>::new())
                         .default_value("production"),
+                )
+                .arg(
+                    Arg::new("test-params")
+                        .long("test-params")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Also generate FlagsTestParams.java for parameterized tests."),
+                )
+                .arg(
+                    Arg::new("flag-count-test")
+                        .long("flag-count-test")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Also generate a test asserting the number of flags is unchanged."),
+                )
+                .arg(
+                    Arg::new("values-snapshot")
+                        .long("values-snapshot")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Also generate a FlagsValues record with the values of all flags."),
+                )
+                .arg(
+                    Arg::new("read-only-test")
+                        .long("read-only-test")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Also generate a JUnit test checking the values of read-only flags."),
+                )
+                .arg(
+                    Arg::new("constants-class")
+                        .long("constants-class")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Also generate a FlagConstants class with the values of the read-only flags."),
+                )
+                .arg(
+                    Arg::new("annotate-permissions")
+                        .long("annotate-permissions")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Annotate accessors with @ReadOnly or @ReadWrite."),
+                )
+                .arg(
+                    Arg::new("log-reads")
+                        .long("log-reads")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Log flag reads, if debug logging is enabled for FeatureFlagsImpl."),
+                )
+                .arg(
+                    Arg::new("value-provider")
+                        .long("value-provider")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Let FeatureFlagsImpl read flags from an injected provider."),
+                )
+                .arg(
+                    Arg::new("kill-switch")
+                        .long("kill-switch")
+                        .help("DeviceConfig flag, as <namespace>/<name>, which when true makes all read-write flags use their default value."),
+                )
+                .arg(
+                    Arg::new("read-timing-helper")
+                        .long("read-timing-helper")
+                        .help("Class with a static recordFlagRead(String, long) method to report the time each read-write flag read takes to."),
+                )
+                .arg(
+                    Arg::new("package-private")
+                        .long("package-private")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Make the accessors in Flags package-private instead of public."),
+                )
+                .arg(
+                    Arg::new("interface-only")
+                        .long("interface-only")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Only generate the FeatureFlags interface."),
+                )
+                .arg(
+                    Arg::new("single-file")
+                        .long("single-file")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Nest the other classes in Flags, generating a single file."),
+                )
+                .arg(
+                    Arg::new("fold-read-only")
+                        .long("fold-read-only")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Mark read-only accessors so they can be folded into constants."),
+                )
+                .arg(
+                    Arg::new("device-config-key-annotation")
+                        .long("device-config-key-annotation")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Annotate read-write accessors with their DeviceConfig namespace and flag."),
+                )
+                .arg(
+                    Arg::new("zip")
+                        .long("zip")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Write the generated files to a zip archive at --out."),
+                )
+                .arg(
+                    Arg::new("flags-class-name")
+                        .long("flags-class-name")
+                        .default_value("Flags")
+                        .help("Name of the class with the static flag accessors."),
+                )
+                .arg(
+                    Arg::new("default-values-map")
+                        .long("default-values-map")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Add a map of the default value of each flag to Flags."),
+                )
+                .arg(
+                    Arg::new("flag-indices")
+                        .long("flag-indices")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Add an index constant for each flag, and the number of flags, to Flags."),
+                )
+                .arg(
+                    Arg::new("change-listeners")
+                        .long("change-listeners")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Add methods to listen for changes of read-write flags."),
+                )
+                .arg(
+                    Arg::new("strict-package-dir")
+                        .long("strict-package-dir")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Expect --out to be the directory of the package and verify it."),
+                )
+                .arg(
+                    Arg::new("indent-width")
+                        .long("indent-width")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("4")
+                        .help("Number of spaces per indentation level in the generated code."),
+                )
+                .arg(
+                    Arg::new("braces-on-new-line")
+                        .long("braces-on-new-line")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Put opening braces of blocks on a line of their own."),
                 ),
         )
         .subcommand(
             Command::new("create-cpp-lib")
                 .arg(Arg::new("cache").long("cache").required(true))
+                .arg(
+                    Arg::new("since-version")
+                        .long("since-version")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Leave out flags introduced after this version."),
+                )
                 .arg(Arg::new("out").long("out").required(true))
+                .arg(
+                    Arg::new("license-header")
+                        .long("license-header")
+                        .help("File with a comment to put at the top of each generated file."),
+                )
+                .arg(
+                    Arg::new("line-endings")
+                        .long("line-endings")
+                        .value_parser(EnumValueParser::<codegen::LineEnding>::new())
+                        .default_value("lf")
+                        .help("Line endings of the generated files."),
+                )
+                .arg(
+                    Arg::new("depfile")
+                        .long("depfile")
+                        .help("Also write a Make style depfile, from the generated files to the flag declarations."),
+                )
                 .arg(
                     Arg::new("mode")
                         .long("mode")
                         .value_parser(EnumValueParser::<commands::CodegenMode>::new())
                         .default_value("production"),
+                )
+                .arg(
+                    Arg::new("zip")
+                        .long("zip")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Write the generated files to a zip archive at --out."),
+                )
+                .arg(
+                    Arg::new("branch-hints")
+                        .long("branch-hints")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Hint that read-write flags usually have their default value."),
+                )
+                .arg(
+                    Arg::new("c-header")
+                        .long("c-header")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Also generate a header for plain C."),
                 ),
         )
         .subcommand(
             Command::new("create-rust-lib")
                 .arg(Arg::new("cache").long("cache").required(true))
+                .arg(
+                    Arg::new("since-version")
+                        .long("since-version")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Leave out flags introduced after this version."),
+                )
                 .arg(Arg::new("out").long("out").required(true))
+                .arg(
+                    Arg::new("license-header")
+                        .long("license-header")
+                        .help("File with a comment to put at the top of each generated file."),
+                )
+                .arg(
+                    Arg::new("line-endings")
+                        .long("line-endings")
+                        .value_parser(EnumValueParser::<codegen::LineEnding>::new())
+                        .default_value("lf")
+                        .help("Line endings of the generated files."),
+                )
+                .arg(
+                    Arg::new("depfile")
+                        .long("depfile")
+                        .help("Also write a Make style depfile, from the generated files to the flag declarations."),
+                )
+                .arg(
+                    Arg::new("mode")
+                        .long("mode")
+                        .value_parser(EnumValueParser::<commands::CodegenMode>::new())
+                        .default_value("production"),
+                )
+                .arg(
+                    Arg::new("read-only-consts")
+                        .long("read-only-consts")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Also generate a const for each read-only flag."),
+                ),
+        )
+        .subcommand(
+            Command::new("generate")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(
+                    Arg::new("since-version")
+                        .long("since-version")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Leave out flags introduced after this version."),
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .required(true)
+                        .help("Directory to write the generated files to."),
+                )
+                .arg(
+                    Arg::new("backend")
+                        .long("backend")
+                        .value_parser(EnumValueParser::<commands::Backend>::new())
+                        .required(true)
+                        .help("Language to generate code for."),
+                )
+                .arg(
+                    Arg::new("line-endings")
+                        .long("line-endings")
+                        .value_parser(EnumValueParser::<codegen::LineEnding>::new())
+                        .default_value("lf")
+                        .help("Line endings of the generated files."),
+                )
+                .arg(
+                    Arg::new("mode")
+                        .long("mode")
+                        .value_parser(EnumValueParser::<commands::CodegenMode>::new())
+                        .default_value("production"),
+                ),
+        )
+        .subcommand(
+            Command::new("generate-all")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(
+                    Arg::new("since-version")
+                        .long("since-version")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Leave out flags introduced after this version."),
+                )
+                .arg(Arg::new("out").long("out").required(true))
+                .arg(
+                    Arg::new("backend")
+                        .long("backend")
+                        .value_parser(EnumValueParser::<commands::Backend>::new())
+                        .action(ArgAction::Append)
+                        .required(true)
+                        .help("Generate code for this language, into a directory of its name."),
+                )
+                .arg(
+                    Arg::new("line-endings")
+                        .long("line-endings")
+                        .value_parser(EnumValueParser::<codegen::LineEnding>::new())
+                        .default_value("lf")
+                        .help("Line endings of the generated files."),
+                )
+                .arg(
+                    Arg::new("mode")
+                        .long("mode")
+                        .value_parser(EnumValueParser::<commands::CodegenMode>::new())
+                        .default_value("production"),
+                ),
+        )
+        .subcommand(
+            Command::new("list-generated-files")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(
+                    Arg::new("since-version")
+                        .long("since-version")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Leave out flags introduced after this version."),
+                )
+                .arg(Arg::new("out").long("out").default_value("-"))
+                .arg(
+                    Arg::new("backend")
+                        .long("backend")
+                        .value_parser(EnumValueParser::<commands::Backend>::new())
+                        .action(ArgAction::Append)
+                        .required(true)
+                        .help("List the files generate-all writes for this language."),
+                )
                 .arg(
                     Arg::new("mode")
                         .long("mode")
@@ -97,6 +497,157 @@ fn cli() -> Command {
                 .arg(Arg::new("cache").long("cache").action(ArgAction::Append).required(true))
                 .arg(Arg::new("out").long("out").default_value("-")),
         )
+        .subcommand(
+            Command::new("list-namespaces")
+                .arg(Arg::new("cache").long("cache").action(ArgAction::Append).required(true))
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("create-namespace-report")
+                .arg(Arg::new("cache").long("cache").action(ArgAction::Append).required(true))
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("validate")
+                .arg(Arg::new("cache").long("cache").action(ArgAction::Append).required(true))
+                .arg(
+                    Arg::new("check-java-member-names")
+                        .long("check-java-member-names")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help(
+                            "Also check Java accessors do not clash with other generated members.",
+                        ),
+                )
+                .arg(
+                    Arg::new("server-defaults")
+                        .long("server-defaults")
+                        .help("Warn about read-write flags with the same default as the server."),
+                )
+                .arg(
+                    Arg::new("provisioned-namespaces")
+                        .long("provisioned-namespaces")
+                        .help("File listing the DeviceConfig namespaces the device provisions, one per line: fail if a read-write flag uses another namespace."),
+                ),
+        )
+        .subcommand(
+            Command::new("assign-flag-ids")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(
+                    Arg::new("ids")
+                        .long("ids")
+                        .help("Ids assigned by a previous run, to keep them stable."),
+                )
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("set-namespace-read-only")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(Arg::new("namespace").long("namespace").required(true))
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("merge-caches")
+                .arg(Arg::new("left").long("left").required(true))
+                .arg(Arg::new("right").long("right").required(true))
+                .arg(
+                    Arg::new("conflict")
+                        .long("conflict")
+                        .value_parser(EnumValueParser::<ConflictPolicy>::new())
+                        .default_value("error")
+                        .help("How to resolve flags defined differently in both caches."),
+                )
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("compare-caches")
+                .arg(Arg::new("left").long("left").required(true))
+                .arg(Arg::new("right").long("right").required(true)),
+        )
+        .subcommand(
+            Command::new("create-removed-flags-report")
+                .arg(Arg::new("baseline").long("baseline").required(true))
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("create-permission-changes-report")
+                .arg(Arg::new("baseline").long("baseline").required(true))
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("rename-package")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(Arg::new("package").long("package").required(true))
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("rename-flag")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(Arg::new("from").long("from").required(true))
+                .arg(Arg::new("to").long("to").required(true))
+                .arg(
+                    Arg::new("keep-alias")
+                        .long("keep-alias")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue)
+                        .help("Keep the old name as an alias of the renamed flag."),
+                )
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("prefix-namespaces")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(Arg::new("prefix").long("prefix").required(true))
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("create-device-config-key-map")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("create-owner-map")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("create-device-config-keys")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("create-admin-ui-json")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(Arg::new("out").long("out").required(true)),
+        )
+        .subcommand(
+            Command::new("create-package-summary")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("create-bug-map")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(EnumValueParser::<commands::BugMapFormat>::new())
+                        .default_value("text"),
+                )
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("create-read-only-golden")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("create-values-template")
+                .arg(Arg::new("cache").long("cache").required(true))
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
         .subcommand(
             Command::new("dump")
                 .arg(Arg::new("cache").long("cache").action(ArgAction::Append))
@@ -107,6 +658,17 @@ fn cli() -> Command {
                         .default_value("text"),
                 )
                 .arg(Arg::new("dedup").long("dedup").num_args(0).action(ArgAction::SetTrue))
+                .arg(
+                    Arg::new("merge-most-restrictive-permission")
+                        .long("merge-most-restrictive-permission")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(Arg::new("out").long("out").default_value("-")),
+        )
+        .subcommand(
+            Command::new("format-declarations")
+                .arg(Arg::new("declarations").long("declarations").required(true))
                 .arg(Arg::new("out").long("out").default_value("-")),
         )
         .subcommand(
@@ -122,6 +684,119 @@ fn cli() -> Command {
         )
 }
 
+// The checks and warnings of parsing the flags of a package, shared by create-cache and
+// create-caches
+fn add_parse_flags_config_args(command: Command) -> Command {
+    command
+        .arg(
+            Arg::new("max-flags-per-package")
+                .long("max-flags-per-package")
+                .value_parser(clap::value_parser!(usize))
+                .help("Fail if the package declares more flags than this."),
+        )
+        .arg(
+            Arg::new("fail-on-empty-package")
+                .long("fail-on-empty-package")
+                .num_args(0)
+                .action(ArgAction::SetTrue)
+                .help("Fail if the package declares no flags."),
+        )
+        .arg(
+            Arg::new("max-description-length")
+                .long("max-description-length")
+                .value_parser(clap::value_parser!(usize))
+                .help("Fail if the description of a flag is longer than this."),
+        )
+        .arg(
+            Arg::new("max-qualified-name-length")
+                .long("max-qualified-name-length")
+                .value_parser(clap::value_parser!(usize))
+                .help("Fail if <package>.<flag> of a flag is longer than this."),
+        )
+        .arg(
+            Arg::new("warn-namespace-typos")
+                .long("warn-namespace-typos")
+                .num_args(0)
+                .action(ArgAction::SetTrue)
+                .help("Warn about namespaces which look like a typo of another namespace."),
+        )
+        .arg(
+            Arg::new("unknown-fields")
+                .long("unknown-fields")
+                .value_parser(EnumValueParser::<commands::UnknownFieldPolicy>::new())
+                .default_value("strict")
+                .help("Fail on (strict) or ignore (lenient) unknown declaration fields."),
+        )
+        .arg(
+            Arg::new("warn-default-mismatches")
+                .long("warn-default-mismatches")
+                .num_args(0)
+                .action(ArgAction::SetTrue)
+                .help("Warn when a flag's inherited default no longer matches its state."),
+        )
+        .arg(
+            Arg::new("warn-case-clashes")
+                .long("warn-case-clashes")
+                .num_args(0)
+                .action(ArgAction::SetTrue)
+                .help("Warn about flags whose Java accessors only differ by case."),
+        )
+        .arg(
+            Arg::new("warn-overridden-states")
+                .long("warn-overridden-states")
+                .num_args(0)
+                .action(ArgAction::SetTrue)
+                .help("Warn when a values file changes the state set by an earlier one."),
+        )
+        .arg(
+            Arg::new("warn-undeclared-values")
+                .long("warn-undeclared-values")
+                .num_args(0)
+                .action(ArgAction::SetTrue)
+                .help("Warn when a values file sets an undeclared flag of this package."),
+        )
+        .arg(
+            Arg::new("normalize-separators")
+                .long("normalize-separators")
+                .num_args(0)
+                .action(ArgAction::SetTrue)
+                .help("Reject flags whose names only differ in underscores."),
+        )
+        .arg(
+            Arg::new("approved-flags")
+                .long("approved-flags")
+                .help("File listing the fully qualified names of the flags allowed to be declared, one per line."),
+        )
+}
+
+fn get_parse_flags_config(matches: &ArgMatches) -> Result<commands::ParseFlagsConfig> {
+    let approved_flags = if matches.contains_id("approved-flags") {
+        let input = open_single_file(matches, "approved-flags")?;
+        Some(commands::read_name_list(input)?)
+    } else {
+        None
+    };
+    Ok(commands::ParseFlagsConfig {
+        max_flags_per_package: get_optional_arg::<usize>(matches, "max-flags-per-package").copied(),
+        fail_on_empty_package: *get_required_arg::<bool>(matches, "fail-on-empty-package")?,
+        warn_namespace_typos: *get_required_arg::<bool>(matches, "warn-namespace-typos")?,
+        normalize_separators: *get_required_arg::<bool>(matches, "normalize-separators")?,
+        warn_case_clashes: *get_required_arg::<bool>(matches, "warn-case-clashes")?,
+        warn_default_mismatches: *get_required_arg::<bool>(matches, "warn-default-mismatches")?,
+        unknown_fields: *get_required_arg::<commands::UnknownFieldPolicy>(
+            matches,
+            "unknown-fields",
+        )?,
+        warn_overridden_states: *get_required_arg::<bool>(matches, "warn-overridden-states")?,
+        warn_undeclared_values: *get_required_arg::<bool>(matches, "warn-undeclared-values")?,
+        approved_flags,
+        max_description_length: get_optional_arg::<usize>(matches, "max-description-length")
+            .copied(),
+        max_qualified_name_length: get_optional_arg::<usize>(matches, "max-qualified-name-length")
+            .copied(),
+    })
+}
+
 fn get_required_arg<'a, T>(matches: &'a ArgMatches, arg_name: &str) -> Result<&'a T>
 where
     T: Any + Clone + Send + Sync + 'static,
@@ -139,8 +814,25 @@ where
 }
 
 fn open_zero_or_more_files(matches: &ArgMatches, arg_name: &str) -> Result<Vec<Input>> {
+    open_zero_or_more_files_or_stdin(matches, arg_name, Box::new(io::stdin()))
+}
+
+// A path of "-" refers to stdin, which can only be read once
+fn open_zero_or_more_files_or_stdin(
+    matches: &ArgMatches,
+    arg_name: &str,
+    stdin: Box<dyn io::Read>,
+) -> Result<Vec<Input>> {
+    let mut stdin = Some(stdin);
     let mut opened_files = vec![];
     for path in matches.get_many::<String>(arg_name).unwrap_or_default() {
+        if path == "-" {
+            let Some(reader) = stdin.take() else {
+                bail!("stdin (-) can only be used once for --{}", arg_name);
+            };
+            opened_files.push(Input { source: "<stdin>".to_string(), reader });
+            continue;
+        }
         let file = Box::new(fs::File::open(path)?);
         opened_files.push(Input { source: path.to_string(), reader: file });
     }
@@ -155,17 +847,54 @@ fn open_single_file(matches: &ArgMatches, arg_name: &str) -> Result<Input> {
     Ok(Input { source: path.to_string(), reader: file })
 }
 
-fn write_output_file_realtive_to_dir(root: &Path, output_file: &OutputFile) -> Result<()> {
-    let path = root.join(&output_file.path);
-    let parent = path
-        .parent()
-        .ok_or(anyhow!("unable to locate parent of output file {}", path.display()))?;
-    fs::create_dir_all(parent)
-        .with_context(|| format!("failed to create directory {}", parent.display()))?;
-    let mut file =
-        fs::File::create(&path).with_context(|| format!("failed to open {}", path.display()))?;
-    file.write_all(&output_file.contents)
-        .with_context(|| format!("failed to write to {}", path.display()))?;
+// The cache to generate code from, without the flags --since-version leaves out
+fn open_cache_for_codegen(matches: &ArgMatches) -> Result<Input> {
+    let cache = open_single_file(matches, "cache")?;
+    match get_optional_arg::<u32>(matches, "since-version") {
+        Some(version) => commands::filter_since_version(cache, *version),
+        None => Ok(cache),
+    }
+}
+
+fn add_license_header(matches: &ArgMatches, files: &mut [OutputFile]) -> Result<()> {
+    let Some(path) = get_optional_arg::<String>(matches, "license-header") else {
+        return Ok(());
+    };
+    let header = fs::read_to_string(path).with_context(|| format!("failed to read {}", path))?;
+    codegen::prepend_license_header(files, &header)
+        .with_context(|| format!("failed to add license header from {}", path))
+}
+
+fn apply_line_endings(matches: &ArgMatches, files: &mut [OutputFile]) -> Result<()> {
+    let line_ending = get_required_arg::<codegen::LineEnding>(matches, "line-endings")?;
+    codegen::apply_line_ending(files, *line_ending);
+    Ok(())
+}
+
+// Reads the cache a second time, since generating code consumed the first input
+fn write_depfile(matches: &ArgMatches, targets: &[PathBuf]) -> Result<()> {
+    let Some(path) = get_optional_arg::<String>(matches, "depfile") else {
+        return Ok(());
+    };
+    let cache = open_single_file(matches, "cache")?;
+    let contents = commands::create_depfile(cache, targets).context("failed to create depfile")?;
+    write_output_to_file_or_stdout(path, &contents)
+}
+
+fn write_output_file_realtive_to_dir(
+    root: &Path,
+    output_file: &OutputFile,
+    diagnostics: &mut Diagnostics,
+) -> Result<()> {
+    output_file.write_to(root)?;
+    diagnostics.info(format!("generated {}", root.join(&output_file.path).display()));
+    Ok(())
+}
+
+fn write_zip(path: &str, files: &[OutputFile], diagnostics: &mut Diagnostics) -> Result<()> {
+    let contents = zip::create_zip(files).context("failed to create zip archive")?;
+    write_output_to_file_or_stdout(path, &contents)?;
+    diagnostics.info(format!("generated {} ({} file(s))", path, files.len()));
     Ok(())
 }
 
@@ -183,6 +912,14 @@ fn write_output_to_file_or_stdout(path: &str, data: &[u8]) -> Result<()> {
 
 fn main() -> Result<()> {
     let matches = cli().get_matches();
+    let verbosity = if *get_required_arg::<bool>(&matches, "quiet")? {
+        Verbosity::Quiet
+    } else if *get_required_arg::<bool>(&matches, "verbose")? {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    };
+    let mut diagnostics = Diagnostics::new(verbosity);
     match matches.subcommand() {
         Some(("create-cache", sub_matches)) => {
             let package = get_required_arg::<String>(sub_matches, "package")?;
@@ -192,44 +929,183 @@ fn main() -> Result<()> {
             let values = open_zero_or_more_files(sub_matches, "values")?;
             let default_permission =
                 get_required_arg::<protos::ProtoFlagPermission>(sub_matches, "default-permission")?;
+            let config = get_parse_flags_config(sub_matches)?;
             let output = commands::parse_flags(
                 package,
                 container,
                 declarations,
                 values,
                 *default_permission,
+                &config,
+                &mut diagnostics,
             )
             .context("failed to create cache")?;
             let path = get_required_arg::<String>(sub_matches, "cache")?;
             write_output_to_file_or_stdout(path, &output)?;
         }
+        Some(("create-caches", sub_matches)) => {
+            let declarations = open_single_file(sub_matches, "declarations")?;
+            let values = open_zero_or_more_files(sub_matches, "values")?;
+            let default_permission =
+                get_required_arg::<protos::ProtoFlagPermission>(sub_matches, "default-permission")?;
+            let caches = commands::parse_multi_package_flags(
+                declarations,
+                values,
+                *default_permission,
+                &get_parse_flags_config(sub_matches)?,
+                &mut diagnostics,
+            )
+            .context("failed to create caches")?;
+            let dir = PathBuf::from(get_required_arg::<String>(sub_matches, "out-dir")?);
+            for (package, contents) in caches {
+                let file = OutputFile { path: PathBuf::from(format!("{}.pb", package)), contents };
+                write_output_file_realtive_to_dir(&dir, &file, &mut diagnostics)?;
+            }
+        }
         Some(("create-java-lib", sub_matches)) => {
-            let cache = open_single_file(sub_matches, "cache")?;
+            let cache = open_cache_for_codegen(sub_matches)?;
             let mode = get_required_arg::<CodegenMode>(sub_matches, "mode")?;
-            let generated_files =
-                commands::create_java_lib(cache, *mode).context("failed to create java lib")?;
-            let dir = PathBuf::from(get_required_arg::<String>(sub_matches, "out")?);
-            generated_files
-                .iter()
-                .try_for_each(|file| write_output_file_realtive_to_dir(&dir, file))?;
+            let config = JavaCodegenConfig {
+                generate_test_params: *get_required_arg::<bool>(sub_matches, "test-params")?,
+                generate_flag_count_test: *get_required_arg::<bool>(
+                    sub_matches,
+                    "flag-count-test",
+                )?,
+                generate_read_only_test: *get_required_arg::<bool>(sub_matches, "read-only-test")?,
+                generate_values_snapshot: *get_required_arg::<bool>(
+                    sub_matches,
+                    "values-snapshot",
+                )?,
+                generate_constants: *get_required_arg::<bool>(sub_matches, "constants-class")?,
+                annotate_permissions: *get_required_arg::<bool>(
+                    sub_matches,
+                    "annotate-permissions",
+                )?,
+                log_reads: *get_required_arg::<bool>(sub_matches, "log-reads")?,
+                value_provider: *get_required_arg::<bool>(sub_matches, "value-provider")?,
+                package_private: *get_required_arg::<bool>(sub_matches, "package-private")?,
+                interface_only: *get_required_arg::<bool>(sub_matches, "interface-only")?,
+                single_file: *get_required_arg::<bool>(sub_matches, "single-file")?,
+                fold_read_only: *get_required_arg::<bool>(sub_matches, "fold-read-only")?,
+                device_config_key_annotation: *get_required_arg::<bool>(
+                    sub_matches,
+                    "device-config-key-annotation",
+                )?,
+                change_listeners: *get_required_arg::<bool>(sub_matches, "change-listeners")?,
+                default_values_map: *get_required_arg::<bool>(sub_matches, "default-values-map")?,
+                flag_indices: *get_required_arg::<bool>(sub_matches, "flag-indices")?,
+                flags_class_name: get_required_arg::<String>(sub_matches, "flags-class-name")?,
+                read_timing_helper: get_optional_arg::<String>(sub_matches, "read-timing-helper")
+                    .map(|helper| helper.as_str()),
+                kill_switch: get_optional_arg::<String>(sub_matches, "kill-switch")
+                    .map(|kill_switch| kill_switch.as_str()),
+                style: JavaStyle {
+                    indent_width: *get_required_arg::<usize>(sub_matches, "indent-width")?,
+                    braces_on_new_line: *get_required_arg::<bool>(
+                        sub_matches,
+                        "braces-on-new-line",
+                    )?,
+                },
+                ..JavaCodegenConfig::new(*mode)
+            };
+            let mut generated_files = commands::create_java_lib(cache, config, &mut diagnostics)
+                .context("failed to create java lib")?;
+            add_license_header(sub_matches, &mut generated_files)?;
+            apply_line_endings(sub_matches, &mut generated_files)?;
+            let out = get_required_arg::<String>(sub_matches, "out")?;
+            if *get_required_arg::<bool>(sub_matches, "zip")? {
+                write_zip(out, &generated_files, &mut diagnostics)?;
+                write_depfile(sub_matches, &[PathBuf::from(out)])?;
+            } else {
+                let mut dir = PathBuf::from(out);
+                if *get_required_arg::<bool>(sub_matches, "strict-package-dir")? {
+                    dir = commands::strip_package_dir(&dir, &generated_files)?;
+                }
+                generated_files.iter().try_for_each(|file| {
+                    write_output_file_realtive_to_dir(&dir, file, &mut diagnostics)
+                })?;
+                let targets: Vec<PathBuf> =
+                    generated_files.iter().map(|file| dir.join(&file.path)).collect();
+                write_depfile(sub_matches, &targets)?;
+            }
         }
         Some(("create-cpp-lib", sub_matches)) => {
-            let cache = open_single_file(sub_matches, "cache")?;
+            let cache = open_cache_for_codegen(sub_matches)?;
             let mode = get_required_arg::<CodegenMode>(sub_matches, "mode")?;
-            let generated_files =
-                commands::create_cpp_lib(cache, *mode).context("failed to create cpp lib")?;
-            let dir = PathBuf::from(get_required_arg::<String>(sub_matches, "out")?);
-            generated_files
-                .iter()
-                .try_for_each(|file| write_output_file_realtive_to_dir(&dir, file))?;
+            let config = CppCodegenConfig {
+                branch_hints: *get_required_arg::<bool>(sub_matches, "branch-hints")?,
+                c_header: *get_required_arg::<bool>(sub_matches, "c-header")?,
+                ..CppCodegenConfig::new(*mode)
+            };
+            let mut generated_files = commands::create_cpp_lib(cache, config, &mut diagnostics)
+                .context("failed to create cpp lib")?;
+            add_license_header(sub_matches, &mut generated_files)?;
+            apply_line_endings(sub_matches, &mut generated_files)?;
+            let out = get_required_arg::<String>(sub_matches, "out")?;
+            if *get_required_arg::<bool>(sub_matches, "zip")? {
+                write_zip(out, &generated_files, &mut diagnostics)?;
+                write_depfile(sub_matches, &[PathBuf::from(out)])?;
+            } else {
+                let dir = PathBuf::from(out);
+                generated_files.iter().try_for_each(|file| {
+                    write_output_file_realtive_to_dir(&dir, file, &mut diagnostics)
+                })?;
+                let targets: Vec<PathBuf> =
+                    generated_files.iter().map(|file| dir.join(&file.path)).collect();
+                write_depfile(sub_matches, &targets)?;
+            }
         }
         Some(("create-rust-lib", sub_matches)) => {
-            let cache = open_single_file(sub_matches, "cache")?;
+            let cache = open_cache_for_codegen(sub_matches)?;
             let mode = get_required_arg::<CodegenMode>(sub_matches, "mode")?;
-            let generated_file =
-                commands::create_rust_lib(cache, *mode).context("failed to create rust lib")?;
+            let config = RustCodegenConfig {
+                read_only_consts: *get_required_arg::<bool>(sub_matches, "read-only-consts")?,
+                ..RustCodegenConfig::new(*mode)
+            };
+            let mut generated_file = commands::create_rust_lib(cache, config, &mut diagnostics)
+                .context("failed to create rust lib")?;
+            add_license_header(sub_matches, std::slice::from_mut(&mut generated_file))?;
+            apply_line_endings(sub_matches, std::slice::from_mut(&mut generated_file))?;
             let dir = PathBuf::from(get_required_arg::<String>(sub_matches, "out")?);
-            write_output_file_realtive_to_dir(&dir, &generated_file)?;
+            write_output_file_realtive_to_dir(&dir, &generated_file, &mut diagnostics)?;
+            write_depfile(sub_matches, &[dir.join(&generated_file.path)])?;
+        }
+        Some(("generate", sub_matches)) => {
+            let cache = open_cache_for_codegen(sub_matches)?;
+            let mode = get_required_arg::<CodegenMode>(sub_matches, "mode")?;
+            let backend = get_required_arg::<commands::Backend>(sub_matches, "backend")?;
+            let dir = PathBuf::from(get_required_arg::<String>(sub_matches, "out")?);
+            let mut generated_files = commands::generate(cache, *backend, *mode, &mut diagnostics)
+                .context("failed to generate flag lib")?;
+            apply_line_endings(sub_matches, &mut generated_files)?;
+            generated_files.iter().try_for_each(|file| {
+                write_output_file_realtive_to_dir(&dir, file, &mut diagnostics)
+            })?;
+        }
+        Some(("generate-all", sub_matches)) => {
+            let cache = open_cache_for_codegen(sub_matches)?;
+            let mode = get_required_arg::<CodegenMode>(sub_matches, "mode")?;
+            let out = PathBuf::from(get_required_arg::<String>(sub_matches, "out")?);
+            let backends: Vec<commands::Backend> =
+                sub_matches.get_many::<commands::Backend>("backend").unwrap().copied().collect();
+            let mut generated_files =
+                commands::generate_all(cache, &backends, *mode, &mut diagnostics)
+                    .context("failed to generate flag libs")?;
+            for (backend, file) in generated_files.iter_mut() {
+                apply_line_endings(sub_matches, std::slice::from_mut(file))?;
+                let dir = out.join(backend.to_possible_value().unwrap().get_name());
+                write_output_file_realtive_to_dir(&dir, file, &mut diagnostics)?;
+            }
+        }
+        Some(("list-generated-files", sub_matches)) => {
+            let cache = open_cache_for_codegen(sub_matches)?;
+            let mode = get_required_arg::<CodegenMode>(sub_matches, "mode")?;
+            let backends: Vec<commands::Backend> =
+                sub_matches.get_many::<commands::Backend>("backend").unwrap().copied().collect();
+            let output = commands::list_generated_files(cache, &backends, *mode)
+                .context("failed to list generated files")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
         }
         Some(("create-device-config-defaults", sub_matches)) => {
             let cache = open_single_file(sub_matches, "cache")?;
@@ -245,12 +1121,194 @@ fn main() -> Result<()> {
             let path = get_required_arg::<String>(sub_matches, "out")?;
             write_output_to_file_or_stdout(path, &output)?;
         }
+        Some(("list-namespaces", sub_matches)) => {
+            let caches = open_zero_or_more_files(sub_matches, "cache")?;
+            let output = commands::list_namespaces(caches).context("failed to list namespaces")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("create-namespace-report", sub_matches)) => {
+            let caches = open_zero_or_more_files(sub_matches, "cache")?;
+            let output = commands::create_namespace_report(caches)
+                .context("failed to create namespace report")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("validate", sub_matches)) => {
+            let caches = open_zero_or_more_files(sub_matches, "cache")?;
+            let server_defaults = if sub_matches.contains_id("server-defaults") {
+                let input = open_single_file(sub_matches, "server-defaults")?;
+                commands::parse_server_defaults(input)?
+            } else {
+                Default::default()
+            };
+            let provisioned_namespaces = if sub_matches.contains_id("provisioned-namespaces") {
+                let input = open_single_file(sub_matches, "provisioned-namespaces")?;
+                Some(commands::read_name_list(input)?)
+            } else {
+                None
+            };
+            let config = commands::ValidateConfig {
+                check_java_member_names: *get_required_arg::<bool>(
+                    sub_matches,
+                    "check-java-member-names",
+                )?,
+                server_defaults,
+                provisioned_namespaces,
+            };
+            commands::validate_caches(caches, &config, &mut diagnostics)?;
+        }
+        Some(("assign-flag-ids", sub_matches)) => {
+            let cache = open_single_file(sub_matches, "cache")?;
+            let previous_ids = if sub_matches.contains_id("ids") {
+                Some(open_single_file(sub_matches, "ids")?)
+            } else {
+                None
+            };
+            let output = commands::assign_flag_ids(cache, previous_ids)
+                .context("failed to assign flag ids")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("set-namespace-read-only", sub_matches)) => {
+            let cache = open_single_file(sub_matches, "cache")?;
+            let namespace = get_required_arg::<String>(sub_matches, "namespace")?;
+            let output = commands::set_namespace_read_only(cache, namespace)
+                .context("failed to make namespace read-only")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("merge-caches", sub_matches)) => {
+            let left = open_single_file(sub_matches, "left")?;
+            let right = open_single_file(sub_matches, "right")?;
+            let policy = get_required_arg::<ConflictPolicy>(sub_matches, "conflict")?;
+            let output =
+                commands::merge_caches(left, right, *policy).context("failed to merge caches")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("compare-caches", sub_matches)) => {
+            let left = open_single_file(sub_matches, "left")?;
+            let right = open_single_file(sub_matches, "right")?;
+            commands::compare_caches(left, right)?;
+        }
+        Some(("create-permission-changes-report", sub_matches)) => {
+            let baseline = open_single_file(sub_matches, "baseline")?;
+            let cache = open_single_file(sub_matches, "cache")?;
+            let output = commands::create_permission_changes_report(baseline, cache)
+                .context("failed to create permission changes report")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("create-removed-flags-report", sub_matches)) => {
+            let baseline = open_single_file(sub_matches, "baseline")?;
+            let cache = open_single_file(sub_matches, "cache")?;
+            let output = commands::create_removed_flags_report(baseline, cache)
+                .context("failed to create removed flags report")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("rename-package", sub_matches)) => {
+            let cache = open_single_file(sub_matches, "cache")?;
+            let package = get_required_arg::<String>(sub_matches, "package")?;
+            let output =
+                commands::rename_package(cache, package).context("failed to rename package")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("rename-flag", sub_matches)) => {
+            let cache = open_single_file(sub_matches, "cache")?;
+            let from = get_required_arg::<String>(sub_matches, "from")?;
+            let to = get_required_arg::<String>(sub_matches, "to")?;
+            let keep_alias = *get_required_arg::<bool>(sub_matches, "keep-alias")?;
+            let output = commands::rename_flag(cache, from, to, keep_alias)
+                .context("failed to rename flag")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("prefix-namespaces", sub_matches)) => {
+            let cache = open_single_file(sub_matches, "cache")?;
+            let prefix = get_required_arg::<String>(sub_matches, "prefix")?;
+            let output = commands::prefix_namespaces(cache, prefix)
+                .context("failed to prefix namespaces")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("create-device-config-key-map", sub_matches)) => {
+            let cache = open_single_file(sub_matches, "cache")?;
+            let output = commands::create_device_config_key_map(cache)
+                .context("failed to create device config key map")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("create-owner-map", sub_matches)) => {
+            let cache = open_single_file(sub_matches, "cache")?;
+            let output = commands::create_owner_map(cache).context("failed to create owner map")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("create-device-config-keys", sub_matches)) => {
+            let cache = open_single_file(sub_matches, "cache")?;
+            let output = commands::create_device_config_keys(cache)
+                .context("failed to create device config keys")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("create-admin-ui-json", sub_matches)) => {
+            let cache = open_single_file(sub_matches, "cache")?;
+            let generated_file =
+                commands::create_admin_ui_json(cache).context("failed to create admin UI json")?;
+            let dir = PathBuf::from(get_required_arg::<String>(sub_matches, "out")?);
+            write_output_file_realtive_to_dir(&dir, &generated_file, &mut diagnostics)?;
+        }
+        Some(("create-package-summary", sub_matches)) => {
+            let cache = open_single_file(sub_matches, "cache")?;
+            let output = commands::create_package_summary(cache)
+                .context("failed to create package summary")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("create-bug-map", sub_matches)) => {
+            let cache = open_single_file(sub_matches, "cache")?;
+            let format = get_required_arg::<commands::BugMapFormat>(sub_matches, "format")?;
+            let output =
+                commands::create_bug_map(cache, *format).context("failed to create bug map")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("create-read-only-golden", sub_matches)) => {
+            let cache = open_single_file(sub_matches, "cache")?;
+            let output = commands::create_read_only_golden(cache)
+                .context("failed to create read-only golden file")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("create-values-template", sub_matches)) => {
+            let cache = open_single_file(sub_matches, "cache")?;
+            let output = commands::create_values_template(cache)
+                .context("failed to create values template")?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
         Some(("dump", sub_matches)) => {
             let input = open_zero_or_more_files(sub_matches, "cache")?;
             let format = get_required_arg::<DumpFormat>(sub_matches, "format")
                 .context("failed to dump previously parsed flags")?;
             let dedup = get_required_arg::<bool>(sub_matches, "dedup")?;
-            let output = commands::dump_parsed_flags(input, *format, *dedup)?;
+            let merge_policy =
+                if *get_required_arg::<bool>(sub_matches, "merge-most-restrictive-permission")? {
+                    protos::parsed_flags::MergePolicy::MostRestrictivePermission
+                } else {
+                    protos::parsed_flags::MergePolicy::Strict
+                };
+            let output = commands::dump_parsed_flags(input, *format, *dedup, merge_policy)?;
+            let path = get_required_arg::<String>(sub_matches, "out")?;
+            write_output_to_file_or_stdout(path, &output)?;
+        }
+        Some(("format-declarations", sub_matches)) => {
+            let declarations = open_single_file(sub_matches, "declarations")?;
+            let output = commands::format_flag_declarations(declarations)
+                .context("failed to format flag declarations")?;
             let path = get_required_arg::<String>(sub_matches, "out")?;
             write_output_to_file_or_stdout(path, &output)?;
         }
@@ -260,11 +1318,301 @@ fn main() -> Result<()> {
             let dir = PathBuf::from(get_required_arg::<String>(sub_matches, "out")?);
             let generated_files = commands::create_storage(cache, container)
                 .context("failed to create storage files")?;
-            generated_files
-                .iter()
-                .try_for_each(|file| write_output_file_realtive_to_dir(&dir, file))?;
+            generated_files.iter().try_for_each(|file| {
+                write_output_file_realtive_to_dir(&dir, file, &mut diagnostics)
+            })?;
         }
         _ => unreachable!(),
     }
-    Ok(())
+    diagnostics.report(&mut io::stderr())?;
+    diagnostics.check(*get_required_arg::<bool>(&matches, "fail-on-warnings")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quiet_java_lib_generation_reports_nothing() {
+        let matches = cli()
+            .try_get_matches_from([
+                "aconfig",
+                "--quiet",
+                "create-java-lib",
+                "--cache",
+                "-",
+                "--out",
+                "-",
+            ])
+            .unwrap();
+        assert!(*get_required_arg::<bool>(&matches, "quiet").unwrap());
+        assert!(cli()
+            .try_get_matches_from(["aconfig", "--quiet", "--verbose", "dump", "--cache", "-"])
+            .is_err());
+
+        let temp_dir = crate::test::TempDir::new("quiet-test");
+        let dir = temp_dir.path();
+        let input = Input {
+            source: "test".to_string(),
+            reader: Box::new(std::io::Cursor::new(
+                protobuf::Message::write_to_bytes(&crate::test::parse_test_flags()).unwrap(),
+            )),
+        };
+        let mut diagnostics = Diagnostics::new(Verbosity::Quiet);
+        let generated_files = commands::create_java_lib(
+            input,
+            JavaCodegenConfig::new(CodegenMode::Production),
+            &mut diagnostics,
+        )
+        .unwrap();
+        for file in generated_files.iter() {
+            write_output_file_realtive_to_dir(dir, file, &mut diagnostics).unwrap();
+        }
+        let mut out = Vec::new();
+        diagnostics.report(&mut out).unwrap();
+        assert!(out.is_empty());
+
+        let mut diagnostics = Diagnostics::new(Verbosity::Verbose);
+        write_output_file_realtive_to_dir(dir, &generated_files[0], &mut diagnostics).unwrap();
+        diagnostics.report(&mut out).unwrap();
+        let expected = format!("generated {}\n", dir.join(&generated_files[0].path).display());
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_generate_writes_into_out_dir() {
+        let temp_dir = crate::test::TempDir::new("generate-test");
+        let out = temp_dir.path().join("out");
+        let matches = cli()
+            .try_get_matches_from([
+                "aconfig",
+                "generate",
+                "--cache",
+                "-",
+                "--out",
+                out.to_str().unwrap(),
+                "--backend",
+                "java",
+            ])
+            .unwrap();
+        let sub_matches = matches.subcommand_matches("generate").unwrap();
+        let backend = get_required_arg::<commands::Backend>(sub_matches, "backend").unwrap();
+        let dir = PathBuf::from(get_required_arg::<String>(sub_matches, "out").unwrap());
+        let input = Input {
+            source: "test".to_string(),
+            reader: Box::new(std::io::Cursor::new(
+                protobuf::Message::write_to_bytes(&crate::test::parse_test_flags()).unwrap(),
+            )),
+        };
+        let mut diagnostics = Diagnostics::default();
+        let generated_files =
+            commands::generate(input, *backend, CodegenMode::Production, &mut diagnostics).unwrap();
+        for file in generated_files.iter() {
+            write_output_file_realtive_to_dir(&dir, file, &mut diagnostics).unwrap();
+        }
+
+        // every file lands in --out, at its package relative path, and nothing next to it
+        let package_dir = out.join("com/android/aconfig/test");
+        let mut written: Vec<_> = fs::read_dir(&package_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        written.sort();
+        assert_eq!(
+            vec![
+                "FakeFeatureFlagsImpl.java",
+                "FeatureFlags.java",
+                "FeatureFlagsImpl.java",
+                "Flags.java"
+            ],
+            written
+        );
+        assert_eq!(1, fs::read_dir(temp_dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn test_generate_commands_apply_line_endings() {
+        for subcommand in ["generate", "generate-all"] {
+            let matches = cli()
+                .try_get_matches_from([
+                    "aconfig",
+                    subcommand,
+                    "--cache",
+                    "-",
+                    "--out",
+                    "-",
+                    "--backend",
+                    "java",
+                    "--line-endings",
+                    "crlf",
+                ])
+                .unwrap();
+            let sub_matches = matches.subcommand_matches(subcommand).unwrap();
+            let input = Input {
+                source: "test".to_string(),
+                reader: Box::new(std::io::Cursor::new(
+                    protobuf::Message::write_to_bytes(&crate::test::parse_test_flags()).unwrap(),
+                )),
+            };
+            let mut generated_files = commands::generate(
+                input,
+                commands::Backend::Java,
+                CodegenMode::Production,
+                &mut Diagnostics::default(),
+            )
+            .unwrap();
+            apply_line_endings(sub_matches, &mut generated_files).unwrap();
+            for file in generated_files.iter() {
+                let contents = std::str::from_utf8(&file.contents).unwrap();
+                assert_eq!(contents.matches('\n').count(), contents.matches("\r\n").count());
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_commands_apply_since_version() {
+        let temp_dir = crate::test::TempDir::new("since-version-test");
+        let cache = temp_dir.path().join("cache.pb");
+        let mut parsed_flags = crate::test::parse_test_flags();
+        for pf in parsed_flags.parsed_flag.iter_mut() {
+            if pf.name() == "enabled_ro" {
+                pf.set_since(35);
+            }
+        }
+        fs::write(&cache, protobuf::Message::write_to_bytes(&parsed_flags).unwrap()).unwrap();
+
+        for subcommand in ["generate", "generate-all", "list-generated-files"] {
+            let matches = cli()
+                .try_get_matches_from([
+                    "aconfig",
+                    subcommand,
+                    "--cache",
+                    cache.to_str().unwrap(),
+                    "--out",
+                    "-",
+                    "--backend",
+                    "java",
+                    "--since-version",
+                    "34",
+                ])
+                .unwrap();
+            let sub_matches = matches.subcommand_matches(subcommand).unwrap();
+            let mut input = open_cache_for_codegen(sub_matches).unwrap();
+            let mut bytes = Vec::new();
+            input.reader.read_to_end(&mut bytes).unwrap();
+            let filtered = protos::parsed_flags::try_from_binary_proto(&bytes).unwrap();
+            assert_eq!(7, filtered.parsed_flag.len(), "{}", subcommand);
+            assert!(!filtered.parsed_flag.iter().any(|pf| pf.name() == "enabled_ro"));
+        }
+    }
+
+    #[test]
+    fn test_create_caches_applies_parse_flags_checks() {
+        let matches = cli()
+            .try_get_matches_from([
+                "aconfig",
+                "create-caches",
+                "--declarations",
+                "-",
+                "--out-dir",
+                "-",
+                "--max-flags-per-package",
+                "1",
+                "--fail-on-empty-package",
+                "--unknown-fields",
+                "lenient",
+            ])
+            .unwrap();
+        let sub_matches = matches.subcommand_matches("create-caches").unwrap();
+        let config = get_parse_flags_config(sub_matches).unwrap();
+        assert_eq!(Some(1), config.max_flags_per_package);
+        assert!(config.fail_on_empty_package);
+        assert_eq!(commands::UnknownFieldPolicy::Lenient, config.unknown_fields);
+
+        let declarations = r#"
+        declarations {
+            package: "com.first"
+            flag {
+                name: "first"
+                namespace: "ns"
+                description: "This is the description of the first flag."
+                bug: "123"
+            }
+            flag {
+                name: "second"
+                namespace: "ns"
+                description: "This is the description of the second flag."
+                bug: "123"
+            }
+        }
+        "#;
+        let error = commands::parse_multi_package_flags(
+            Input { source: "memory".to_string(), reader: Box::new(declarations.as_bytes()) },
+            vec![],
+            commands::DEFAULT_FLAG_PERMISSION,
+            &config,
+            &mut Diagnostics::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            "package com.first declares 2 flags, more than the maximum of 1",
+            format!("{:#}", error.root_cause())
+        );
+    }
+
+    #[test]
+    fn test_create_cache_declarations_from_stdin() {
+        let matches = cli()
+            .try_get_matches_from([
+                "aconfig",
+                "create-cache",
+                "--package",
+                "com.android.aconfig.test",
+                "--declarations",
+                "-",
+                "--cache",
+                "-",
+            ])
+            .unwrap();
+        let (_, sub_matches) = matches.subcommand().unwrap();
+        let stdin = Box::new(include_bytes!("../tests/test.aconfig").as_slice());
+        let declarations =
+            open_zero_or_more_files_or_stdin(sub_matches, "declarations", stdin).unwrap();
+        assert_eq!(1, declarations.len());
+        let bytes = commands::parse_flags(
+            "com.android.aconfig.test",
+            None,
+            declarations,
+            vec![],
+            commands::DEFAULT_FLAG_PERMISSION,
+            &commands::ParseFlagsConfig::default(),
+            &mut commands::Diagnostics::default(),
+        )
+        .unwrap();
+        let parsed_flags = protos::parsed_flags::try_from_binary_proto(&bytes).unwrap();
+        let disabled_ro =
+            parsed_flags.parsed_flag.iter().find(|pf| pf.name() == "disabled_ro").unwrap();
+        assert_eq!("<stdin>", disabled_ro.trace[0].source());
+
+        let matches = cli()
+            .try_get_matches_from([
+                "aconfig",
+                "create-cache",
+                "--package",
+                "com.android.aconfig.test",
+                "--declarations",
+                "-",
+                "--declarations",
+                "-",
+                "--cache",
+                "-",
+            ])
+            .unwrap();
+        let (_, sub_matches) = matches.subcommand().unwrap();
+        let error =
+            open_zero_or_more_files_or_stdin(sub_matches, "declarations", Box::new(io::empty()))
+                .map(|_| ())
+                .unwrap_err();
+        assert_eq!(format!("{:?}", error), "stdin (-) can only be used once for --declarations");
+    }
 }
@@ -32,11 +32,14 @@ mod auto_generated {
     pub use aconfig_protos::aconfig::flag_metadata::Flag_purpose as ProtoFlagPurpose;
     pub use aconfig_protos::aconfig::Flag_declaration as ProtoFlagDeclaration;
     pub use aconfig_protos::aconfig::Flag_declarations as ProtoFlagDeclarations;
+    pub use aconfig_protos::aconfig::Flag_declarations_set as ProtoFlagDeclarationsSet;
     pub use aconfig_protos::aconfig::Flag_metadata as ProtoFlagMetadata;
     pub use aconfig_protos::aconfig::Flag_permission as ProtoFlagPermission;
     pub use aconfig_protos::aconfig::Flag_state as ProtoFlagState;
+    pub use aconfig_protos::aconfig::Flag_summary as ProtoFlagSummary;
     pub use aconfig_protos::aconfig::Flag_value as ProtoFlagValue;
     pub use aconfig_protos::aconfig::Flag_values as ProtoFlagValues;
+    pub use aconfig_protos::aconfig::Package_summary as ProtoPackageSummary;
     pub use aconfig_protos::aconfig::Parsed_flag as ProtoParsedFlag;
     pub use aconfig_protos::aconfig::Parsed_flags as ProtoParsedFlags;
     pub use aconfig_protos::aconfig::Tracepoint as ProtoTracepoint;
@@ -52,11 +55,14 @@ mod auto_generated {
     pub use aconfig::flag_metadata::Flag_purpose as ProtoFlagPurpose;
     pub use aconfig::Flag_declaration as ProtoFlagDeclaration;
     pub use aconfig::Flag_declarations as ProtoFlagDeclarations;
+    pub use aconfig::Flag_declarations_set as ProtoFlagDeclarationsSet;
     pub use aconfig::Flag_metadata as ProtoFlagMetadata;
     pub use aconfig::Flag_permission as ProtoFlagPermission;
     pub use aconfig::Flag_state as ProtoFlagState;
+    pub use aconfig::Flag_summary as ProtoFlagSummary;
     pub use aconfig::Flag_value as ProtoFlagValue;
     pub use aconfig::Flag_values as ProtoFlagValues;
+    pub use aconfig::Package_summary as ProtoPackageSummary;
     pub use aconfig::Parsed_flag as ProtoParsedFlag;
     pub use aconfig::Parsed_flags as ProtoParsedFlags;
     pub use aconfig::Tracepoint as ProtoTracepoint;
@@ -67,6 +73,7 @@ pub use auto_generated::*;
 
 use anyhow::Result;
 use paste::paste;
+use serde::Serialize;
 
 fn try_from_text_proto<T>(s: &str) -> Result<T>
 where
@@ -75,6 +82,201 @@ where
     protobuf::text_format::parse_from_str(s).map_err(|e| e.into())
 }
 
+// Like try_from_text_proto, but fields unknown to this version of aconfig (e.g. added by a newer
+// version of the proto) are skipped instead of rejected. Also returns the skipped fields.
+fn try_from_text_proto_ignoring_unknown_fields<T>(s: &str) -> Result<(T, Vec<String>)>
+where
+    T: protobuf::MessageFull,
+{
+    let mut scanner = UnknownFieldScanner { s, tokens: text_proto_tokens(s), pos: 0 };
+    let mut unknown_fields = vec![];
+    scanner.scan_message(&T::descriptor(), "", &mut unknown_fields);
+    // blank out the unknown fields rather than removing them, so that the locations in parse
+    // errors still match the original input
+    let mut bytes = s.as_bytes().to_vec();
+    for (_, start, end) in unknown_fields.iter() {
+        bytes[*start..*end].iter_mut().filter(|b| **b != b'\n').for_each(|b| *b = b' ');
+    }
+    let parsed = try_from_text_proto(&String::from_utf8(bytes)?)?;
+    Ok((parsed, unknown_fields.into_iter().map(|(path, _, _)| path).collect()))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum TextProtoToken {
+    Symbol(u8),
+    String,
+    // identifiers and numbers
+    Word,
+}
+
+// Just enough of a text proto tokenizer to find where fields start and end: returns each token
+// with its byte range
+fn text_proto_tokens(s: &str) -> Vec<(TextProtoToken, usize, usize)> {
+    let bytes = s.as_bytes();
+    let is_symbol = |b: u8| b"{}<>[]:,;".contains(&b);
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            b if b.is_ascii_whitespace() => i += 1,
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            quote @ (b'"' | b'\'') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i = bytes.len().min(i + 1);
+                tokens.push((TextProtoToken::String, start, i));
+            }
+            b if is_symbol(b) => {
+                i += 1;
+                tokens.push((TextProtoToken::Symbol(b), start, i));
+            }
+            _ => {
+                while i < bytes.len()
+                    && !bytes[i].is_ascii_whitespace()
+                    && !is_symbol(bytes[i])
+                    && !b"#\"'".contains(&bytes[i])
+                {
+                    i += 1;
+                }
+                tokens.push((TextProtoToken::Word, start, i));
+            }
+        }
+    }
+    tokens
+}
+
+// Authoring tools sometimes write `state: true` instead of `state: ENABLED`: replace such boolean
+// states with the enum values they stand for
+fn normalize_boolean_states(s: &str) -> String {
+    let mut normalized = String::with_capacity(s.len());
+    let mut copied = 0;
+    for window in text_proto_tokens(s).windows(3) {
+        let [(name, name_start, name_end), (colon, _, _), (value, start, end)] = window else {
+            continue;
+        };
+        if (*name, *colon, *value)
+            != (TextProtoToken::Word, TextProtoToken::Symbol(b':'), TextProtoToken::Word)
+            || &s[*name_start..*name_end] != "state"
+        {
+            continue;
+        }
+        let state = match &s[*start..*end] {
+            "true" => "ENABLED",
+            "false" => "DISABLED",
+            _ => continue,
+        };
+        normalized.push_str(&s[copied..*start]);
+        normalized.push_str(state);
+        copied = *end;
+    }
+    normalized.push_str(&s[copied..]);
+    normalized
+}
+
+struct UnknownFieldScanner<'a> {
+    s: &'a str,
+    tokens: Vec<(TextProtoToken, usize, usize)>,
+    pos: usize,
+}
+
+impl UnknownFieldScanner<'_> {
+    fn peek(&self) -> Option<TextProtoToken> {
+        self.tokens.get(self.pos).map(|(token, _, _)| *token)
+    }
+
+    fn skip_if(&mut self, symbols: &[u8]) {
+        if let Some(TextProtoToken::Symbol(b)) = self.peek() {
+            if symbols.contains(&b) {
+                self.pos += 1;
+            }
+        }
+    }
+
+    // Collects (path, start, end) of each unknown field, until the end of the current message.
+    // Malformed input is skipped over: the parser reports it later.
+    fn scan_message(
+        &mut self,
+        descriptor: &protobuf::reflect::MessageDescriptor,
+        prefix: &str,
+        unknown_fields: &mut Vec<(String, usize, usize)>,
+    ) {
+        use protobuf::reflect::{RuntimeFieldType, RuntimeType};
+
+        while let Some(token) = self.peek() {
+            let (_, start, end) = self.tokens[self.pos];
+            match token {
+                TextProtoToken::Symbol(b'}' | b'>') => return,
+                TextProtoToken::Word => self.pos += 1,
+                _ => {
+                    self.pos += 1;
+                    continue;
+                }
+            }
+            let name = &self.s[start..end];
+            let path = format!("{}{}", prefix, name);
+            self.skip_if(b":");
+            match descriptor.field_by_name(name) {
+                Some(field) => {
+                    let message_type = match field.runtime_field_type() {
+                        RuntimeFieldType::Singular(RuntimeType::Message(m))
+                        | RuntimeFieldType::Repeated(RuntimeType::Message(m)) => Some(m),
+                        _ => None,
+                    };
+                    match (message_type, self.peek()) {
+                        (Some(m), Some(TextProtoToken::Symbol(b'{' | b'<'))) => {
+                            self.pos += 1;
+                            self.scan_message(&m, &format!("{}.", path), unknown_fields);
+                            self.skip_if(b"}>");
+                        }
+                        _ => self.skip_value(),
+                    }
+                    self.skip_if(b",;");
+                }
+                None => {
+                    self.skip_value();
+                    self.skip_if(b",;");
+                    let end = self.tokens[self.pos - 1].2;
+                    unknown_fields.push((path, start, end));
+                }
+            }
+        }
+    }
+
+    fn skip_value(&mut self) {
+        match self.peek() {
+            Some(TextProtoToken::Symbol(b'{' | b'<' | b'[')) => {
+                let mut depth = 0;
+                while let Some(token) = self.peek() {
+                    self.pos += 1;
+                    match token {
+                        TextProtoToken::Symbol(b'{' | b'<' | b'[') => depth += 1,
+                        TextProtoToken::Symbol(b'}' | b'>' | b']') => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        return;
+                    }
+                }
+            }
+            // adjacent string literals are concatenated
+            Some(TextProtoToken::String) => {
+                while self.peek() == Some(TextProtoToken::String) {
+                    self.pos += 1;
+                }
+            }
+            Some(TextProtoToken::Word) => self.pos += 1,
+            _ => {}
+        }
+    }
+}
+
 macro_rules! ensure_required_fields {
     ($type:expr, $struct:expr, $($field:expr),+) => {
         $(
@@ -97,6 +299,27 @@ pub mod flag_declaration {
         ensure!(codegen::is_valid_name_ident(pdf.namespace()), "bad flag declaration: bad name");
         ensure!(!pdf.description().is_empty(), "bad flag declaration: empty description");
         ensure!(pdf.bug.len() == 1, "bad flag declaration: exactly one bug required");
+        for alias in pdf.alias.iter() {
+            ensure!(codegen::is_valid_name_ident(alias), "bad flag declaration: bad alias");
+            ensure!(alias != pdf.name(), "bad flag declaration: alias same as name");
+        }
+        if pdf.has_group() {
+            ensure!(codegen::is_valid_name_ident(pdf.group()), "bad flag declaration: bad group");
+        }
+        if pdf.has_owner() {
+            ensure!(!pdf.owner().trim().is_empty(), "bad flag declaration: empty owner");
+        }
+        for (i, namespace) in pdf.fallback_namespace.iter().enumerate() {
+            ensure!(
+                codegen::is_valid_name_ident(namespace),
+                "bad flag declaration: bad fallback namespace"
+            );
+            ensure!(
+                namespace != pdf.namespace() && !pdf.fallback_namespace[..i].contains(namespace),
+                "bad flag declaration: duplicate fallback namespace {}",
+                namespace
+            );
+        }
 
         Ok(())
     }
@@ -108,11 +331,34 @@ pub mod flag_declarations {
     use anyhow::ensure;
 
     pub fn try_from_text_proto(s: &str) -> Result<ProtoFlagDeclarations> {
-        let pdf: ProtoFlagDeclarations = super::try_from_text_proto(s)?;
+        let mut pdf: ProtoFlagDeclarations = super::try_from_text_proto(s)?;
+        apply_default_namespace(&mut pdf);
         verify_fields(&pdf)?;
         Ok(pdf)
     }
 
+    /// Give the flags without a namespace the namespace of the file, if it has one
+    pub fn apply_default_namespace(pdf: &mut ProtoFlagDeclarations) {
+        if !pdf.has_namespace() {
+            return;
+        }
+        let namespace = pdf.namespace().to_string();
+        for flag_declaration in pdf.flag.iter_mut().filter(|fd| !fd.has_namespace()) {
+            flag_declaration.set_namespace(namespace.clone());
+        }
+    }
+
+    /// Also returns the unknown fields which were ignored, e.g. "flag.new_field"
+    pub fn try_from_text_proto_ignoring_unknown_fields(
+        s: &str,
+    ) -> Result<(ProtoFlagDeclarations, Vec<String>)> {
+        let (mut pdf, unknown_fields): (ProtoFlagDeclarations, _) =
+            super::try_from_text_proto_ignoring_unknown_fields(s)?;
+        apply_default_namespace(&mut pdf);
+        verify_fields(&pdf)?;
+        Ok((pdf, unknown_fields))
+    }
+
     pub fn verify_fields(pdf: &ProtoFlagDeclarations) -> Result<()> {
         ensure_required_fields!("flag declarations", pdf, "package");
         // TODO(b/312769710): Make the container field required.
@@ -125,6 +371,10 @@ pub mod flag_declarations {
             !pdf.has_container() || codegen::is_valid_container_ident(pdf.container()),
             "bad flag declarations: bad container"
         );
+        ensure!(
+            !pdf.has_namespace() || codegen::is_valid_name_ident(pdf.namespace()),
+            "bad flag declarations: bad namespace"
+        );
         for flag_declaration in pdf.flag.iter() {
             super::flag_declaration::verify_fields(flag_declaration)?;
         }
@@ -133,6 +383,41 @@ pub mod flag_declarations {
     }
 }
 
+pub mod flag_declarations_set {
+    use super::*;
+    use anyhow::ensure;
+
+    pub fn try_from_text_proto(s: &str) -> Result<ProtoFlagDeclarationsSet> {
+        let mut pfds: ProtoFlagDeclarationsSet = super::try_from_text_proto(s)?;
+        pfds.declarations.iter_mut().for_each(super::flag_declarations::apply_default_namespace);
+        verify_fields(&pfds)?;
+        Ok(pfds)
+    }
+
+    /// Also returns the unknown fields which were ignored, e.g. "declarations.flag.new_field"
+    pub fn try_from_text_proto_ignoring_unknown_fields(
+        s: &str,
+    ) -> Result<(ProtoFlagDeclarationsSet, Vec<String>)> {
+        let (mut pfds, unknown_fields): (ProtoFlagDeclarationsSet, _) =
+            super::try_from_text_proto_ignoring_unknown_fields(s)?;
+        pfds.declarations.iter_mut().for_each(super::flag_declarations::apply_default_namespace);
+        verify_fields(&pfds)?;
+        Ok((pfds, unknown_fields))
+    }
+
+    pub fn verify_fields(pfds: &ProtoFlagDeclarationsSet) -> Result<()> {
+        for (i, pdf) in pfds.declarations.iter().enumerate() {
+            super::flag_declarations::verify_fields(pdf)?;
+            ensure!(
+                pfds.declarations[..i].iter().all(|other| other.package() != pdf.package()),
+                "bad flag declarations set: package {} declared more than once",
+                pdf.package()
+            );
+        }
+        Ok(())
+    }
+}
+
 pub mod flag_value {
     use super::*;
     use crate::codegen;
@@ -152,7 +437,7 @@ pub mod flag_values {
     use super::*;
 
     pub fn try_from_text_proto(s: &str) -> Result<ProtoFlagValues> {
-        let pfv: ProtoFlagValues = super::try_from_text_proto(s)?;
+        let pfv: ProtoFlagValues = super::try_from_text_proto(&normalize_boolean_states(s))?;
         verify_fields(&pfv)?;
         Ok(pfv)
     }
@@ -183,6 +468,14 @@ pub mod flag_permission {
             ProtoFlagPermission::READ_ONLY => "read_only",
         }
     }
+
+    pub fn most_restrictive(a: ProtoFlagPermission, b: ProtoFlagPermission) -> ProtoFlagPermission {
+        if a == ProtoFlagPermission::READ_ONLY || b == ProtoFlagPermission::READ_ONLY {
+            ProtoFlagPermission::READ_ONLY
+        } else {
+            ProtoFlagPermission::READ_WRITE
+        }
+    }
 }
 
 pub mod tracepoint {
@@ -228,6 +521,21 @@ pub mod parsed_flag {
             super::tracepoint::verify_fields(tp)?;
         }
         ensure!(pf.bug.len() == 1, "bad flag declaration: exactly one bug required");
+        for alias in pf.alias.iter() {
+            ensure!(codegen::is_valid_name_ident(alias), "bad parsed flag: bad alias");
+        }
+        if pf.has_group() {
+            ensure!(codegen::is_valid_name_ident(pf.group()), "bad parsed flag: bad group");
+        }
+        if pf.has_owner() {
+            ensure!(!pf.owner().trim().is_empty(), "bad parsed flag: empty owner");
+        }
+        for namespace in pf.fallback_namespace.iter() {
+            ensure!(
+                codegen::is_valid_name_ident(namespace),
+                "bad parsed flag: bad fallback namespace"
+            );
+        }
         if pf.is_fixed_read_only() {
             ensure!(
                 pf.permission() == ProtoFlagPermission::READ_ONLY,
@@ -284,15 +592,74 @@ pub mod parsed_flags {
             super::parsed_flag::verify_fields(parsed_flag)?;
             previous = Some(parsed_flag);
         }
+        verify_aliases(pf)?;
         Ok(())
     }
 
-    pub fn merge(parsed_flags: Vec<ProtoParsedFlags>, dedup: bool) -> Result<ProtoParsedFlags> {
+    pub fn iter_read_write(pfs: &ProtoParsedFlags) -> impl Iterator<Item = &ProtoParsedFlag> {
+        pfs.parsed_flag.iter().filter(|pf| !pf.is_compile_time_constant())
+    }
+
+    /// Number of read-write flags, e.g. for the flags a code generator was given
+    pub fn rw_flag_count<'a, I>(parsed_flags: I) -> usize
+    where
+        I: IntoIterator<Item = &'a ProtoParsedFlag>,
+    {
+        parsed_flags.into_iter().filter(|pf| !pf.is_compile_time_constant()).count()
+    }
+
+    pub fn iter_read_only(pfs: &ProtoParsedFlags) -> impl Iterator<Item = &ProtoParsedFlag> {
+        pfs.parsed_flag.iter().filter(|pf| pf.is_compile_time_constant())
+    }
+
+    /// Names of the flags, sorted. Flags from different packages may share a name: each occurrence
+    /// is included.
+    pub fn flag_names(pfs: &ProtoParsedFlags) -> Vec<&str> {
+        let mut names: Vec<&str> = pfs.parsed_flag.iter().map(|pf| pf.name()).collect();
+        names.sort();
+        names
+    }
+
+    // An alias must not be used by any other flag, alias or not, in the same package
+    fn verify_aliases(pf: &ProtoParsedFlags) -> Result<()> {
+        let mut names: std::collections::HashSet<(&str, &str)> =
+            pf.parsed_flag.iter().map(|pf| (pf.package(), pf.name())).collect();
+        for parsed_flag in pf.parsed_flag.iter() {
+            for alias in parsed_flag.alias.iter() {
+                if !names.insert((parsed_flag.package(), alias)) {
+                    bail!(
+                        "bad parsed flags: alias {} of flag {} is already in use",
+                        alias,
+                        parsed_flag.fully_qualified_name()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum MergePolicy {
+        /// Flags with the same name trigger an error (unless deduplicated).
+        Strict,
+        /// Flags with the same name which only differ in permission (and trace) are merged into
+        /// a single flag with the most restrictive permission.
+        MostRestrictivePermission,
+    }
+
+    pub fn merge(
+        parsed_flags: Vec<ProtoParsedFlags>,
+        dedup: bool,
+        policy: MergePolicy,
+    ) -> Result<ProtoParsedFlags> {
         let mut merged = ProtoParsedFlags::new();
         for mut pfs in parsed_flags.into_iter() {
             merged.parsed_flag.append(&mut pfs.parsed_flag);
         }
         merged.parsed_flag.sort_by_cached_key(create_sorting_key);
+        if policy == MergePolicy::MostRestrictivePermission {
+            merged.parsed_flag = merge_permissions(merged.parsed_flag);
+        }
         if dedup {
             // Deduplicate identical protobuf messages.  Messages with the same sorting key but
             // different fields (including the path to the original source file) will not be
@@ -303,6 +670,225 @@ pub mod parsed_flags {
         Ok(merged)
     }
 
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+    pub enum ConflictPolicy {
+        /// A flag present in both, but different, triggers an error.
+        Error,
+        /// Conflicting flags are taken from the left-hand side.
+        PreferLeft,
+        /// Conflicting flags are taken from the right-hand side.
+        PreferRight,
+    }
+
+    /// Combine two sets of flags of the same package. Flags present in both (with the same
+    /// name) are resolved according to `policy`; identical flags are never a conflict.
+    pub fn merge_pair(
+        left: ProtoParsedFlags,
+        right: ProtoParsedFlags,
+        policy: ConflictPolicy,
+    ) -> Result<ProtoParsedFlags> {
+        let mut packages =
+            left.parsed_flag.iter().chain(right.parsed_flag.iter()).map(|pf| pf.package());
+        if let Some(package) = packages.next() {
+            if let Some(other) = packages.find(|other| *other != package) {
+                bail!("cannot merge flags of different packages {} and {}", package, other);
+            }
+        }
+
+        let mut merged = left;
+        for pf in right.parsed_flag.into_iter() {
+            match merged.parsed_flag.iter_mut().find(|existing| existing.name() == pf.name()) {
+                None => merged.parsed_flag.push(pf),
+                Some(existing) if *existing == pf => {}
+                Some(existing) => match policy {
+                    ConflictPolicy::Error => bail!(
+                        "conflicting definitions of flag {} (defined in {} and {})",
+                        create_sorting_key(&pf),
+                        super::parsed_flag::path_to_declaration(existing),
+                        super::parsed_flag::path_to_declaration(&pf)
+                    ),
+                    ConflictPolicy::PreferLeft => {}
+                    ConflictPolicy::PreferRight => *existing = pf,
+                },
+            }
+        }
+        sort_parsed_flags(&mut merged);
+        verify_fields(&merged)?;
+        Ok(merged)
+    }
+
+    // Expects the flags to be sorted. Of two adjacent flags that only differ in permission (and
+    // trace), the one with the most restrictive permission is kept, with the trace of both.
+    // Identical flags are left to dedup.
+    fn merge_permissions(parsed_flags: Vec<ProtoParsedFlag>) -> Vec<ProtoParsedFlag> {
+        let mut merged: Vec<ProtoParsedFlag> = Vec::with_capacity(parsed_flags.len());
+        for mut pf in parsed_flags.into_iter() {
+            if let Some(prev) = merged.last_mut() {
+                if differ_only_in_permission(prev, &pf) {
+                    let permission = super::flag_permission::most_restrictive(
+                        prev.permission(),
+                        pf.permission(),
+                    );
+                    if pf.permission() == permission {
+                        std::mem::swap(prev, &mut pf);
+                    }
+                    prev.trace.append(&mut pf.trace);
+                    continue;
+                }
+            }
+            merged.push(pf);
+        }
+        merged
+    }
+
+    fn differ_only_in_permission(a: &ProtoParsedFlag, b: &ProtoParsedFlag) -> bool {
+        if a.permission() == b.permission() {
+            return false;
+        }
+        let mut a = a.clone();
+        let mut b = b.clone();
+        for pf in [&mut a, &mut b] {
+            pf.clear_permission();
+            pf.trace.clear();
+        }
+        a == b
+    }
+
+    /// Compare two sets of parsed flags, ignoring the order of the individual flags. The
+    /// derived `PartialEq` of `ProtoParsedFlags` takes the order into account.
+    pub fn eq_ignoring_order(a: &ProtoParsedFlags, b: &ProtoParsedFlags) -> bool {
+        use protobuf::Message;
+
+        if a.parsed_flag.len() != b.parsed_flag.len() {
+            return false;
+        }
+        fn sorted(pfs: &ProtoParsedFlags) -> Vec<&ProtoParsedFlag> {
+            let mut flags: Vec<&ProtoParsedFlag> = pfs.parsed_flag.iter().collect();
+            flags.sort_by_cached_key(|pf| (create_sorting_key(pf), pf.write_to_bytes().ok()));
+            flags
+        }
+        a.special_fields == b.special_fields && sorted(a) == sorted(b)
+    }
+
+    /// Move all flags to a new package. Everything derived from the package (e.g. the
+    /// device config keys and the location of the generated code) follows the new name.
+    pub fn rename_package(pfs: &ProtoParsedFlags, new_package: &str) -> Result<ProtoParsedFlags> {
+        anyhow::ensure!(
+            crate::codegen::is_valid_package_ident(new_package),
+            "bad package name {}",
+            new_package
+        );
+        let mut renamed = pfs.clone();
+        for pf in renamed.parsed_flag.iter_mut() {
+            pf.set_package(new_package.to_string());
+        }
+        sort_parsed_flags(&mut renamed);
+        verify_fields(&renamed)?;
+        Ok(renamed)
+    }
+
+    /// Rename flag `old_name`, e.g. during a refactoring. With `keep_alias`, the old name becomes
+    /// an alias of the flag, so that code using the old accessor keeps compiling.
+    pub fn rename_flag(
+        pfs: &ProtoParsedFlags,
+        old_name: &str,
+        new_name: &str,
+        keep_alias: bool,
+    ) -> Result<ProtoParsedFlags> {
+        anyhow::ensure!(
+            crate::codegen::is_valid_name_ident(new_name),
+            "bad flag name {}",
+            new_name
+        );
+        let mut renamed = pfs.clone();
+        let matches: Vec<usize> = (0..renamed.parsed_flag.len())
+            .filter(|index| renamed.parsed_flag[*index].name() == old_name)
+            .collect();
+        let [index] = matches[..] else {
+            bail!("expected exactly one flag {}, found {}", old_name, matches.len());
+        };
+        let package = renamed.parsed_flag[index].package().to_string();
+        anyhow::ensure!(
+            !renamed.parsed_flag.iter().any(|pf| pf.package() == package && pf.name() == new_name),
+            "cannot rename flag {} to {}: flag {}.{} already exists",
+            old_name,
+            new_name,
+            package,
+            new_name
+        );
+        let pf = &mut renamed.parsed_flag[index];
+        pf.set_name(new_name.to_string());
+        // renaming a flag back to one of its aliases
+        pf.alias.retain(|alias| alias != new_name);
+        if keep_alias {
+            pf.alias.push(old_name.to_string());
+        }
+        sort_parsed_flags(&mut renamed);
+        verify_fields(&renamed)?;
+        Ok(renamed)
+    }
+
+    /// Prefix the namespace of every flag, e.g. to keep the server side configuration of several
+    /// builds apart. The device config keys do not change: they only depend on package and name.
+    pub fn prefix_namespaces(pfs: &ProtoParsedFlags, prefix: &str) -> Result<ProtoParsedFlags> {
+        let mut prefixed = pfs.clone();
+        for pf in prefixed.parsed_flag.iter_mut() {
+            let namespace = format!("{}{}", prefix, pf.namespace());
+            anyhow::ensure!(
+                crate::codegen::is_valid_name_ident(&namespace),
+                "bad namespace prefix {}: {} is not a valid namespace",
+                prefix,
+                namespace
+            );
+            pf.set_namespace(namespace);
+        }
+        verify_fields(&prefixed)?;
+        Ok(prefixed)
+    }
+
+    /// Make all flags in `namespace` read-only. The change is recorded as a tracepoint.
+    pub fn with_namespace_read_only(
+        pfs: &ProtoParsedFlags,
+        namespace: &str,
+    ) -> Result<ProtoParsedFlags> {
+        let mut result = pfs.clone();
+        for pf in result.parsed_flag.iter_mut().filter(|pf| pf.namespace() == namespace) {
+            if pf.permission() == ProtoFlagPermission::READ_ONLY {
+                continue;
+            }
+            pf.set_permission(ProtoFlagPermission::READ_ONLY);
+            let mut tracepoint = ProtoTracepoint::new();
+            tracepoint.set_source(format!("read-only override of namespace {}", namespace));
+            tracepoint.set_state(pf.state());
+            tracepoint.set_permission(ProtoFlagPermission::READ_ONLY);
+            pf.trace.push(tracepoint);
+        }
+        verify_fields(&result)?;
+        Ok(result)
+    }
+
+    /// The flags in both `old` and `new` whose permission changed, as (fully qualified name, old
+    /// permission, new permission). Flags added or removed are not included.
+    pub fn diff_permissions(
+        old: &ProtoParsedFlags,
+        new: &ProtoParsedFlags,
+    ) -> Vec<(String, ProtoFlagPermission, ProtoFlagPermission)> {
+        let old_permissions: std::collections::BTreeMap<String, ProtoFlagPermission> =
+            old.parsed_flag.iter().map(|pf| (pf.fully_qualified_name(), pf.permission())).collect();
+        new.parsed_flag
+            .iter()
+            .filter_map(|pf| {
+                let name = pf.fully_qualified_name();
+                let old_permission = *old_permissions.get(&name)?;
+                (old_permission != pf.permission()).then_some((
+                    name,
+                    old_permission,
+                    pf.permission(),
+                ))
+            })
+            .collect()
+    }
+
     pub fn sort_parsed_flags(pf: &mut ProtoParsedFlags) {
         pf.parsed_flag.sort_by_key(create_sorting_key);
     }
@@ -314,17 +900,202 @@ pub mod parsed_flags {
 
 pub trait ParsedFlagExt {
     fn fully_qualified_name(&self) -> String;
+    fn effective_default_value(&self) -> bool;
+    fn canonical_string(&self) -> String;
+    fn is_compile_time_constant(&self) -> bool;
 }
 
 impl ParsedFlagExt for ProtoParsedFlag {
     fn fully_qualified_name(&self) -> String {
         format!("{}.{}", self.package(), self.name())
     }
+
+    // Read-write flags may use an explicit DeviceConfig fallback; read-only flags always use
+    // their state.
+    fn effective_default_value(&self) -> bool {
+        if self.permission() == ProtoFlagPermission::READ_WRITE && self.has_default_value() {
+            self.default_value()
+        } else {
+            self.state() == ProtoFlagState::ENABLED
+        }
+    }
+
+    // Stable, single line representation, e.g. for logs and snapshot tests
+    fn canonical_string(&self) -> String {
+        format!(
+            "package={} name={} state={} permission={} namespace={}",
+            self.package(),
+            self.name(),
+            match self.state() {
+                ProtoFlagState::ENABLED => "enabled",
+                ProtoFlagState::DISABLED => "disabled",
+            },
+            flag_permission::to_string(&self.permission()),
+            self.namespace()
+        )
+    }
+
+    // Whether generated code may fold the flag's value into a constant at build time, rather
+    // than look it up at runtime
+    fn is_compile_time_constant(&self) -> bool {
+        match self.permission() {
+            ProtoFlagPermission::READ_ONLY => true,
+            ProtoFlagPermission::READ_WRITE => false,
+        }
+    }
+}
+
+/// Owned, plain description of a parsed flag, for consumers that should not depend on the
+/// layout of the generated protobuf types
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FlagDescriptor {
+    pub package: String,
+    pub name: String,
+    pub namespace: String,
+    pub container: String,
+    pub description: String,
+    pub bugs: Vec<String>,
+    pub enabled: bool,
+    pub read_only: bool,
+    pub exported: bool,
+}
+
+impl From<&ProtoParsedFlag> for FlagDescriptor {
+    fn from(pf: &ProtoParsedFlag) -> Self {
+        FlagDescriptor {
+            package: pf.package().to_string(),
+            name: pf.name().to_string(),
+            namespace: pf.namespace().to_string(),
+            container: pf.container().to_string(),
+            description: pf.description().to_string(),
+            bugs: pf.bug.clone(),
+            enabled: pf.state() == ProtoFlagState::ENABLED,
+            read_only: pf.permission() == ProtoFlagPermission::READ_ONLY,
+            exported: pf.is_exported(),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protos::parsed_flags::MergePolicy;
+
+    #[test]
+    fn test_flag_declarations_try_from_text_proto_ignoring_unknown_fields() {
+        let declarations = r#"
+package: "com.foo.bar"
+container: "system"
+new_top_level_field: 42
+flag {
+    name: "first"
+    namespace: "first_ns"
+    # comments may contain } and other symbols
+    new_message_field {
+        nested: "with a } in a string"
+        deeper < x: 1 >
+    }
+    description: "This is the description of the first flag."
+    bug: "123"
+    new_repeated_field: [1, 2, 3];
+    new_enum_field: SOME_VALUE
+}
+flag {
+    name: "second"
+    namespace: "second_ns"
+    description: "This is the description of the second flag."
+    bug: "abc"
+    metadata { purpose: PURPOSE_FEATURE new_metadata_field: "x" "y" }
+}
+"#;
+        assert!(flag_declarations::try_from_text_proto(declarations).is_err());
+
+        let (flag_declarations, unknown_fields) =
+            flag_declarations::try_from_text_proto_ignoring_unknown_fields(declarations).unwrap();
+        assert_eq!(
+            vec![
+                "new_top_level_field",
+                "flag.new_message_field",
+                "flag.new_repeated_field",
+                "flag.new_enum_field",
+                "flag.metadata.new_metadata_field"
+            ],
+            unknown_fields
+        );
+        assert_eq!(flag_declarations.package(), "com.foo.bar");
+        assert_eq!(2, flag_declarations.flag.len());
+        let first = &flag_declarations.flag[0];
+        assert_eq!(first.name(), "first");
+        assert_eq!(first.description(), "This is the description of the first flag.");
+        assert_eq!(first.bug, vec!["123"]);
+        let second = &flag_declarations.flag[1];
+        assert_eq!(second.name(), "second");
+        assert_eq!(second.metadata.purpose(), ProtoFlagPurpose::PURPOSE_FEATURE);
+
+        // errors in the known fields are still reported, at their original location
+        let error = flag_declarations::try_from_text_proto_ignoring_unknown_fields(
+            r#"
+package: "com.foo.bar"
+new_field: "x"
+flag { name: 1 }
+"#,
+        )
+        .unwrap_err();
+        assert!(format!("{:?}", error).starts_with("4:"), "{:?}", error);
+    }
+
+    #[test]
+    fn test_flag_declarations_default_namespace() {
+        let flag_declarations = flag_declarations::try_from_text_proto(
+            r#"
+package: "com.foo.bar"
+namespace: "file_ns"
+flag {
+    name: "first"
+    description: "This is the description of the first flag."
+    bug: "123"
+}
+flag {
+    name: "second"
+    namespace: "second_ns"
+    description: "This is the description of the second flag."
+    bug: "123"
+}
+flag {
+    name: "third"
+    description: "This is the description of the third flag."
+    bug: "123"
+}
+"#,
+        )
+        .unwrap();
+        let namespaces: Vec<&str> =
+            flag_declarations.flag.iter().map(|fd| fd.namespace()).collect();
+        assert_eq!(vec!["file_ns", "second_ns", "file_ns"], namespaces);
+
+        // without a default, each flag still needs a namespace
+        let error = flag_declarations::try_from_text_proto(
+            r#"
+package: "com.foo.bar"
+flag {
+    name: "first"
+    description: "This is the description of the first flag."
+    bug: "123"
+}
+"#,
+        )
+        .unwrap_err();
+        assert_eq!(format!("{:?}", error), "bad flag declaration: missing namespace");
+
+        let error = flag_declarations::try_from_text_proto(
+            r#"
+package: "com.foo.bar"
+namespace: "Bad namespace"
+"#,
+        )
+        .unwrap_err();
+        assert_eq!(format!("{:?}", error), "bad flag declarations: bad namespace");
+    }
 
     #[test]
     fn test_flag_declarations_try_from_text_proto() {
@@ -508,9 +1279,64 @@ flag {
 "#,
         )
         .unwrap_err();
-        assert!(format!("{:?}", error).contains("bad flag declarations: bad container"));
-
-        // TODO(b/312769710): Verify error when container is missing.
+        assert!(format!("{:?}", error).contains("bad flag declarations: bad container"));
+
+        // bad input: fallback namespace same as namespace
+        let error = flag_declarations::try_from_text_proto(
+            r#"
+package: "com.foo.bar"
+container: "system"
+flag {
+    name: "first"
+    namespace: "first_ns"
+    description: "This is the description of the first flag."
+    bug: "123"
+    fallback_namespace: "old_ns"
+    fallback_namespace: "first_ns"
+}
+"#,
+        )
+        .unwrap_err();
+        assert!(format!("{:?}", error)
+            .contains("bad flag declaration: duplicate fallback namespace first_ns"));
+
+        // TODO(b/312769710): Verify error when container is missing.
+    }
+
+    #[test]
+    fn test_flag_declarations_set_try_from_text_proto() {
+        let text_proto = r#"
+declarations {
+    package: "com.first"
+    flag {
+        name: "first"
+        namespace: "first_ns"
+        description: "This is the description of the first flag."
+        bug: "123"
+    }
+}
+declarations {
+    package: "com.second"
+    flag {
+        name: "first"
+        namespace: "first_ns"
+        description: "This is the description of the first flag."
+        bug: "123"
+    }
+}
+"#;
+        let pfds = flag_declarations_set::try_from_text_proto(text_proto).unwrap();
+        assert_eq!(2, pfds.declarations.len());
+        assert_eq!("com.second", pfds.declarations[1].package());
+
+        let error = flag_declarations_set::try_from_text_proto(
+            &text_proto.replace("com.second", "com.first"),
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "bad flag declarations set: package com.first declared more than once"
+        );
     }
 
     #[test]
@@ -544,6 +1370,29 @@ flag_value {
         assert_eq!(second.state(), ProtoFlagState::ENABLED);
         assert_eq!(second.permission(), ProtoFlagPermission::READ_WRITE);
 
+        // valid input: boolean states
+        let flag_values = flag_values::try_from_text_proto(
+            r#"
+flag_value {
+    package: "com.first"
+    name: "first"
+    state: false
+    permission: READ_ONLY
+}
+flag_value {
+    package: "com.second"
+    # state: false
+    name: "second"
+    state:true
+    permission: READ_WRITE
+}
+"#,
+        )
+        .unwrap();
+        assert_eq!(flag_values.flag_value[0].state(), ProtoFlagState::DISABLED);
+        assert_eq!(flag_values.flag_value[1].state(), ProtoFlagState::ENABLED);
+        assert_eq!(flag_values.flag_value[1].name(), "second");
+
         // bad input: bad package in flag value
         let error = flag_values::try_from_text_proto(
             r#"
@@ -948,42 +1797,528 @@ parsed_flag {
         // bad cases
 
         // two of the same flag with dedup disabled
-        let error = parsed_flags::merge(vec![first.clone(), first.clone()], false).unwrap_err();
+        let error =
+            parsed_flags::merge(vec![first.clone(), first.clone()], false, MergePolicy::Strict)
+                .unwrap_err();
         assert_eq!(format!("{:?}", error), "bad parsed flags: duplicate flag com.first.first (defined in flags.declarations and flags.declarations)");
 
         // two conflicting flags with dedup disabled
-        let error =
-            parsed_flags::merge(vec![second.clone(), second_duplicate.clone()], false).unwrap_err();
+        let error = parsed_flags::merge(
+            vec![second.clone(), second_duplicate.clone()],
+            false,
+            MergePolicy::Strict,
+        )
+        .unwrap_err();
         assert_eq!(format!("{:?}", error), "bad parsed flags: duplicate flag com.second.second (defined in flags.declarations and duplicate/flags.declarations)");
 
         // two conflicting flags with dedup enabled
-        let error =
-            parsed_flags::merge(vec![second.clone(), second_duplicate.clone()], true).unwrap_err();
+        let error = parsed_flags::merge(
+            vec![second.clone(), second_duplicate.clone()],
+            true,
+            MergePolicy::Strict,
+        )
+        .unwrap_err();
         assert_eq!(format!("{:?}", error), "bad parsed flags: duplicate flag com.second.second (defined in flags.declarations and duplicate/flags.declarations)");
 
         // valid cases
-        assert!(parsed_flags::merge(vec![], false).unwrap().parsed_flag.is_empty());
-        assert!(parsed_flags::merge(vec![], true).unwrap().parsed_flag.is_empty());
-        assert_eq!(first, parsed_flags::merge(vec![first.clone()], false).unwrap());
-        assert_eq!(first, parsed_flags::merge(vec![first.clone()], true).unwrap());
+        assert!(parsed_flags::merge(vec![], false, MergePolicy::Strict)
+            .unwrap()
+            .parsed_flag
+            .is_empty());
+        assert!(parsed_flags::merge(vec![], true, MergePolicy::Strict)
+            .unwrap()
+            .parsed_flag
+            .is_empty());
+        assert_eq!(
+            first,
+            parsed_flags::merge(vec![first.clone()], false, MergePolicy::Strict).unwrap()
+        );
+        assert_eq!(
+            first,
+            parsed_flags::merge(vec![first.clone()], true, MergePolicy::Strict).unwrap()
+        );
         assert_eq!(
             expected,
-            parsed_flags::merge(vec![first.clone(), second.clone()], false).unwrap()
+            parsed_flags::merge(vec![first.clone(), second.clone()], false, MergePolicy::Strict)
+                .unwrap()
         );
         assert_eq!(
             expected,
-            parsed_flags::merge(vec![first.clone(), second.clone()], true).unwrap()
+            parsed_flags::merge(vec![first.clone(), second.clone()], true, MergePolicy::Strict)
+                .unwrap()
         );
         assert_eq!(
             expected,
-            parsed_flags::merge(vec![second.clone(), first.clone()], false).unwrap()
+            parsed_flags::merge(vec![second.clone(), first.clone()], false, MergePolicy::Strict)
+                .unwrap()
         );
         assert_eq!(
             expected,
-            parsed_flags::merge(vec![second.clone(), first.clone()], true).unwrap()
+            parsed_flags::merge(vec![second.clone(), first.clone()], true, MergePolicy::Strict)
+                .unwrap()
         );
 
         // two identical flags with dedup enabled
-        assert_eq!(first, parsed_flags::merge(vec![first.clone(), first.clone()], true).unwrap());
+        assert_eq!(
+            first,
+            parsed_flags::merge(vec![first.clone(), first.clone()], true, MergePolicy::Strict)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parsed_flags_merge_most_restrictive_permission() {
+        let text_proto = r#"
+parsed_flag {
+    package: "com.first"
+    name: "first"
+    namespace: "first_ns"
+    description: "This is the description of the first flag."
+    bug: "a"
+    state: ENABLED
+    permission: READ_WRITE
+    trace {
+        source: "read_write/flags.declarations"
+        state: ENABLED
+        permission: READ_WRITE
+    }
+    container: "system"
+}
+"#;
+        let read_write = try_from_binary_proto_from_text_proto(text_proto).unwrap();
+
+        let text_proto = r#"
+parsed_flag {
+    package: "com.first"
+    name: "first"
+    namespace: "first_ns"
+    description: "This is the description of the first flag."
+    bug: "a"
+    state: ENABLED
+    permission: READ_ONLY
+    trace {
+        source: "read_only/flags.declarations"
+        state: ENABLED
+        permission: READ_ONLY
+    }
+    container: "system"
+}
+"#;
+        let read_only = try_from_binary_proto_from_text_proto(text_proto).unwrap();
+
+        // strict policy: differing permissions are a conflict
+        let error = parsed_flags::merge(
+            vec![read_write.clone(), read_only.clone()],
+            true,
+            MergePolicy::Strict,
+        )
+        .unwrap_err();
+        assert_eq!(format!("{:?}", error), "bad parsed flags: duplicate flag com.first.first (defined in read_write/flags.declarations and read_only/flags.declarations)");
+
+        // most restrictive policy: READ_ONLY wins, regardless of order
+        for input in [
+            vec![read_write.clone(), read_only.clone()],
+            vec![read_only.clone(), read_write.clone()],
+        ] {
+            let merged =
+                parsed_flags::merge(input, false, MergePolicy::MostRestrictivePermission).unwrap();
+            assert_eq!(1, merged.parsed_flag.len());
+            assert_eq!(ProtoFlagPermission::READ_ONLY, merged.parsed_flag[0].permission());
+            // the trace of the dropped flag is kept after the one of the surviving flag
+            let sources: Vec<_> =
+                merged.parsed_flag[0].trace.iter().map(|tracepoint| tracepoint.source()).collect();
+            assert_eq!(
+                vec!["read_only/flags.declarations", "read_write/flags.declarations"],
+                sources
+            );
+        }
+
+        // most restrictive policy: identical flags are not merged, but deduplicated
+        let error = parsed_flags::merge(
+            vec![read_only.clone(), read_only.clone()],
+            false,
+            MergePolicy::MostRestrictivePermission,
+        )
+        .unwrap_err();
+        assert!(format!("{:?}", error).contains("duplicate flag com.first.first"));
+        let merged = parsed_flags::merge(
+            vec![read_only.clone(), read_only.clone()],
+            true,
+            MergePolicy::MostRestrictivePermission,
+        )
+        .unwrap();
+        assert_eq!(read_only, merged);
+
+        // most restrictive policy: flags differing in more than permission still conflict
+        let mut other_state = read_only.clone();
+        other_state.parsed_flag[0].set_state(ProtoFlagState::DISABLED);
+        let error = parsed_flags::merge(
+            vec![read_write.clone(), other_state],
+            false,
+            MergePolicy::MostRestrictivePermission,
+        )
+        .unwrap_err();
+        assert!(format!("{:?}", error).contains("duplicate flag com.first.first"));
+    }
+
+    #[test]
+    fn test_parsed_flags_aliases() {
+        let text_proto = r#"
+parsed_flag {
+    package: "com.first"
+    name: "first"
+    namespace: "first_ns"
+    description: "This is the description of the first flag."
+    bug: "a"
+    state: DISABLED
+    permission: READ_ONLY
+    trace {
+        source: "flags.declarations"
+        state: DISABLED
+        permission: READ_ONLY
+    }
+    alias: "old_first"
+}
+parsed_flag {
+    package: "com.first"
+    name: "second"
+    namespace: "first_ns"
+    description: "This is the description of the second flag."
+    bug: "b"
+    state: ENABLED
+    permission: READ_WRITE
+    trace {
+        source: "flags.declarations"
+        state: ENABLED
+        permission: READ_WRITE
+    }
+}
+"#;
+        let parsed_flags = try_from_binary_proto_from_text_proto(text_proto).unwrap();
+        assert_eq!(vec!["old_first".to_string()], parsed_flags.parsed_flag[0].alias);
+
+        // alias collides with another flag
+        let error = try_from_binary_proto_from_text_proto(
+            &text_proto.replace(r#"alias: "old_first""#, r#"alias: "second""#),
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "bad parsed flags: alias second of flag com.first.first is already in use"
+        );
+
+        // alias collides with another alias
+        let error = try_from_binary_proto_from_text_proto(&text_proto.replace(
+            r#"bug: "b""#,
+            r#"bug: "b"
+    alias: "old_first""#,
+        ))
+        .unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "bad parsed flags: alias old_first of flag com.first.second is already in use"
+        );
+
+        // bad alias
+        let error = try_from_binary_proto_from_text_proto(
+            &text_proto.replace(r#"alias: "old_first""#, r#"alias: "Old First""#),
+        )
+        .unwrap_err();
+        assert_eq!(format!("{:?}", error), "bad parsed flag: bad alias");
+    }
+
+    #[test]
+    fn test_parsed_flags_merge_pair() {
+        let first = r#"
+parsed_flag {
+    package: "com.first"
+    name: "first"
+    namespace: "first_ns"
+    description: "This is the description of the first flag."
+    bug: "a"
+    state: DISABLED
+    permission: READ_WRITE
+    trace {
+        source: "left.values"
+        state: DISABLED
+        permission: READ_WRITE
+    }
+}
+"#;
+        let second = r#"
+parsed_flag {
+    package: "com.first"
+    name: "second"
+    namespace: "first_ns"
+    description: "This is the description of the second flag."
+    bug: "b"
+    state: ENABLED
+    permission: READ_ONLY
+    trace {
+        source: "left.values"
+        state: ENABLED
+        permission: READ_ONLY
+    }
+}
+"#;
+        let left = try_from_binary_proto_from_text_proto(&format!("{}{}", first, second)).unwrap();
+        let right = try_from_binary_proto_from_text_proto(
+            &first.replace("DISABLED", "ENABLED").replace("left.values", "right.values"),
+        )
+        .unwrap();
+
+        let error = parsed_flags::merge_pair(
+            left.clone(),
+            right.clone(),
+            parsed_flags::ConflictPolicy::Error,
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "conflicting definitions of flag com.first.first (defined in left.values and right.values)"
+        );
+
+        let merged = parsed_flags::merge_pair(
+            left.clone(),
+            right.clone(),
+            parsed_flags::ConflictPolicy::PreferLeft,
+        )
+        .unwrap();
+        assert_eq!(2, merged.parsed_flag.len());
+        assert_eq!(ProtoFlagState::DISABLED, merged.parsed_flag[0].state());
+        assert_eq!("second", merged.parsed_flag[1].name());
+
+        let merged = parsed_flags::merge_pair(
+            left.clone(),
+            right.clone(),
+            parsed_flags::ConflictPolicy::PreferRight,
+        )
+        .unwrap();
+        assert_eq!(2, merged.parsed_flag.len());
+        assert_eq!(ProtoFlagState::ENABLED, merged.parsed_flag[0].state());
+        assert_eq!("right.values", merged.parsed_flag[0].trace[0].source());
+
+        // identical flags are not a conflict
+        let merged = parsed_flags::merge_pair(
+            left.clone(),
+            left.clone(),
+            parsed_flags::ConflictPolicy::Error,
+        )
+        .unwrap();
+        assert_eq!(left, merged);
+
+        // different packages
+        let other =
+            try_from_binary_proto_from_text_proto(&second.replace("com.first", "com.second"))
+                .unwrap();
+        let error = parsed_flags::merge_pair(left, other, parsed_flags::ConflictPolicy::PreferLeft)
+            .unwrap_err();
+        assert_eq!(
+            format!("{:?}", error),
+            "cannot merge flags of different packages com.first and com.second"
+        );
+    }
+
+    #[test]
+    fn test_parsed_flags_eq_ignoring_order() {
+        let text_proto = r#"
+parsed_flag {
+    package: "com.first"
+    name: "first"
+    namespace: "first_ns"
+    description: "This is the description of the first flag."
+    bug: "a"
+    state: DISABLED
+    permission: READ_ONLY
+    trace {
+        source: "flags.declarations"
+        state: DISABLED
+        permission: READ_ONLY
+    }
+}
+parsed_flag {
+    package: "com.first"
+    name: "second"
+    namespace: "first_ns"
+    description: "This is the description of the second flag."
+    bug: "b"
+    state: ENABLED
+    permission: READ_WRITE
+    trace {
+        source: "flags.declarations"
+        state: ENABLED
+        permission: READ_WRITE
+    }
+}
+"#;
+        let a = try_from_binary_proto_from_text_proto(text_proto).unwrap();
+        let mut b = a.clone();
+        b.parsed_flag.reverse();
+        assert_ne!(a, b);
+        assert!(parsed_flags::eq_ignoring_order(&a, &b));
+        assert!(parsed_flags::eq_ignoring_order(&b, &a));
+
+        let mut c = b.clone();
+        c.parsed_flag[0].set_state(ProtoFlagState::DISABLED);
+        assert!(!parsed_flags::eq_ignoring_order(&a, &c));
+
+        let mut d = a.clone();
+        d.parsed_flag.pop();
+        assert!(!parsed_flags::eq_ignoring_order(&a, &d));
+    }
+
+    #[test]
+    fn test_parsed_flags_iter_by_permission() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let names = |iter: &mut dyn Iterator<Item = &ProtoParsedFlag>| {
+            iter.map(|pf| pf.name().to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![
+                "disabled_rw",
+                "disabled_rw_exported",
+                "disabled_rw_in_other_namespace",
+                "enabled_rw"
+            ],
+            names(&mut parsed_flags::iter_read_write(&parsed_flags))
+        );
+        assert_eq!(
+            vec!["disabled_ro", "enabled_fixed_ro", "enabled_ro", "enabled_ro_exported"],
+            names(&mut parsed_flags::iter_read_only(&parsed_flags))
+        );
+        assert_eq!(4, parsed_flags::rw_flag_count(&parsed_flags.parsed_flag));
+        assert_eq!(0, parsed_flags::rw_flag_count(parsed_flags::iter_read_only(&parsed_flags)));
+    }
+
+    #[test]
+    fn test_flag_descriptor_from_parsed_flag() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let enabled_ro = parsed_flags.parsed_flag.iter().find(|pf| pf.name() == "enabled_ro");
+        let descriptor = FlagDescriptor::from(enabled_ro.unwrap());
+        assert_eq!(
+            FlagDescriptor {
+                package: "com.android.aconfig.test".to_string(),
+                name: "enabled_ro".to_string(),
+                namespace: "aconfig_test".to_string(),
+                container: "system".to_string(),
+                description: "This flag is ENABLED + READ_ONLY".to_string(),
+                bugs: vec!["abc".to_string()],
+                enabled: true,
+                read_only: true,
+                exported: false,
+            },
+            descriptor
+        );
+    }
+
+    #[test]
+    fn test_parsed_flags_diff_permissions() {
+        let old = crate::test::parse_test_flags();
+        let mut new = old.clone();
+        new.parsed_flag
+            .iter_mut()
+            .find(|pf| pf.name() == "disabled_rw")
+            .unwrap()
+            .set_permission(ProtoFlagPermission::READ_ONLY);
+        // added and removed flags are ignored
+        new.parsed_flag.retain(|pf| pf.name() != "enabled_rw");
+        assert_eq!(
+            vec![(
+                "com.android.aconfig.test.disabled_rw".to_string(),
+                ProtoFlagPermission::READ_WRITE,
+                ProtoFlagPermission::READ_ONLY
+            )],
+            parsed_flags::diff_permissions(&old, &new)
+        );
+        assert!(parsed_flags::diff_permissions(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_parsed_flags_with_namespace_read_only() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let result = parsed_flags::with_namespace_read_only(&parsed_flags, "aconfig_test").unwrap();
+        assert_eq!(parsed_flags.parsed_flag.len(), result.parsed_flag.len());
+        for (before, after) in parsed_flags.parsed_flag.iter().zip(result.parsed_flag.iter()) {
+            if before.namespace() == "aconfig_test" {
+                assert_eq!(ProtoFlagPermission::READ_ONLY, after.permission());
+            } else {
+                assert_eq!(before, after);
+            }
+            assert_eq!(before.state(), after.state());
+        }
+        let disabled_rw = result.parsed_flag.iter().find(|pf| pf.name() == "disabled_rw").unwrap();
+        assert_eq!(
+            "read-only override of namespace aconfig_test",
+            disabled_rw.trace.last().unwrap().source()
+        );
+        let other = result
+            .parsed_flag
+            .iter()
+            .find(|pf| pf.name() == "disabled_rw_in_other_namespace")
+            .unwrap();
+        assert_eq!(ProtoFlagPermission::READ_WRITE, other.permission());
+    }
+
+    #[test]
+    fn test_parsed_flag_is_compile_time_constant() {
+        let parsed_flags = crate::test::parse_test_flags();
+        for pf in parsed_flags.parsed_flag.iter() {
+            assert_eq!(
+                pf.permission() == ProtoFlagPermission::READ_ONLY,
+                pf.is_compile_time_constant(),
+                "{}",
+                pf.name()
+            );
+        }
+        let constants: Vec<&str> = parsed_flags
+            .parsed_flag
+            .iter()
+            .filter(|pf| pf.is_compile_time_constant())
+            .map(|pf| pf.name())
+            .collect();
+        assert_eq!(
+            vec!["disabled_ro", "enabled_fixed_ro", "enabled_ro", "enabled_ro_exported"],
+            constants
+        );
+    }
+
+    #[test]
+    fn test_parsed_flags_flag_names() {
+        let mut parsed_flags = crate::test::parse_test_flags();
+        parsed_flags.parsed_flag.reverse();
+        assert_eq!(
+            vec![
+                "disabled_ro",
+                "disabled_rw",
+                "disabled_rw_exported",
+                "disabled_rw_in_other_namespace",
+                "enabled_fixed_ro",
+                "enabled_ro",
+                "enabled_ro_exported",
+                "enabled_rw"
+            ],
+            parsed_flags::flag_names(&parsed_flags)
+        );
+        assert!(parsed_flags::flag_names(&ProtoParsedFlags::new()).is_empty());
+    }
+
+    #[test]
+    fn test_parsed_flag_canonical_string() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let enabled_rw =
+            parsed_flags.parsed_flag.iter().find(|pf| pf.name() == "enabled_rw").unwrap();
+        assert_eq!(
+            "package=com.android.aconfig.test name=enabled_rw state=enabled permission=read_write namespace=aconfig_test",
+            enabled_rw.canonical_string()
+        );
+    }
+
+    #[test]
+    fn test_flag_permission_most_restrictive() {
+        use ProtoFlagPermission::{READ_ONLY, READ_WRITE};
+        assert_eq!(READ_ONLY, flag_permission::most_restrictive(READ_ONLY, READ_ONLY));
+        assert_eq!(READ_ONLY, flag_permission::most_restrictive(READ_ONLY, READ_WRITE));
+        assert_eq!(READ_ONLY, flag_permission::most_restrictive(READ_WRITE, READ_ONLY));
+        assert_eq!(READ_WRITE, flag_permission::most_restrictive(READ_WRITE, READ_WRITE));
     }
 }
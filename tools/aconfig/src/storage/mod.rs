@@ -122,6 +122,8 @@ mod tests {
                     }],
                     vec![],
                     crate::commands::DEFAULT_FLAG_PERMISSION,
+                    &crate::commands::ParseFlagsConfig::default(),
+                    &mut crate::commands::Diagnostics::default(),
                 )
                 .unwrap();
                 crate::protos::parsed_flags::try_from_binary_proto(&bytes).unwrap()
@@ -19,6 +19,8 @@ pub mod test_utils {
     use crate::commands::Input;
     use crate::protos::ProtoParsedFlags;
     use itertools;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     pub const TEST_PACKAGE: &str = "com.android.aconfig.test";
 
@@ -244,11 +246,42 @@ parsed_flag {
                 },
             ],
             crate::commands::DEFAULT_FLAG_PERMISSION,
+            &crate::commands::ParseFlagsConfig::default(),
+            &mut crate::commands::Diagnostics::default(),
         )
         .unwrap();
         crate::protos::parsed_flags::try_from_binary_proto(&bytes).unwrap()
     }
 
+    /// A new directory in the system temp directory, removed with its contents when dropped, i.e.
+    /// also when the test fails
+    pub struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub fn new(name: &str) -> TempDir {
+            // tests run in parallel in one process: the pid alone is not unique
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "aconfig-{}-{}-{}",
+                name,
+                std::process::id(),
+                COUNT.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
     pub fn first_significant_code_diff(a: &str, b: &str) -> Option<String> {
         let a = a.lines().map(|line| line.trim_start()).filter(|line| !line.is_empty());
         let b = b.lines().map(|line| line.trim_start()).filter(|line| !line.is_empty());
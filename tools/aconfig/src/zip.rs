@@ -0,0 +1,216 @@
+/*
+ * Copyright (C) 2023 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use anyhow::{anyhow, ensure, Result};
+use std::path::Component;
+
+use crate::commands::OutputFile;
+
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_DIRECTORY_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+// 2.0: the minimum version a reader needs to support, and the version used to write the archive
+const VERSION: u16 = 20;
+const METHOD_STORED: u16 = 0;
+// Use a fixed timestamp (1980-01-01 00:00, the earliest DOS date) for reproducible archives
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn entry_name(file: &OutputFile) -> Result<String> {
+    let components = file
+        .path
+        .components()
+        .map(|c| match c {
+            Component::Normal(s) => s.to_str(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .filter(|names| !names.is_empty())
+        .ok_or_else(|| anyhow!("bad path {} in zip archive", file.path.display()))?;
+    Ok(components.join("/"))
+}
+
+fn checked_u32(value: usize, what: &str) -> Result<u32> {
+    u32::try_from(value).map_err(|_| anyhow!("{} too large for zip archive", what))
+}
+
+// Write the files to a zip archive. Entries are stored uncompressed: the archives are only passed
+// between build steps, and this keeps aconfig free of a compression dependency.
+pub fn create_zip(files: &[OutputFile]) -> Result<Vec<u8>> {
+    let entry_count = u16::try_from(files.len())
+        .map_err(|_| anyhow!("too many files ({}) for zip archive", files.len()))?;
+    let mut output = Vec::new();
+    let mut central_directory = Vec::new();
+    let mut names = Vec::new();
+    for file in files {
+        let name = entry_name(file)?;
+        ensure!(!names.contains(&name), "duplicate file {} in zip archive", name);
+        let name_len = u16::try_from(name.len())
+            .map_err(|_| anyhow!("file name {} too long for zip archive", name))?;
+        let size = checked_u32(file.contents.len(), &name)?;
+        let crc = crc32(&file.contents);
+        let offset = checked_u32(output.len(), "archive")?;
+
+        output.extend(LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes());
+        output.extend(VERSION.to_le_bytes());
+        output.extend(0u16.to_le_bytes()); // flags
+        output.extend(METHOD_STORED.to_le_bytes());
+        output.extend(DOS_TIME.to_le_bytes());
+        output.extend(DOS_DATE.to_le_bytes());
+        output.extend(crc.to_le_bytes());
+        output.extend(size.to_le_bytes()); // compressed size
+        output.extend(size.to_le_bytes()); // uncompressed size
+        output.extend(name_len.to_le_bytes());
+        output.extend(0u16.to_le_bytes()); // extra field length
+        output.extend(name.as_bytes());
+        output.extend(&file.contents);
+
+        central_directory.extend(CENTRAL_DIRECTORY_HEADER_SIGNATURE.to_le_bytes());
+        central_directory.extend(VERSION.to_le_bytes()); // version made by
+        central_directory.extend(VERSION.to_le_bytes()); // version needed to extract
+        central_directory.extend(0u16.to_le_bytes()); // flags
+        central_directory.extend(METHOD_STORED.to_le_bytes());
+        central_directory.extend(DOS_TIME.to_le_bytes());
+        central_directory.extend(DOS_DATE.to_le_bytes());
+        central_directory.extend(crc.to_le_bytes());
+        central_directory.extend(size.to_le_bytes()); // compressed size
+        central_directory.extend(size.to_le_bytes()); // uncompressed size
+        central_directory.extend(name_len.to_le_bytes());
+        central_directory.extend(0u16.to_le_bytes()); // extra field length
+        central_directory.extend(0u16.to_le_bytes()); // comment length
+        central_directory.extend(0u16.to_le_bytes()); // disk number
+        central_directory.extend(0u16.to_le_bytes()); // internal attributes
+        central_directory.extend(0u32.to_le_bytes()); // external attributes
+        central_directory.extend(offset.to_le_bytes());
+        central_directory.extend(name.as_bytes());
+
+        names.push(name);
+    }
+
+    let central_directory_offset = checked_u32(output.len(), "archive")?;
+    let central_directory_size = checked_u32(central_directory.len(), "archive")?;
+    output.extend(central_directory);
+    output.extend(END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+    output.extend(0u16.to_le_bytes()); // disk number
+    output.extend(0u16.to_le_bytes()); // disk with the central directory
+    output.extend(entry_count.to_le_bytes()); // entries on this disk
+    output.extend(entry_count.to_le_bytes()); // total entries
+    output.extend(central_directory_size.to_le_bytes());
+    output.extend(central_directory_offset.to_le_bytes());
+    output.extend(0u16.to_le_bytes()); // comment length
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::java::JavaCodegenConfig;
+    use crate::commands::CodegenMode;
+    use std::path::PathBuf;
+
+    fn u16_at(bytes: &[u8], offset: usize) -> usize {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap()) as usize
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> usize {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
+    // Extract the files of an archive written by create_zip, by walking its central directory
+    fn unzip(bytes: &[u8]) -> Vec<OutputFile> {
+        let eocd = bytes.len() - 22;
+        assert_eq!(END_OF_CENTRAL_DIRECTORY_SIGNATURE as usize, u32_at(bytes, eocd));
+        let entry_count = u16_at(bytes, eocd + 10);
+        let mut pos = u32_at(bytes, eocd + 16);
+        let mut files = vec![];
+        for _ in 0..entry_count {
+            assert_eq!(CENTRAL_DIRECTORY_HEADER_SIGNATURE as usize, u32_at(bytes, pos));
+            let crc = u32_at(bytes, pos + 16);
+            let size = u32_at(bytes, pos + 24);
+            let name_len = u16_at(bytes, pos + 28);
+            let offset = u32_at(bytes, pos + 42);
+            let name = std::str::from_utf8(&bytes[pos + 46..pos + 46 + name_len]).unwrap();
+            pos += 46 + name_len;
+
+            assert_eq!(LOCAL_FILE_HEADER_SIGNATURE as usize, u32_at(bytes, offset));
+            let data_start = offset + 30 + u16_at(bytes, offset + 26) + u16_at(bytes, offset + 28);
+            let contents = bytes[data_start..data_start + size].to_vec();
+            assert_eq!(crc, crc32(&contents) as usize);
+            files.push(OutputFile { path: PathBuf::from(name), contents });
+        }
+        files
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0, crc32(b""));
+        assert_eq!(0xcbf43926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn test_create_zip_empty() {
+        let bytes = create_zip(&[]).unwrap();
+        assert_eq!(22, bytes.len());
+        assert!(unzip(&bytes).is_empty());
+    }
+
+    #[test]
+    fn test_create_zip_java_lib() {
+        let parsed_flags = crate::test::parse_test_flags();
+        let generated_files = crate::codegen::java::generate_java_code(
+            crate::test::TEST_PACKAGE,
+            parsed_flags.parsed_flag.iter(),
+            JavaCodegenConfig::new(CodegenMode::Production),
+        )
+        .unwrap();
+        let bytes = create_zip(&generated_files).unwrap();
+        let unzipped = unzip(&bytes);
+        assert_eq!(generated_files.len(), unzipped.len());
+        for (expected, actual) in generated_files.iter().zip(unzipped.iter()) {
+            assert_eq!(expected.path, actual.path);
+            assert_eq!(expected.contents, actual.contents);
+        }
+        assert_eq!(
+            PathBuf::from("com/android/aconfig/test/Flags.java"),
+            unzipped.first().unwrap().path
+        );
+    }
+
+    #[test]
+    fn test_create_zip_bad_path() {
+        let files = [OutputFile { path: PathBuf::from("../Flags.java"), contents: vec![] }];
+        let error = create_zip(&files).unwrap_err();
+        assert_eq!(format!("{:?}", error), "bad path ../Flags.java in zip archive");
+
+        let files = [
+            OutputFile { path: PathBuf::from("a/Flags.java"), contents: vec![] },
+            OutputFile { path: PathBuf::from("a/Flags.java"), contents: vec![] },
+        ];
+        let error = create_zip(&files).unwrap_err();
+        assert_eq!(format!("{:?}", error), "duplicate file a/Flags.java in zip archive");
+    }
+}